@@ -0,0 +1,44 @@
+//! Benchmarks of the packet codec and the offset computation
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use sntprs::bench;
+use sntprs::vectors::VECTORS;
+use sntprs::NtpPacket;
+
+fn codec(c: &mut Criterion) {
+    let vector = &VECTORS[0];
+    let response = bench::decode(vector.response).unwrap();
+    let mut request = NtpPacket::new();
+
+    request.tx_timestamp = vector.t1;
+
+    c.bench_function("encode", |b| {
+        b.iter(|| bench::encode(black_box(&request)))
+    });
+    c.bench_function("decode", |b| {
+        b.iter(|| bench::decode(black_box(vector.response)))
+    });
+    c.bench_function("offset_and_delay", |b| {
+        b.iter(|| {
+            bench::offset_and_delay(
+                black_box(vector.t1),
+                black_box(response.recv_timestamp),
+                black_box(response.tx_timestamp),
+                black_box(vector.t4),
+            )
+        })
+    });
+    c.bench_function("process", |b| {
+        b.iter(|| {
+            bench::process(
+                black_box(&request),
+                black_box(&response),
+                black_box(vector.t4),
+            )
+        })
+    });
+}
+
+criterion_group!(benches, codec);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sntprs::{NtpPacket, RequestContext, ValidationProfile};
+
+fuzz_target!(|input: (NtpPacket, u64, u64, bool, &[u8])| {
+    let (request, origin, recv, strict, bytes) = input;
+    let profile = if strict {
+        ValidationProfile::Strict
+    } else {
+        ValidationProfile::Lenient
+    };
+    let context = RequestContext::new(request, recv)
+        .origin_timestamp(origin)
+        .profile(profile);
+
+    let _ = sntprs::parse_response_bytes(bytes, &context);
+});
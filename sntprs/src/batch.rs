@@ -0,0 +1,446 @@
+//! Bulk measurements
+//!
+//! Queries a large list of servers, as done by measurement studies,
+//! with a bounded number of requests in flight and a global rate limit.
+//! Results are handed to a callback on the calling thread as soon as
+//! they are available
+//!
+//! ```rust,no_run
+//! use sntprs::batch::Batch;
+//!
+//! let servers = ["192.0.2.1:123".parse().unwrap()];
+//!
+//! Batch::new().concurrency(64).rate(100.0).run(&servers, |server, result| {
+//!     println!("{}: {:?}", server, result.map(|r| r.result().offset()));
+//! });
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::ntppacket::MAX_MAC_SIZE;
+use crate::trace::Direction;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring::UringSocket;
+use crate::{
+    get_ntp_timestamp, DetailedResult, NtpClient, NtpPacket, RawPacket,
+    Timings, NTP_PACKET_SIZE,
+};
+
+const DEFAULT_CONCURRENCY: usize = 16;
+const DEFAULT_RATE: f64 = 50.0;
+/// How often a shared socket checks for expired requests
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Position of the origin timestamp in a response
+const ORIGIN_OFFSET: usize = 24;
+
+/// Scheduler of requests to many servers
+pub struct Batch {
+    client: NtpClient,
+    concurrency: usize,
+    rate: f64,
+    shared_socket: bool,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    io_uring: bool,
+}
+
+impl Batch {
+    /// Create new batch with 16 requests in flight and 50 requests/s
+    pub fn new() -> Self {
+        Batch {
+            client: NtpClient::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+            rate: DEFAULT_RATE,
+            shared_socket: false,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            io_uring: false,
+        }
+    }
+
+    /// Use the given client for the requests
+    pub fn client(mut self, client: NtpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Maximum number of requests in flight, at least 1
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Maximum number of requests started per second.
+    /// Non positive values disable the limit
+    pub fn rate(mut self, per_second: f64) -> Self {
+        self.rate = per_second;
+        self
+    }
+
+    /// Send the requests from a single socket per address family,
+    /// matching the responses to the pending requests, instead of
+    /// opening a socket per request. This avoids running out of file
+    /// descriptors when many requests are in flight
+    pub fn shared_socket(mut self, enabled: bool) -> Self {
+        self.shared_socket = enabled;
+        self
+    }
+
+    /// Drive the shared sockets through io_uring, queueing the requests
+    /// and sending them together while waiting for the responses.
+    /// Implies [`Batch::shared_socket`]. Send failures are logged and
+    /// reported as timeouts
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn io_uring(mut self, enabled: bool) -> Self {
+        self.io_uring = enabled;
+        self.shared_socket |= enabled;
+        self
+    }
+
+    /// Query every server, passing each result to `callback` in the
+    /// order the results are available. Returns when all the servers
+    /// have been queried
+    pub fn run<F>(&self, servers: &[SocketAddr], mut callback: F)
+    where
+        F: FnMut(SocketAddr, io::Result<DetailedResult>),
+    {
+        let next = AtomicUsize::new(0);
+        let limiter = Mutex::new(Instant::now());
+        let interval = if self.rate > 0.0 {
+            Duration::from_secs_f64(1.0 / self.rate)
+        } else {
+            Duration::ZERO
+        };
+        let (tx, rx) = mpsc::channel();
+
+        thread::scope(|scope| {
+            if self.shared_socket {
+                let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+                    servers.iter().partition(|addr| addr.is_ipv6());
+
+                for (ipv6, servers) in [(false, v4), (true, v6)] {
+                    let tx = tx.clone();
+                    let limiter = &limiter;
+
+                    if !servers.is_empty() {
+                        scope.spawn(move || {
+                            self.demux(ipv6, &servers, limiter, interval, tx)
+                        });
+                    }
+                }
+            } else {
+                for _ in 0..self.concurrency.min(servers.len()) {
+                    let tx = tx.clone();
+                    let (next, limiter) = (&next, &limiter);
+
+                    scope.spawn(move || {
+                        let pending = || next.fetch_add(1, Ordering::Relaxed);
+
+                        while let Some(&server) = servers.get(pending()) {
+                            wait_slot(limiter, interval);
+
+                            let result = self.client.request_addr(server);
+
+                            if tx.send((server, result)).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+            }
+
+            drop(tx);
+
+            for (server, result) in rx {
+                callback(server, result);
+            }
+        });
+    }
+
+    /// Query `servers` of the same address family from a single socket,
+    /// matching every response to its request by server address and
+    /// origin timestamp
+    fn demux(
+        &self,
+        ipv6: bool,
+        servers: &[SocketAddr],
+        limiter: &Mutex<Instant>,
+        interval: Duration,
+        tx: Sender<(SocketAddr, io::Result<DetailedResult>)>,
+    ) {
+        let mut socket = match self.open_shared_socket(ipv6) {
+            Ok(socket) => socket,
+            Err(err) => {
+                for &server in servers {
+                    let err = io::Error::new(err.kind(), err.to_string());
+                    let _ = tx.send((server, Err(err)));
+                }
+                return;
+            }
+        };
+        let local = socket
+            .local()
+            .local_addr()
+            .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+        let timeout = self.client.read_timeout();
+        let policy = self.client.source_policy();
+        let mut pending: HashMap<(SocketAddr, u64), Pending> = HashMap::new();
+        let mut queue = servers.iter().peekable();
+        let mut buf = [0u8; NTP_PACKET_SIZE + MAX_MAC_SIZE];
+
+        loop {
+            while pending.len() < self.concurrency {
+                let server = match queue.peek() {
+                    Some(&&server) if try_slot(limiter, interval) => server,
+                    _ => break,
+                };
+                let start = Instant::now();
+                let (req, origin_timestamp) = self.client.new_request();
+                let raw = RawPacket::from(&req);
+
+                queue.next();
+
+                match socket.send_to(&raw, server) {
+                    Ok(_) => {
+                        self.client.trace_packet(
+                            local,
+                            Direction::Sent,
+                            server,
+                            &raw,
+                        );
+                        pending.insert(
+                            (server, req.tx_timestamp),
+                            Pending {
+                                req,
+                                origin_timestamp,
+                                start,
+                                sent: Instant::now(),
+                            },
+                        );
+                    }
+                    Err(err) => {
+                        let _ = tx.send((server, Err(err)));
+                    }
+                }
+            }
+
+            if pending.is_empty() && queue.peek().is_none() {
+                break;
+            }
+
+            match socket.recv_from(&mut buf) {
+                Ok((size, src)) => {
+                    let recv_timestamp = get_ntp_timestamp();
+                    let received = Instant::now();
+                    let response = &buf[..size];
+
+                    self.client.trace_packet(
+                        local,
+                        Direction::Received,
+                        src,
+                        response,
+                    );
+
+                    if size < NTP_PACKET_SIZE {
+                        continue;
+                    }
+
+                    let origin =
+                        u64::from_be_bytes(*array_ref![buf, ORIGIN_OFFSET, 8]);
+                    let key = match self.match_pending(&pending, src, origin) {
+                        Some(key) => key,
+                        None => {
+                            debug!("Dropping unexpected response from {}", src);
+                            continue;
+                        }
+                    };
+                    let request = pending.remove(&key).unwrap();
+                    let result = self
+                        .client
+                        .process(
+                            &request.req,
+                            request.origin_timestamp,
+                            response,
+                            recv_timestamp,
+                        )
+                        .map(|(result, timestamps)| DetailedResult {
+                            result,
+                            server: key.0,
+                            timings: Timings {
+                                dns: Duration::ZERO,
+                                send: request.sent - request.start,
+                                wait: received - request.sent,
+                                processing: received.elapsed(),
+                                timeout,
+                                ..Timings::default()
+                            },
+                            timestamps,
+                            source_check: policy,
+                        });
+
+                    let _ = tx.send((key.0, result));
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => debug!("Receive failed: {}", err),
+            }
+
+            pending.retain(|&(server, _), request| {
+                if request.sent.elapsed() < timeout {
+                    return true;
+                }
+
+                let err = io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "No response from the server",
+                );
+                let _ = tx.send((server, Err(err)));
+                false
+            });
+        }
+    }
+
+    /// Returns the key of the pending request a response from `src`
+    /// echoing `origin` answers, according to the source check policy
+    fn match_pending(
+        &self,
+        pending: &HashMap<(SocketAddr, u64), Pending>,
+        src: SocketAddr,
+        origin: u64,
+    ) -> Option<(SocketAddr, u64)> {
+        if pending.contains_key(&(src, origin)) {
+            return Some((src, origin));
+        }
+
+        let policy = self.client.source_policy();
+
+        pending
+            .keys()
+            .find(|&&(server, tx)| tx == origin && policy.accepts(server, src))
+            .copied()
+    }
+
+    fn open_shared_socket(
+        &self,
+        ipv6: bool,
+    ) -> io::Result<Box<dyn SharedSocket>> {
+        let socket = self.client.open_socket(ipv6)?;
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if self.io_uring {
+            return Ok(Box::new(UringSocket::new(socket, POLL_INTERVAL)?));
+        }
+
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Box::new(socket))
+    }
+}
+
+/// Socket shared by the pending requests of a batch
+trait SharedSocket {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Returns the underlying socket
+    fn local(&self) -> &UdpSocket;
+}
+
+impl SharedSocket for UdpSocket {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn local(&self) -> &UdpSocket {
+        self
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl SharedSocket for UringSocket {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UringSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UringSocket::recv_from(self, buf)
+    }
+
+    fn local(&self) -> &UdpSocket {
+        self.socket()
+    }
+}
+
+/// Request waiting for its response on a shared socket
+struct Pending {
+    req: NtpPacket,
+    origin_timestamp: u64,
+    start: Instant,
+    sent: Instant,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Batch::new()
+    }
+}
+
+/// Take the next slot of the rate limiter if it is already free
+fn try_slot(limiter: &Mutex<Instant>, interval: Duration) -> bool {
+    let mut next = limiter.lock().unwrap();
+    let now = Instant::now();
+
+    if *next > now {
+        return false;
+    }
+
+    *next = now + interval;
+    true
+}
+
+/// Wait for the next free slot of the rate limiter
+fn wait_slot(limiter: &Mutex<Instant>, interval: Duration) {
+    let slot = {
+        let mut next = limiter.lock().unwrap();
+        let slot = (*next).max(Instant::now());
+
+        *next = slot + interval;
+        slot
+    };
+
+    thread::sleep(slot.saturating_duration_since(Instant::now()));
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use crate::batch::{try_slot, wait_slot};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rate_limiter_spacing() {
+        let start = Instant::now();
+        let limiter = Mutex::new(start);
+        let interval = Duration::from_millis(20);
+
+        for _ in 0..3 {
+            wait_slot(&limiter, interval);
+        }
+
+        assert!(start.elapsed() >= 2 * interval);
+        assert!(*limiter.lock().unwrap() >= start + 3 * interval);
+        assert!(!try_slot(&limiter, interval));
+    }
+}
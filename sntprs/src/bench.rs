@@ -0,0 +1,44 @@
+//! Benchmark hooks
+//!
+//! Entry points into the packet codec and the offset computation for the
+//! criterion benchmarks under `benches`, which cannot reach the private
+//! functions. Enabled by the `bench` feature and not part of the public
+//! API
+//!
+//! ```sh
+//! cargo bench --features bench
+//! ```
+
+use crate::{Error, NtpPacket, NtpResult, RawPacket, ValidationReport};
+
+/// Returns the wire format of `packet`
+pub fn encode(packet: &NtpPacket) -> RawPacket {
+    RawPacket::from(packet)
+}
+
+/// Returns the packet of a response datagram, possibly followed by a MAC
+pub fn decode(bytes: &[u8]) -> Result<NtpPacket, Error> {
+    crate::parse_datagram(bytes)
+}
+
+/// Returns the offset and the roundtrip delay in microseconds of an
+/// exchange from its four timestamps
+pub fn offset_and_delay(t1: u64, t2: u64, t3: u64, t4: u64) -> (i64, i64) {
+    crate::offset_and_delay(t1, t2, t3, t4)
+}
+
+/// Returns the result of validating and processing the response to
+/// `request` received at `recv_timestamp`
+pub fn process(
+    request: &NtpPacket,
+    response: &NtpPacket,
+    recv_timestamp: u64,
+) -> Result<NtpResult, ValidationReport> {
+    crate::process_response(
+        request,
+        response,
+        request.tx_timestamp,
+        recv_timestamp,
+        Default::default(),
+    )
+}
@@ -1,7 +1,13 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io;
+use std::net::ToSocketAddrs;
 use std::str::FromStr;
+#[cfg(not(feature = "measure-only"))]
+use std::time::{Instant, UNIX_EPOCH};
 
-use clap::{crate_version, App, Arg};
-use simple_logger;
+use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
+use sntprs::config::Config;
 
 const GOOGLE_NTP_ADDR: &str = "time.google.com";
 
@@ -24,6 +30,63 @@ fn main() {
                 .default_value("123")
                 .help("NTP server port"),
         )
+        .arg(
+            Arg::with_name("rtc")
+                .long("rtc")
+                .takes_value(true)
+                .value_name("DEVICE")
+                .help("Write the new time to the hardware clock, e.g. /dev/rtc0"),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Print the state of the running service")
+                .arg(
+                    Arg::with_name("session")
+                        .long("session")
+                        .help("Query the session bus"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ctl")
+                .about("Send a command to the running service")
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .help("Control socket path"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .required(true)
+                        .multiple(true)
+                        .help("status, sync-now, add-server, remove-server or interval"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-from-best")
+                .about("Set the clock only if enough servers agree")
+                .arg(
+                    Arg::with_name("quorum")
+                        .short("n")
+                        .long("quorum")
+                        .takes_value(true)
+                        .help("Number of servers that must agree, a majority by default"),
+                )
+                .arg(
+                    Arg::with_name("tolerance")
+                        .long("tolerance")
+                        .takes_value(true)
+                        .value_name("MS")
+                        .default_value("100")
+                        .help("Maximum disagreement between the agreeing servers in milliseconds"),
+                )
+                .arg(
+                    Arg::with_name("servers")
+                        .required(true)
+                        .multiple(true)
+                        .help("Servers as host or host:port"),
+                ),
+        )
         .get_matches();
 
     if cfg!(debug_assertions) {
@@ -32,22 +95,243 @@ fn main() {
         simple_logger::init_with_level(log::Level::Info).unwrap();
     }
 
+    if let Some(status) = app.subcommand_matches("status") {
+        if let Err(err) = print_status(status.is_present("session")) {
+            eprintln!("Unable to query the service status: {}", err);
+        }
+        return;
+    }
+
+    if let Some(ctl) = app.subcommand_matches("ctl") {
+        let command: Vec<&str> = ctl.values_of("command").unwrap().collect();
+
+        match send_command(ctl.value_of("socket"), &command.join(" ")) {
+            Ok(answer) => print!("{}", answer),
+            Err(err) => eprintln!("Unable to control the service: {}", err),
+        }
+        return;
+    }
+
+    if let Some(best) = app.subcommand_matches("set-from-best") {
+        if let Err(err) = set_from_best(best, app.value_of("rtc")) {
+            eprintln!("Clock not set: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let ntp_server = app.value_of("server").unwrap();
     let ntp_port = u32::from_str(app.value_of("port").unwrap());
 
     let ntp_port = match ntp_port {
         Ok(ntp_port) => ntp_port,
         Err(err) => {
-            eprintln!(
-                "Unable to convert NTP server port value: {}",
-                err.to_string()
-            );
+            eprintln!("Unable to convert NTP server port value: {}", err);
             return;
         }
     };
 
-    let time = sntprs::request(ntp_server, ntp_port)
-        .expect(format!("Unable to receive time from: {}", GOOGLE_NTP_ADDR).as_str());
+    let time = sntprs::request(ntp_server, ntp_port).unwrap_or_else(|_| {
+        panic!("Unable to receive time from: {}", ntp_server)
+    });
+
+    println!("{}", sntprs::utils::display_result(&time));
+
+    #[cfg(not(feature = "measure-only"))]
+    set_clock(&time, app.value_of("rtc"));
+}
+
+/// Set the clock, and the hardware clock if given, to the time of
+/// `time`
+#[cfg(not(feature = "measure-only"))]
+fn set_clock(time: &sntprs::NtpResult, rtc: Option<&str>) {
+    match sntprs::utils::update_system_time(time.sec(), time.nsec()) {
+        Ok(adjustment) => println!("{}", adjustment),
+        Err(err) => {
+            eprintln!("Clock not set: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(device) = rtc {
+        if let Err(err) =
+            sntprs::utils::write_rtc(device, time.sec(), time.nsec())
+        {
+            eprintln!("Unable to write the hardware clock: {}", err);
+        }
+    }
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid {}: {}", name, value),
+        )
+    })
+}
+
+/// Sample every server, print the offsets marking the agreeing ones, and
+/// set the clock to their median offset if they reach the quorum. The
+/// servers resolving to the same address as a previous one are dropped,
+/// so that they are not counted twice
+fn set_from_best(args: &ArgMatches, rtc: Option<&str>) -> io::Result<()> {
+    let servers = distinct_servers(Config {
+        servers: args
+            .values_of("servers")
+            .unwrap()
+            .map(String::from)
+            .collect(),
+        ..Config::default()
+    });
+    let quorum = match args.value_of("quorum") {
+        Some(quorum) => parse_arg("quorum", quorum)?,
+        None => servers.len() / 2 + 1,
+    };
+    let tolerance: u64 =
+        parse_arg("tolerance", args.value_of("tolerance").unwrap())?;
+    let config = Config {
+        servers,
+        ..Config::default()
+    };
+    let samples = config.coordinator()?.sample_all();
+    let agreeing = sntprs::source::quorum(&samples, quorum, tolerance * 1_000)
+        .unwrap_or_default();
+
+    for sample in &samples {
+        let agrees = agreeing.iter().any(|s| s.source == sample.source);
+
+        println!(
+            "{} {:<40} offset {:>12} us  error {:>10} us",
+            if agrees { '*' } else { ' ' },
+            sample.source,
+            sample.offset,
+            sample.max_error
+        );
+    }
+
+    println!(
+        "{} of {} servers answered, {} agree within {} ms, {} required",
+        samples.len(),
+        config.servers.len(),
+        agreeing.len(),
+        tolerance,
+        quorum
+    );
+
+    if agreeing.is_empty() {
+        return Err(io::Error::other("no quorum"));
+    }
+
+    apply_offset(agreeing[agreeing.len() / 2].offset, rtc)
+}
+
+/// Returns the servers of `config` whose first address differs from the
+/// ones of the servers before them. Servers failing to resolve are kept
+fn distinct_servers(config: Config) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let distinct: Vec<bool> = config
+        .servers()
+        .into_iter()
+        .map(|(server, port)| {
+            let addr = u16::try_from(port)
+                .ok()
+                .and_then(|port| (server, port).to_socket_addrs().ok())
+                .and_then(|mut addrs| addrs.next());
+
+            addr.is_none_or(|addr| seen.insert(addr))
+        })
+        .collect();
+
+    config
+        .servers
+        .into_iter()
+        .zip(distinct)
+        .filter(|(_, distinct)| *distinct)
+        .map(|(server, _)| server)
+        .collect()
+}
+
+/// Apply `offset` to the clock, then write the hardware clock if given
+/// with the time aimed at, as a slewed clock only reaches it gradually
+#[cfg(not(feature = "measure-only"))]
+fn apply_offset(offset: i64, rtc: Option<&str>) -> io::Result<()> {
+    println!("Applying median offset {} us", offset);
+
+    let start = Instant::now();
+    let adjustment = sntprs::utils::adjust_system_time(offset)?;
+
+    println!("{}", adjustment);
+
+    let time = (adjustment.new_time + start.elapsed())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    if let Some(device) = rtc {
+        sntprs::utils::write_rtc(
+            device,
+            time.as_secs() as u32,
+            time.subsec_nanos(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Report `offset` only, as setting the clock is compiled out
+#[cfg(feature = "measure-only")]
+fn apply_offset(offset: i64, _rtc: Option<&str>) -> io::Result<()> {
+    println!(
+        "Median offset {} us, not applied in a measure-only build",
+        offset
+    );
+
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+fn print_status(session: bool) -> std::io::Result<()> {
+    let connection = if session {
+        zbus::blocking::Connection::session()
+    } else {
+        zbus::blocking::Connection::system()
+    };
+    let connection = connection.map_err(std::io::Error::other)?;
+
+    for (name, value) in sntprs::dbus::query_status(&connection)? {
+        println!("{}={}", name, value);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "dbus")))]
+fn print_status(_session: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "built without the dbus feature",
+    ))
+}
+
+#[cfg(unix)]
+fn send_command(
+    socket: Option<&str>,
+    command: &str,
+) -> std::io::Result<String> {
+    use sntprs::control;
+
+    let socket = socket.unwrap_or(control::DEFAULT_SOCKET);
+
+    control::send(socket, &command.parse()?)
+}
 
-    sntprs::utils::update_system_time(time.sec(), time.nsec());
+#[cfg(not(unix))]
+fn send_command(
+    _socket: Option<&str>,
+    _command: &str,
+) -> std::io::Result<String> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "control sockets are only available on Unix",
+    ))
 }
@@ -0,0 +1,179 @@
+//! Listener for unsolicited NTP broadcasts
+//!
+//! Broadcast (mode 5) packets are only accepted here: the unicast
+//! client rejects them as replies to its requests.
+//! As broadcasts carry no origin timestamp to match, the listener
+//! remembers the latest transmit timestamp of every server and drops
+//! replayed or reordered packets.
+//!
+//! ```rust,no_run
+//! let listener = sntprs::broadcast::BroadcastListener::bind(123).unwrap();
+//! let (result, server) = listener.recv().unwrap();
+//!
+//! println!("{} offset: {} us", server, result.offset());
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::ntptimestamp::fixed_to_micros;
+use crate::validation::validate_broadcast;
+use crate::{
+    get_ntp_timestamp, NtpPacket, NtpResult, NtpTimestamp, ValidationProfile,
+    NTP_PACKET_SIZE,
+};
+
+/// One-way delay assumed for broadcasts, as the reference implementation
+const DEFAULT_DELAY: Duration = Duration::from_millis(4);
+/// Servers whose latest transmit timestamp is remembered
+const MAX_SERVERS: usize = 256;
+
+/// Receives and validates NTP broadcasts on a local UDP port
+pub struct BroadcastListener {
+    socket: UdpSocket,
+    delay: Duration,
+    profile: ValidationProfile,
+    last_seen: Mutex<HashMap<IpAddr, (NtpTimestamp, Instant)>>,
+}
+
+impl BroadcastListener {
+    /// Listen for broadcasts on the given port of every IPv4 interface
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+
+        Ok(BroadcastListener {
+            socket,
+            delay: DEFAULT_DELAY,
+            profile: ValidationProfile::Strict,
+            last_seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// One-way network delay from the broadcast server, added to the
+    /// received server time. Defaults to 4 ms
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Select the sanity checks run on the broadcasts
+    pub fn validation(mut self, profile: ValidationProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Give up waiting for a broadcast after the given time
+    pub fn timeout(self, timeout: Option<Duration>) -> io::Result<Self> {
+        self.socket.set_read_timeout(timeout)?;
+        Ok(self)
+    }
+
+    /// Returns the local address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Wait for the next valid broadcast and return the measured offset
+    /// along with the sending server. Invalid, replayed and reordered
+    /// datagrams are skipped
+    pub fn recv(&self) -> io::Result<(NtpResult, SocketAddr)> {
+        let mut buf = [0u8; NTP_PACKET_SIZE];
+
+        loop {
+            let (size, src) = self.socket.recv_from(&mut buf)?;
+            let recv_timestamp = get_ntp_timestamp();
+
+            if size < NTP_PACKET_SIZE {
+                debug!("Dropping {} bytes datagram from {}", size, src);
+                continue;
+            }
+
+            let packet = NtpPacket::from(buf);
+            let report = validate_broadcast(&packet, self.profile);
+
+            if !report.is_valid() {
+                debug!("Dropping broadcast from {}: {}", src, report);
+                continue;
+            }
+
+            if !self.is_fresh(src.ip(), packet.tx_timestamp.into()) {
+                debug!("Dropping replayed broadcast from {}", src);
+                continue;
+            }
+
+            return Ok((self.process(&packet, recv_timestamp), src));
+        }
+    }
+
+    /// Returns whether `tx` is later than the latest transmit timestamp
+    /// received from `server`, remembering it if so
+    fn is_fresh(&self, server: IpAddr, tx: NtpTimestamp) -> bool {
+        let mut last_seen = self.last_seen.lock().unwrap();
+
+        if let Some((last, _)) = last_seen.get(&server) {
+            if tx.diff(*last) <= 0 {
+                return false;
+            }
+        } else if last_seen.len() >= MAX_SERVERS {
+            let oldest = last_seen
+                .iter()
+                .min_by_key(|(_, (_, seen))| *seen)
+                .map(|(server, _)| *server);
+
+            if let Some(oldest) = oldest {
+                last_seen.remove(&oldest);
+            }
+        }
+
+        last_seen.insert(server, (tx, Instant::now()));
+        true
+    }
+
+    fn process(&self, packet: &NtpPacket, recv_timestamp: u64) -> NtpResult {
+        let delay = self.delay.as_micros() as i64;
+        let t3 = NtpTimestamp::from(packet.tx_timestamp);
+        let t4 = NtpTimestamp::from(recv_timestamp);
+        let theta = fixed_to_micros(t3.diff(t4)).saturating_add(delay);
+
+        debug!("Broadcast offset: {} us", theta);
+
+        NtpResult {
+            stratum: packet.stratum,
+            root_delay: packet.root_delay,
+            root_dispersion: packet.root_dispersion,
+            precision: packet.precision,
+            ..NtpResult::new(
+                t3.seconds().wrapping_sub(NtpPacket::NTP_TIMESTAMP_DELTA),
+                t3.subsec_nanos(),
+                2 * delay.unsigned_abs(),
+                theta,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod broadcast_tests {
+    use crate::broadcast::BroadcastListener;
+    use crate::NtpTimestamp;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_replayed_broadcasts() {
+        let listener = BroadcastListener::bind(0).unwrap();
+        let (a, b): (IpAddr, IpAddr) =
+            ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let tx = |secs: u64| NtpTimestamp::from_bits(secs << 32);
+
+        assert!(listener.is_fresh(a, tx(100)));
+        assert!(!listener.is_fresh(a, tx(100)));
+        assert!(!listener.is_fresh(a, tx(90)));
+        assert!(listener.is_fresh(b, tx(90)));
+        assert!(listener.is_fresh(a, tx(164)));
+    }
+}
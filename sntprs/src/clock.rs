@@ -0,0 +1,639 @@
+//! Corrected clock
+//!
+//! Applications that cannot or must not adjust the system clock can read
+//! the time from a [`NtpClock`] instead, which adds the measured offset
+//! to the system time. New offsets are slewed in at a bounded rate, so
+//! the corrected time keeps moving forward even when the correction is
+//! negative
+//!
+//! The local timestamps of the exchanges come from a [`ClockSource`],
+//! the [`SystemClock`] unless the client is given another one. Steps of
+//! the system clock are caught by a [`StepDetector`], suspensions of the
+//! system by a [`SuspendDetector`]
+//!
+//! Between two updates, a [`NtpClock`] advances from the time of the last
+//! update with a monotonic clock, `CLOCK_BOOTTIME` on Linux and
+//! `QueryPerformanceCounter` on Windows, so the corrected time stays
+//! smooth and high-resolution whatever steps the system clock goes
+//! through. `CLOCK_BOOTTIME` runs at the frequency corrected by the
+//! kernel, keeping the drift within the tolerance of the clock
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::holdover::DEFAULT_TOLERANCE_PPM;
+use crate::{NtpResult, NtpTimestamp};
+
+/// Source of the local timestamps of the exchanges
+pub trait ClockSource {
+    /// Returns the current local time
+    fn now(&self) -> NtpTimestamp;
+}
+
+/// Clock source reading the system time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> NtpTimestamp {
+        NtpTimestamp::from_system_time(precise_now())
+    }
+}
+
+/// Returns the system time at the best resolution of the platform
+#[cfg(not(windows))]
+pub(crate) fn precise_now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Returns the system time read with `GetSystemTimePreciseAsFileTime`,
+/// whose resolution is below the microsecond instead of the 15.6 ms
+/// timer tick
+#[cfg(windows)]
+pub(crate) fn precise_now() -> SystemTime {
+    /// Seconds from 1601-01-01, the FILETIME epoch, to the UNIX epoch
+    const FILETIME_UNIX_DELTA: u64 = 11_644_473_600;
+
+    #[repr(C)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetSystemTimePreciseAsFileTime(time: *mut FileTime);
+    }
+
+    let mut time = FileTime { low: 0, high: 0 };
+
+    // SAFETY: the function only writes the given FILETIME
+    unsafe { GetSystemTimePreciseAsFileTime(&mut time) };
+
+    // 100 ns intervals since the FILETIME epoch
+    let ticks = (u64::from(time.high) << 32) | u64::from(time.low);
+    let since_epoch =
+        Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+
+    SystemTime::UNIX_EPOCH
+        + since_epoch.saturating_sub(Duration::from_secs(FILETIME_UNIX_DELTA))
+}
+
+/// Returns the reading of the monotonic clock, counted from the boot,
+/// with `CLOCK_BOOTTIME`: never stepped, running at the frequency
+/// corrected by the kernel and counting the time spent suspended
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn monotonic() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    // SAFETY: clock_gettime only writes the given timespec
+    unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut time) };
+
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Returns the reading of the monotonic clock, counted from an
+/// arbitrary origin, with `QueryPerformanceCounter`
+#[cfg(windows)]
+pub(crate) fn monotonic() -> Duration {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn QueryPerformanceCounter(count: *mut i64) -> i32;
+        fn QueryPerformanceFrequency(frequency: *mut i64) -> i32;
+    }
+
+    let mut count = 0;
+    let mut frequency = 0;
+
+    // SAFETY: the functions only write the given integers, and cannot
+    // fail from Windows XP on
+    unsafe {
+        QueryPerformanceCounter(&mut count);
+        QueryPerformanceFrequency(&mut frequency);
+    }
+
+    let (count, frequency) = (count as u64, frequency.max(1) as u64);
+
+    Duration::from_secs(count / frequency)
+        + Duration::from_nanos((count % frequency) * 1_000_000_000 / frequency)
+}
+
+/// Returns the reading of the monotonic clock, counted from its first
+/// reading
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+pub(crate) fn monotonic() -> Duration {
+    static ORIGIN: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+
+    ORIGIN.get_or_init(Instant::now).elapsed()
+}
+
+/// Default maximum slew rate, in PPM
+pub const DEFAULT_MAX_SLEW_PPM: f64 = 500.0;
+
+/// System clock corrected by the offset measured against NTP servers
+pub struct NtpClock {
+    anchor: Instant,
+    applied: i64,
+    target: i64,
+    max_slew: f64,
+    synced: Option<(Instant, u64)>,
+    drift: f64,
+    tolerance: f64,
+    /// Monotonic reading and system time at the last update
+    base: Option<(Duration, SystemTime)>,
+}
+
+impl NtpClock {
+    /// Create new clock without correction, slewing at the default rate
+    pub fn new() -> Self {
+        NtpClock {
+            anchor: Instant::now(),
+            applied: 0,
+            target: 0,
+            max_slew: DEFAULT_MAX_SLEW_PPM,
+            synced: None,
+            drift: 0.0,
+            tolerance: DEFAULT_TOLERANCE_PPM,
+            base: None,
+        }
+    }
+
+    /// Limit the rate at which new offsets are applied.
+    /// Rates below 1,000,000 PPM keep the corrected time monotonic
+    /// Args:
+    /// * `ppm` - maximum slew rate in PPM
+    pub fn max_slew(mut self, ppm: f64) -> Self {
+        self.max_slew = ppm.abs();
+        self
+    }
+
+    /// Set the frequency tolerance of the local clock in PPM, bounding
+    /// the drift unaccounted for by the uncertainty. Defaults to 15 PPM
+    pub fn tolerance(mut self, ppm: f64) -> Self {
+        self.tolerance = ppm.abs();
+        self
+    }
+
+    /// Apply the offset measured by `result`, whose maximum error bounds
+    /// the uncertainty right after the synchronization. The system time
+    /// is read once here, the corrected time then advances with the
+    /// monotonic clock
+    pub fn update(&mut self, result: &NtpResult) {
+        let now = Instant::now();
+
+        self.set_offset_at(now, result.offset());
+        self.synced = Some((now, result.max_error()));
+        self.base = Some((monotonic(), precise_now()));
+    }
+
+    /// Set the drift of the local clock in PPM, as estimated by
+    /// [`Discipline::drift`](crate::discipline::Discipline::drift), making
+    /// the uncertainty grow faster
+    pub fn set_drift(&mut self, ppm: f64) {
+        self.drift = ppm;
+    }
+
+    /// Slew the correction towards `offset` microseconds from now on
+    pub fn set_offset(&mut self, offset: i64) {
+        self.set_offset_at(Instant::now(), offset);
+    }
+
+    /// Slew the correction towards `offset` microseconds from the given
+    /// instant on
+    pub fn set_offset_at(&mut self, at: Instant, offset: i64) {
+        self.applied = self.offset_at(at);
+        self.anchor = at;
+        self.target = offset;
+    }
+
+    /// Apply `offset` microseconds at once, the corrected time can jump
+    pub fn step(&mut self, offset: i64) {
+        self.anchor = Instant::now();
+        self.applied = offset;
+        self.target = offset;
+    }
+
+    /// Returns the offset the clock is slewing towards in microseconds
+    pub fn target_offset(&self) -> i64 {
+        self.target
+    }
+
+    /// Returns the correction currently applied in microseconds
+    pub fn offset(&self) -> i64 {
+        self.offset_at(Instant::now())
+    }
+
+    /// Returns the correction applied at the given instant in microseconds
+    pub fn offset_at(&self, at: Instant) -> i64 {
+        let elapsed = at.saturating_duration_since(self.anchor).as_secs_f64();
+        let max_step = (elapsed * self.max_slew).min(i64::MAX as f64) as i64;
+        let remaining = self.target.saturating_sub(self.applied);
+
+        self.applied + remaining.clamp(-max_step, max_step)
+    }
+
+    /// Returns the maximum error of the corrected time at the given
+    /// instant, or `None` before the first update. It adds to the error of
+    /// the last synchronization the correction not slewed in yet and the
+    /// drift since, at the estimated rate plus the tolerance
+    pub fn uncertainty_at(&self, at: Instant) -> Option<Duration> {
+        let (synced_at, error) = self.synced?;
+        let elapsed = at.saturating_duration_since(synced_at).as_secs_f64();
+        let drift = (self.drift.abs() + self.tolerance) * elapsed;
+        let pending = self.target.abs_diff(self.offset_at(at));
+
+        Some(Duration::from_micros(
+            error
+                .saturating_add(pending)
+                .saturating_add(drift.ceil() as u64),
+        ))
+    }
+
+    /// Returns the corrected current time and its maximum error, the true
+    /// time lying within this interval around it, or `None` before the
+    /// first update
+    pub fn now_bounded(&self) -> Option<(SystemTime, Duration)> {
+        let uncertainty = self.uncertainty_at(Instant::now())?;
+
+        Some((self.now(), uncertainty))
+    }
+
+    /// Returns the corrected current time, advanced with the monotonic
+    /// clock since the last update, or the corrected system
+    /// time before it
+    pub fn now(&self) -> SystemTime {
+        let offset = self.offset();
+        let magnitude = Duration::from_micros(offset.unsigned_abs());
+        let time = match self.base {
+            Some((mono, time)) => time + monotonic().saturating_sub(mono),
+            None => precise_now(),
+        };
+
+        if offset < 0 {
+            time - magnitude
+        } else {
+            time + magnitude
+        }
+    }
+}
+
+impl Default for NtpClock {
+    fn default() -> Self {
+        NtpClock::new()
+    }
+}
+
+/// Corrected clock whose time never decreases, even when the correction
+/// is stepped back or the system clock jumps backwards. Instead, the
+/// returned time keeps advancing at the maximum slew rate below real
+/// time until the corrected time catches up
+pub struct MonotonicCorrectedClock {
+    clock: NtpClock,
+    last: Mutex<Option<(Instant, SystemTime)>>,
+}
+
+impl MonotonicCorrectedClock {
+    /// Wrap the given clock
+    pub fn new(clock: NtpClock) -> Self {
+        MonotonicCorrectedClock {
+            clock,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Returns the wrapped clock
+    pub fn clock(&self) -> &NtpClock {
+        &self.clock
+    }
+
+    /// Returns the wrapped clock, to update its correction
+    pub fn clock_mut(&mut self) -> &mut NtpClock {
+        &mut self.clock
+    }
+
+    /// Returns the corrected current time, never earlier than the
+    /// previously returned one
+    pub fn now(&self) -> SystemTime {
+        self.advance(Instant::now(), self.clock.now())
+    }
+
+    fn advance(&self, at: Instant, corrected: SystemTime) -> SystemTime {
+        let mut last = self.last.lock().unwrap();
+        let time = match *last {
+            Some((last_at, last_time)) => {
+                let elapsed = at.saturating_duration_since(last_at);
+                let rate = (1.0 - self.clock.max_slew / 1e6).max(0.0);
+                let floor = last_time + elapsed.mul_f64(rate);
+
+                corrected.max(floor)
+            }
+            None => corrected,
+        };
+
+        *last = Some((at, time));
+        time
+    }
+}
+
+/// Default smallest change of the system clock reported as a step
+pub const DEFAULT_STEP_THRESHOLD: Duration = Duration::from_millis(125);
+
+/// Detector of steps of the system clock, comparing the wall time and
+/// the monotonic time elapsed between two checks. Differences up to
+/// what slewing at [`DEFAULT_MAX_SLEW_PPM`] accounts for are tolerated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StepDetector {
+    threshold: Duration,
+    last: Option<(Instant, SystemTime)>,
+}
+
+impl StepDetector {
+    /// Create new detector reporting steps larger than `threshold`
+    pub fn new(threshold: Duration) -> Self {
+        StepDetector {
+            threshold,
+            last: None,
+        }
+    }
+
+    /// Returns the step of the system clock since the previous check in
+    /// microseconds, if any
+    pub fn check(&mut self) -> Option<i64> {
+        self.check_at(Instant::now(), SystemTime::now())
+    }
+
+    /// Returns the step of the wall clock between the previous check and
+    /// the given times in microseconds, if any
+    pub fn check_at(&mut self, at: Instant, wall: SystemTime) -> Option<i64> {
+        let (last_at, last_wall) = self.last.replace((at, wall))?;
+        let elapsed = at.saturating_duration_since(last_at);
+        let wall_elapsed = match wall.duration_since(last_wall) {
+            Ok(forward) => forward.as_micros() as i64,
+            Err(err) => -(err.duration().as_micros() as i64),
+        };
+        let step = wall_elapsed - elapsed.as_micros() as i64;
+        let tolerance =
+            self.threshold + elapsed.mul_f64(DEFAULT_MAX_SLEW_PPM / 1e6);
+
+        if step.unsigned_abs() > tolerance.as_micros() as u64 {
+            Some(step)
+        } else {
+            None
+        }
+    }
+
+    /// Forget the previous check, e.g. after a suspension
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Take the current times as the reference of the next check,
+    /// ignoring any step since the previous one, e.g. right after
+    /// stepping the clock on purpose
+    pub fn rebase(&mut self) {
+        self.rebase_at(Instant::now(), SystemTime::now());
+    }
+
+    /// Take the given times as the reference of the next check
+    pub fn rebase_at(&mut self, at: Instant, wall: SystemTime) {
+        self.last = Some((at, wall));
+    }
+}
+
+impl Default for StepDetector {
+    fn default() -> Self {
+        StepDetector::new(DEFAULT_STEP_THRESHOLD)
+    }
+}
+
+/// Default shortest suspension reported on resume
+pub const DEFAULT_SUSPEND_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Detector of suspensions of the system, comparing on Linux the boot
+/// time, which keeps counting while suspended, with the monotonic time,
+/// which does not. Available on Linux and Android, nothing is detected
+/// on other platforms
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SuspendDetector {
+    threshold: Duration,
+    last: Option<Duration>,
+}
+
+impl SuspendDetector {
+    /// Create new detector reporting suspensions longer than `threshold`
+    pub fn new(threshold: Duration) -> Self {
+        SuspendDetector {
+            threshold,
+            last: None,
+        }
+    }
+
+    /// Returns how long the system was suspended since the previous
+    /// check, if it was
+    pub fn check(&mut self) -> Option<Duration> {
+        self.check_with(suspended_time()?)
+    }
+
+    fn check_with(&mut self, suspended: Duration) -> Option<Duration> {
+        let last = self.last.replace(suspended)?;
+        let slept = suspended.saturating_sub(last);
+
+        if slept > self.threshold {
+            Some(slept)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SuspendDetector {
+    fn default() -> Self {
+        SuspendDetector::new(DEFAULT_SUSPEND_THRESHOLD)
+    }
+}
+
+/// Returns the total time the system spent suspended since boot
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn suspended_time() -> Option<Duration> {
+    let read = |clock| {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        // SAFETY: clock_gettime only writes the given timespec
+        if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+            return None;
+        }
+
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    };
+    let monotonic = read(libc::CLOCK_MONOTONIC)?;
+    let boot = read(libc::CLOCK_BOOTTIME)?;
+
+    Some(boot.saturating_sub(monotonic))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn suspended_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use crate::clock::{
+        monotonic, precise_now, MonotonicCorrectedClock, NtpClock,
+        StepDetector, SuspendDetector,
+    };
+    use crate::NtpResult;
+    use std::thread;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    #[test]
+    fn test_ntp_clock_anchor() {
+        let mut clock = NtpClock::new().max_slew(1e6);
+        let base = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+
+        clock.step(-500_000);
+        clock.base = Some((monotonic(), base));
+
+        let elapsed = clock
+            .now()
+            .duration_since(base - Duration::from_millis(500))
+            .unwrap();
+
+        assert!(elapsed < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_ntp_clock_bound() {
+        let mut clock = NtpClock::new();
+
+        // 1 ms of maximum error
+        clock.update(&NtpResult::new(0, 0, 2_000, 0));
+        thread::sleep(Duration::from_millis(200));
+
+        let (now, uncertainty) = clock.now_bounded().unwrap();
+        let system = precise_now();
+        let error = system
+            .duration_since(now)
+            .unwrap_or_else(|err| err.duration());
+
+        assert!(uncertainty >= Duration::from_millis(1));
+        assert!(error <= uncertainty, "{:?} > {:?}", error, uncertainty);
+    }
+
+    #[test]
+    fn test_ntp_clock_slew() {
+        let mut clock = NtpClock::new().max_slew(500.0);
+        let start = Instant::now();
+
+        clock.set_offset_at(start, -1_000);
+
+        assert_eq!(0, clock.offset_at(start));
+        assert_eq!(-500, clock.offset_at(start + Duration::from_secs(1)));
+        assert_eq!(-1_000, clock.offset_at(start + Duration::from_secs(3)));
+
+        clock.set_offset_at(start + Duration::from_secs(1), 250);
+
+        assert_eq!(0, clock.offset_at(start + Duration::from_secs(2)));
+        assert_eq!(250, clock.offset_at(start + Duration::from_secs(10)));
+        assert_eq!(250, clock.target_offset());
+    }
+
+    #[test]
+    fn test_ntp_clock_uncertainty() {
+        let mut clock = NtpClock::new().max_slew(500.0).tolerance(10.0);
+        let start = Instant::now();
+
+        assert_eq!(None, clock.uncertainty_at(start));
+
+        clock.synced = Some((start, 1_000));
+        clock.set_drift(-5.0);
+        clock.set_offset_at(start, 2_000);
+
+        // 1000 of the 2000 us remain to slew, 30 us drifted
+        assert_eq!(
+            Some(Duration::from_micros(2_030)),
+            clock.uncertainty_at(start + Duration::from_secs(2))
+        );
+        assert_eq!(
+            Some(Duration::from_micros(2_500)),
+            clock.uncertainty_at(start + Duration::from_secs(100))
+        );
+    }
+
+    #[test]
+    fn test_monotonic_clock_smears_backward_steps() {
+        let clock = MonotonicCorrectedClock::new(NtpClock::new());
+        let start = Instant::now();
+        let time = UNIX_EPOCH + Duration::from_secs(1_000);
+        let second = Duration::from_secs(1);
+
+        assert_eq!(time, clock.advance(start, time));
+
+        // Corrected time stepped 10 s back
+        let smeared = clock.advance(start + second, time - 9 * second);
+
+        assert_eq!(time + Duration::from_micros(999_500), smeared);
+        assert_eq!(
+            time + 12 * second,
+            clock.advance(start + 2 * second, time + 12 * second)
+        );
+    }
+
+    #[test]
+    fn test_step_detector() {
+        let mut detector = StepDetector::default();
+        let start = Instant::now();
+        let time = UNIX_EPOCH + Duration::from_secs(1_000);
+        let minute = Duration::from_secs(60);
+        let second = Duration::from_secs(1);
+
+        assert_eq!(None, detector.check_at(start, time));
+        // Slewed by 20 ms
+        assert_eq!(
+            None,
+            detector.check_at(
+                start + minute,
+                time + minute + Duration::from_millis(20)
+            )
+        );
+        assert_eq!(
+            Some(-2_020_000),
+            detector.check_at(
+                start + 2 * minute,
+                time + 2 * minute - 2 * Duration::from_secs(1)
+            )
+        );
+
+        // Stepped 10 s ahead on purpose
+        detector.rebase_at(start + 3 * minute, time + 3 * minute + 10 * second);
+
+        assert_eq!(
+            None,
+            detector
+                .check_at(start + 4 * minute, time + 4 * minute + 10 * second)
+        );
+    }
+
+    #[test]
+    fn test_suspend_detector() {
+        let mut detector = SuspendDetector::default();
+        let second = Duration::from_secs(1);
+
+        assert_eq!(None, detector.check_with(10 * second));
+        assert_eq!(None, detector.check_with(10 * second));
+        assert_eq!(Some(50 * second), detector.check_with(60 * second));
+
+        if cfg!(any(target_os = "linux", target_os = "android")) {
+            let mut detector = SuspendDetector::default();
+
+            assert_eq!(None, detector.check());
+            assert_eq!(None, detector.check());
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt::Debug;
+use std::fmt::Formatter;
+
+/// Outcome of a system clock accuracy check
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClockVerdict {
+    /// Median offset of the system clock in microseconds
+    pub offset: i64,
+    /// Smallest maximum error among the collected samples in microseconds
+    pub max_error: u64,
+    /// Number of successful samples the verdict is based on
+    pub samples: usize,
+    /// Accepted absolute offset in microseconds
+    pub tolerance: u64,
+}
+
+impl ClockVerdict {
+    /// Returns measured system clock offset in microseconds
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Returns number of samples the verdict is based on
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Returns whether the system clock is within the requested tolerance
+    pub fn is_accurate(&self) -> bool {
+        self.offset.unsigned_abs() <= self.tolerance
+    }
+}
+
+impl Debug for ClockVerdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClockVerdict")
+            .field("offset", &self.offset)
+            .field("max_error", &self.max_error)
+            .field("samples", &self.samples)
+            .field("tolerance", &self.tolerance)
+            .field("accurate", &self.is_accurate())
+            .finish()
+    }
+}
@@ -0,0 +1,293 @@
+//! RFC 4330 compliance mode
+//!
+//! [`Compliance`] wraps a client to enforce the client requirements of
+//! RFC 4330, section 10, helping to certify embedded products:
+//!
+//! * requests to a server are never less than 15 s apart
+//! * the interval between two requests to a server is randomized, from
+//!   the poll interval up to half of it more
+//! * an address that sent a kiss-o'-death is never sent a request again,
+//!   the other addresses of its name still are
+//! * every response goes through the strict sanity checks
+//!
+//! Requests breaking these rules are refused, and recorded along with
+//! the rejected responses in a [`ComplianceReport`]
+//!
+//! ```rust,no_run
+//! use sntprs::compliance::Compliance;
+//! use std::thread;
+//!
+//! let mut client = Compliance::new();
+//!
+//! loop {
+//!     thread::sleep(client.poll_delay("pool.ntp.org", 123));
+//!
+//!     if let Ok(result) = client.request("pool.ntp.org", 123) {
+//!         println!("Offset: {}", result.offset());
+//!     }
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::clock::{ClockSource, SystemClock};
+use crate::resolver::resolve;
+use crate::transport::{Transport, UdpTransport};
+use crate::{random_u64, Error, NtpClient, NtpResult};
+use crate::{Reason, ValidationProfile, Violation};
+
+/// Shortest poll interval allowed by RFC 4330
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(64);
+
+/// Request refused as it would break a requirement of RFC 4330
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ComplianceViolation {
+    /// The request came `early` before the end of the poll interval
+    PollTooSoon { server: String, early: Duration },
+    /// Every address of the server sent a kiss-o'-death before
+    KissedServer { server: String },
+}
+
+impl Display for ComplianceViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComplianceViolation::PollTooSoon { server, early } => write!(
+                f,
+                "Request to {} {} ms before the end of the poll interval",
+                server,
+                early.as_millis()
+            ),
+            ComplianceViolation::KissedServer { server } => {
+                write!(f, "Request to {} after a kiss-o'-death", server)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ComplianceViolation {}
+
+/// Violations recorded by a [`Compliance`] client
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ComplianceReport {
+    /// Requests refused, in the order they were made
+    pub violations: Vec<ComplianceViolation>,
+    /// Responses failing a sanity check, with the address of their
+    /// server
+    pub rejected: Vec<(SocketAddr, Violation)>,
+}
+
+impl ComplianceReport {
+    /// Returns whether no request had to be refused
+    pub fn is_compliant(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Display for ComplianceReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Refused requests: {}", self.violations.len())?;
+
+        for violation in &self.violations {
+            writeln!(f, "  {}", violation)?;
+        }
+
+        writeln!(f, "Rejected responses: {}", self.rejected.len())?;
+
+        for (server, violation) in &self.rejected {
+            writeln!(f, "  {}: {}", server, violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Client enforcing the requirements of RFC 4330
+pub struct Compliance<T = UdpTransport, C = SystemClock> {
+    client: NtpClient<T, C>,
+    poll_interval: Duration,
+    next_poll: HashMap<String, Instant>,
+    kissed: HashSet<SocketAddr>,
+    report: ComplianceReport,
+}
+
+impl Compliance {
+    /// Create new compliant client polling every 64 s or more
+    pub fn new() -> Self {
+        Compliance {
+            client: NtpClient::new().validation(ValidationProfile::Strict),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            next_poll: HashMap::new(),
+            kissed: HashSet::new(),
+            report: ComplianceReport::default(),
+        }
+    }
+}
+
+impl<T, C> Compliance<T, C> {
+    /// Use the given client for the requests, with the strict sanity
+    /// checks
+    pub fn client<U, D>(self, client: NtpClient<U, D>) -> Compliance<U, D> {
+        Compliance {
+            client: client.validation(ValidationProfile::Strict),
+            poll_interval: self.poll_interval,
+            next_poll: self.next_poll,
+            kissed: self.kissed,
+            report: self.report,
+        }
+    }
+
+    /// Minimum time between two requests to a server, raised to 15 s
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval.max(MIN_POLL_INTERVAL);
+        self
+    }
+
+    /// Returns the time left before the server may be sent a request
+    ///
+    /// * `pool` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn poll_delay(&self, pool: &str, port: u32) -> Duration {
+        self.next_poll
+            .get(&server_key(pool, port))
+            .map_or(Duration::ZERO, |next| {
+                next.saturating_duration_since(Instant::now())
+            })
+    }
+
+    /// Returns the violations recorded so far
+    pub fn report(&self) -> &ComplianceReport {
+        &self.report
+    }
+
+    /// Record a refused request, returned as an error of kind
+    /// [`io::ErrorKind::PermissionDenied`]
+    fn refuse(&mut self, violation: ComplianceViolation) -> io::Error {
+        debug!("{}", violation);
+        self.report.violations.push(violation.clone());
+        io::Error::new(io::ErrorKind::PermissionDenied, violation)
+    }
+}
+
+impl<T, C> Compliance<T, C>
+where
+    T: Transport + Clone + Send + 'static,
+    C: ClockSource + Clone + Send + 'static,
+{
+    /// Send request to the first address of a NTP server that did not
+    /// send a kiss-o'-death, unless RFC 4330 forbids it
+    ///
+    /// * `pool` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn request(&mut self, pool: &str, port: u32) -> io::Result<NtpResult> {
+        let server = server_key(pool, port);
+        let early = self.poll_delay(pool, port);
+
+        if !early.is_zero() {
+            return Err(
+                self.refuse(ComplianceViolation::PollTooSoon { server, early })
+            );
+        }
+
+        let port = u16::try_from(port).map_err(|_| Error::InvalidPort(port))?;
+        let addrs = resolve(pool, port, self.client.resolve_timeout())?;
+        let addr = match addrs.into_iter().find(|a| !self.kissed.contains(a)) {
+            Some(addr) => addr,
+            None => {
+                return Err(
+                    self.refuse(ComplianceViolation::KissedServer { server })
+                )
+            }
+        };
+
+        // Up to half of the poll interval more, in thousandths
+        let jitter = self.poll_interval / 2000 * (random_u64() % 1001) as u32;
+
+        self.next_poll
+            .insert(server, Instant::now() + self.poll_interval + jitter);
+
+        let result = self.client.request_addr(addr);
+
+        if let Some(Error::Validation(violation)) =
+            result.as_ref().err().and_then(Error::from_io)
+        {
+            if violation.reason() == Reason::KissOfDeath {
+                debug!("Kiss-o'-death from {}, no more requests", addr);
+                self.kissed.insert(addr);
+            }
+
+            self.report.rejected.push((addr, violation));
+        }
+
+        result.map(NtpResult::from)
+    }
+}
+
+impl Default for Compliance {
+    fn default() -> Self {
+        Compliance::new()
+    }
+}
+
+fn server_key(pool: &str, port: u32) -> String {
+    format!("{}:{}", pool, port)
+}
+
+#[cfg(test)]
+mod compliance_tests {
+    use crate::compliance::{Compliance, ComplianceViolation};
+    use crate::fakeserver::FakeServer;
+    use crate::proto::KISS_DENY;
+    use crate::{NtpClient, Violation};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_compliance() {
+        let kissing: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let server = FakeServer::new().kissing(kissing);
+        let mut client = Compliance::new()
+            .poll_interval(Duration::from_secs(1))
+            .client(NtpClient::new().transport(server.clone()));
+
+        assert!(client.request("192.0.2.2", 123).is_ok());
+
+        let delay = client.poll_delay("192.0.2.2", 123);
+
+        assert!(delay > Duration::from_secs(14));
+        assert!(delay <= Duration::from_millis(22_500));
+        assert!(client.request("192.0.2.2", 123).is_err());
+
+        assert!(client.request("192.0.2.1", 123).is_err());
+
+        client.next_poll.clear();
+
+        assert!(client.request("192.0.2.1", 123).is_err());
+        assert_eq!(2, server.exchanges());
+
+        let report = client.report();
+
+        assert!(!report.is_compliant());
+        assert!(matches!(
+            report.violations[0],
+            ComplianceViolation::PollTooSoon { .. }
+        ));
+        assert_eq!(
+            ComplianceViolation::KissedServer {
+                server: "192.0.2.1:123".to_string()
+            },
+            report.violations[1]
+        );
+        assert_eq!(
+            vec![(kissing, Violation::KissOfDeath(KISS_DENY))],
+            report.rejected
+        );
+    }
+}
@@ -0,0 +1,602 @@
+//! Shared configuration
+//!
+//! [`Config`] gathers the settings of a client or of the synchronization
+//! service in a single type, which can be loaded from TOML or JSON files
+//! when the `toml` or `json` features are enabled. Any field can be
+//! overridden by an environment variable:
+//!
+//! | Variable                   | Field                 |
+//! |----------------------------|-----------------------|
+//! | `SNTP_SERVERS`             | `servers`, comma separated |
+//! | `SNTP_POLL_INTERVAL_SECS`  | `poll_interval_secs`  |
+//! | `SNTP_POLL_JITTER_PERCENT` | `poll_jitter_percent` |
+//! | `SNTP_TIMEOUT_MS`          | `timeout_ms`          |
+//! | `SNTP_OFFSET_THRESHOLD_US` | `offset_threshold_us` |
+//! | `SNTP_FAILURE_THRESHOLD`   | `failure_threshold`   |
+//! | `SNTP_BIND_ADDRESS`        | `bind_address`        |
+//! | `SNTP_POOL_ZONE`           | `pool_zone`           |
+//! | `SNTP_POOL_SIZE`           | `pool_size`           |
+//! | `SNTP_POOL_RESOLVE_INTERVAL_SECS` | `pool_resolve_interval_secs` |
+//!
+//! As in `ntp.conf`, a server can be followed by the `prefer`,
+//! `noselect` and `weight=N` options.
+//!
+//! Rather than listing servers, a pool.ntp.org vendor zone can be given
+//! along with the number of distinct servers to poll, see [`Pool`].
+//!
+//! Presets tune the timeout, polling interval, validation and alert
+//! thresholds for common scenarios: [`Config::embedded`],
+//! [`Config::desktop`], [`Config::server`] and [`Config::measurement`].
+//!
+//! # Example
+//!
+//! ```toml
+//! servers = ["time.google.com prefer", "pool.ntp.org:123 weight=2"]
+//! poll_interval_secs = 64
+//! timeout_ms = 2000
+//! offset_threshold_us = 100000
+//! ```
+
+use std::env;
+use std::fmt::Display;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::debug;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::monitor::Monitor;
+use crate::pool::Pool;
+use crate::proto::NTP_PORT;
+use crate::source::{Coordinator, NtpSource, SelectOptions};
+use crate::webhook::Webhook;
+use crate::{NtpClient, ValidationProfile};
+
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 64;
+const DEFAULT_TIMEOUT_MS: u64 = 2_000;
+const DEFAULT_POOL_SIZE: usize = 4;
+const DEFAULT_POOL_RESOLVE_INTERVAL_SECS: u64 = 3_600;
+const ENV_PREFIX: &str = "SNTP_";
+
+/// Symmetric key shared with an NTP server
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AuthKey {
+    /// Key identifier
+    pub id: u32,
+    /// Digest algorithm, e.g. `MD5` or `SHA1`
+    pub algorithm: String,
+    /// Key value
+    pub key: String,
+}
+
+/// Client and synchronization service configuration
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Config {
+    /// NTP servers as `host` or `host:port`, optionally followed by
+    /// selection options
+    pub servers: Vec<String>,
+    /// Polling interval in seconds
+    pub poll_interval_secs: u64,
+    /// Random part of the polling interval, in percent of it, more or
+    /// less, up to 50
+    pub poll_jitter_percent: u8,
+    /// Response timeout in milliseconds
+    pub timeout_ms: u64,
+    /// Absolute clock offset in microseconds raising an alert
+    pub offset_threshold_us: Option<u64>,
+    /// Number of consecutive failed polls raising an alert
+    pub failure_threshold: Option<usize>,
+    /// Symmetric keys shared with the servers
+    pub keys: Vec<AuthKey>,
+    /// Local address the requests are sent from
+    pub bind_address: Option<IpAddr>,
+    /// URL the alerts are posted to, as `http://host[:port]/path`
+    pub webhook_url: Option<String>,
+    /// Sanity checks run on the responses
+    pub validation: ValidationProfile,
+    /// pool.ntp.org vendor zone, e.g. `vendor.pool.ntp.org`
+    pub pool_zone: Option<String>,
+    /// Number of distinct servers of the pool to poll
+    pub pool_size: usize,
+    /// Minimum time between two resolutions of the pool in seconds
+    pub pool_resolve_interval_secs: u64,
+}
+
+impl Config {
+    /// Returns a configuration for embedded devices: infrequent polls
+    /// of a small pool to save power and bandwidth, spread by a 10 %
+    /// jitter as whole fleets often start together, tolerating the
+    /// legacy servers often found on industrial networks
+    pub fn embedded() -> Config {
+        Config {
+            poll_interval_secs: 1_024,
+            poll_jitter_percent: 10,
+            timeout_ms: 1_000,
+            failure_threshold: Some(8),
+            validation: ValidationProfile::Lenient,
+            pool_size: 2,
+            ..Config::default()
+        }
+    }
+
+    /// Returns a configuration for desktops and laptops, polling every
+    /// few minutes and alerting when the clock is off by a second
+    pub fn desktop() -> Config {
+        Config {
+            poll_interval_secs: 256,
+            timeout_ms: 2_000,
+            offset_threshold_us: Some(1_000_000),
+            failure_threshold: Some(4),
+            ..Config::default()
+        }
+    }
+
+    /// Returns a configuration for servers, polling every minute with
+    /// a short timeout and alerting on offsets over 100 ms
+    pub fn server() -> Config {
+        Config {
+            poll_interval_secs: 64,
+            timeout_ms: 1_000,
+            offset_threshold_us: Some(100_000),
+            failure_threshold: Some(3),
+            ..Config::default()
+        }
+    }
+
+    /// Returns a configuration for offset measurements, polling a large
+    /// pool as often as servers allow and reporting any failure
+    pub fn measurement() -> Config {
+        Config {
+            poll_interval_secs: 16,
+            timeout_ms: 500,
+            failure_threshold: Some(1),
+            pool_size: 8,
+            pool_resolve_interval_secs: 600,
+            ..Config::default()
+        }
+    }
+
+    /// Returns the default configuration overridden by the `SNTP_*`
+    /// environment variables
+    pub fn from_env() -> io::Result<Config> {
+        let mut config = Config::default();
+
+        config.overlay_env()?;
+        Ok(config)
+    }
+
+    /// Override fields with the values of the `SNTP_*` environment variables
+    pub fn overlay_env(&mut self) -> io::Result<()> {
+        self.overlay(
+            env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)),
+        )
+    }
+
+    fn overlay<I>(&mut self, vars: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            match name.as_str() {
+                "SNTP_SERVERS" => {
+                    self.servers = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|server| !server.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+                "SNTP_POLL_INTERVAL_SECS" => {
+                    self.poll_interval_secs = parse_var(&name, &value)?
+                }
+                "SNTP_POLL_JITTER_PERCENT" => {
+                    self.poll_jitter_percent = parse_var(&name, &value)?
+                }
+                "SNTP_TIMEOUT_MS" => {
+                    self.timeout_ms = parse_var(&name, &value)?
+                }
+                "SNTP_OFFSET_THRESHOLD_US" => {
+                    self.offset_threshold_us = Some(parse_var(&name, &value)?)
+                }
+                "SNTP_FAILURE_THRESHOLD" => {
+                    self.failure_threshold = Some(parse_var(&name, &value)?)
+                }
+                "SNTP_BIND_ADDRESS" => {
+                    self.bind_address = Some(parse_var(&name, &value)?)
+                }
+                "SNTP_POOL_ZONE" => self.pool_zone = Some(value),
+                "SNTP_POOL_SIZE" => self.pool_size = parse_var(&name, &value)?,
+                "SNTP_POOL_RESOLVE_INTERVAL_SECS" => {
+                    self.pool_resolve_interval_secs = parse_var(&name, &value)?
+                }
+                "SNTP_WEBHOOK_URL" => {
+                    Webhook::new(&value)?;
+                    self.webhook_url = Some(value);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the configured servers as name and port pairs
+    pub fn servers(&self) -> Vec<(&str, u32)> {
+        self.servers
+            .iter()
+            .map(|server| split_host_port(server_address(server)))
+            .collect()
+    }
+
+    /// Returns the selection options of every configured server
+    pub fn server_options(&self) -> io::Result<Vec<SelectOptions>> {
+        self.servers
+            .iter()
+            .map(|server| parse_options(server))
+            .collect()
+    }
+
+    /// Returns a coordinator sampling the configured servers with a
+    /// client set up according to the configuration. A server listed
+    /// twice is only sampled once, with its first options
+    pub fn coordinator(&self) -> io::Result<Coordinator> {
+        let client = self.client();
+        let options = self.server_options()?;
+        let servers = self.servers();
+
+        Ok(servers.iter().zip(options).enumerate().fold(
+            Coordinator::new(),
+            |coordinator, (i, (&(server, port), options))| {
+                if servers[..i].contains(&(server, port)) {
+                    return coordinator;
+                }
+
+                coordinator.source(
+                    NtpSource::new(server, port)
+                        .client(client.clone())
+                        .options(options),
+                )
+            },
+        ))
+    }
+
+    /// Returns a pool of servers from the configured vendor zone, polled
+    /// with a client set up according to the configuration
+    pub fn pool(&self) -> Option<Pool> {
+        let zone = self.pool_zone.as_ref()?;
+
+        Some(
+            Pool::zone(zone, NTP_PORT)
+                .client(self.client())
+                .size(self.pool_size)
+                .resolve_interval(Duration::from_secs(
+                    self.pool_resolve_interval_secs,
+                )),
+        )
+    }
+
+    /// Returns the polling interval
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    /// Returns the poll jitter as a fraction of the polling interval, for
+    /// [`SyncService::poll_jitter`](crate::service::SyncService::poll_jitter),
+    /// up to 50 %
+    pub fn poll_jitter(&self) -> f64 {
+        f64::from(self.poll_jitter_percent.min(50)) / 100.0
+    }
+
+    /// Returns the response timeout
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Returns a client set up according to the configuration
+    pub fn client(&self) -> NtpClient {
+        let client = NtpClient::new()
+            .timeout(self.timeout())
+            .poll_interval(self.poll_interval())
+            .validation(self.validation);
+
+        match self.bind_address {
+            Some(addr) => client.bind_address(addr),
+            None => client,
+        }
+    }
+
+    /// Returns a monitor raising alerts on the configured thresholds,
+    /// posting them to the configured webhook
+    pub fn monitor(&self) -> Monitor {
+        let monitor = Monitor::new();
+        let monitor = match self.offset_threshold_us {
+            Some(threshold) => monitor.offset_threshold(threshold),
+            None => monitor,
+        };
+        let mut monitor = match self.failure_threshold {
+            Some(count) => monitor.failure_threshold(count),
+            None => monitor,
+        };
+
+        if let Some(webhook) = self.webhook() {
+            webhook.register(&mut monitor);
+        }
+
+        monitor
+    }
+
+    /// Returns the webhook the alerts are posted to, if configured with
+    /// a supported URL
+    pub fn webhook(&self) -> Option<Webhook> {
+        let url = self.webhook_url.as_ref()?;
+
+        match Webhook::new(url) {
+            Ok(webhook) => Some(webhook),
+            Err(err) => {
+                debug!("Ignoring webhook: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Parse configuration in TOML format
+    #[cfg(feature = "toml")]
+    pub fn from_toml(content: &str) -> io::Result<Config> {
+        toml::from_str(content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Parse configuration in JSON format
+    #[cfg(feature = "json")]
+    pub fn from_json(content: &str) -> io::Result<Config> {
+        serde_json::from_str(content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Load configuration from a file, whose format is chosen by its
+    /// extension (`.toml` or `.json`), then override it with the `SNTP_*`
+    /// environment variables
+    #[cfg(any(feature = "toml", feature = "json"))]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<Config> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let mut config = match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Config::from_toml(&content)?,
+            #[cfg(feature = "json")]
+            Some("json") => Config::from_json(&content)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Unsupported configuration file format",
+                ))
+            }
+        };
+
+        config.overlay_env()?;
+        Ok(config)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            servers: Vec::new(),
+            poll_interval_secs: DEFAULT_POLL_INTERVAL_SECS,
+            poll_jitter_percent: 0,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            offset_threshold_us: None,
+            failure_threshold: None,
+            keys: Vec::new(),
+            bind_address: None,
+            webhook_url: None,
+            validation: ValidationProfile::Strict,
+            pool_zone: None,
+            pool_size: DEFAULT_POOL_SIZE,
+            pool_resolve_interval_secs: DEFAULT_POOL_RESOLVE_INTERVAL_SECS,
+        }
+    }
+}
+
+fn parse_var<T>(name: &str, value: &str) -> io::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.trim().parse().map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid value of {}: {}", name, err),
+        )
+    })
+}
+
+/// Returns the address part of a server entry, before its options
+fn server_address(server: &str) -> &str {
+    server.split_whitespace().next().unwrap_or("")
+}
+
+/// Parse the `prefer`, `noselect` and `weight=N` options of a server entry
+fn parse_options(server: &str) -> io::Result<SelectOptions> {
+    let mut options = SelectOptions::default();
+
+    for option in server.split_whitespace().skip(1) {
+        match option.split_once('=') {
+            None if option == "prefer" => options.prefer = true,
+            None if option == "noselect" => options.noselect = true,
+            Some(("weight", weight)) => {
+                options.weight = parse_var("weight", weight)?;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown option {} of server {}", option, server),
+                ))
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+/// Split `host:port`, `[ipv6]:port` or a bare host name into its parts
+fn split_host_port(server: &str) -> (&str, u32) {
+    let parse = |host, port: &str| match port.parse() {
+        Ok(port) => (host, port),
+        Err(_) => (server, NTP_PORT),
+    };
+
+    if let Some(rest) = server.strip_prefix('[') {
+        return match rest.split_once("]:") {
+            Some((host, port)) => parse(host, port),
+            None => (rest.trim_end_matches(']'), NTP_PORT),
+        };
+    }
+
+    match server.split_once(':') {
+        // More than one colon is a bare IPv6 address
+        Some((host, port)) if !port.contains(':') => parse(host, port),
+        _ => (server, NTP_PORT),
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use crate::config::Config;
+    use crate::source::SelectOptions;
+    use crate::ValidationProfile;
+
+    #[test]
+    fn test_config_servers() {
+        let config = Config {
+            servers: vec![
+                "pool.ntp.org".to_string(),
+                "time.google.com:1123".to_string(),
+                "[::1]:123".to_string(),
+                "::1".to_string(),
+            ],
+            ..Config::default()
+        };
+
+        assert_eq!(
+            vec![
+                ("pool.ntp.org", 123),
+                ("time.google.com", 1123),
+                ("::1", 123),
+                ("::1", 123),
+            ],
+            config.servers()
+        );
+    }
+
+    #[test]
+    fn test_config_server_options() {
+        let config = Config {
+            servers: vec![
+                "pool.ntp.org".to_string(),
+                "time.google.com:1123 noselect".to_string(),
+                "::1 prefer weight=3".to_string(),
+            ],
+            ..Config::default()
+        };
+
+        assert_eq!(
+            vec![
+                ("pool.ntp.org", 123),
+                ("time.google.com", 1123),
+                ("::1", 123)
+            ],
+            config.servers()
+        );
+
+        let options = config.server_options().unwrap();
+
+        assert_eq!(SelectOptions::default(), options[0]);
+        assert!(options[1].noselect && !options[1].prefer);
+        assert!(options[2].prefer && !options[2].noselect);
+        assert_eq!(3, options[2].weight);
+        assert!(Config {
+            servers: vec!["pool.ntp.org iburst".to_string()],
+            ..Config::default()
+        }
+        .server_options()
+        .is_err());
+    }
+
+    #[test]
+    fn test_config_env_overlay() {
+        let mut config = Config::default();
+        let vars = vec![
+            ("SNTP_SERVERS", "pool.ntp.org, time.google.com"),
+            ("SNTP_TIMEOUT_MS", "250"),
+            ("SNTP_BIND_ADDRESS", "10.0.0.1"),
+            ("SNTP_POOL_ZONE", "vendor.pool.ntp.org"),
+            ("SNTP_POOL_SIZE", "6"),
+            ("SNTP_UNKNOWN", "ignored"),
+        ];
+        let vars = vars
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        config.overlay(vars).unwrap();
+
+        assert_eq!(vec!["pool.ntp.org", "time.google.com"], config.servers);
+        assert_eq!(250, config.timeout_ms);
+        assert_eq!(Some("10.0.0.1".parse().unwrap()), config.bind_address);
+        assert_eq!(6, config.pool_size);
+        assert!(config.pool().is_some());
+        assert!(Config::default().pool().is_none());
+        assert!(config
+            .overlay(vec![("SNTP_TIMEOUT_MS".to_string(), "x".to_string())])
+            .is_err());
+    }
+
+    #[test]
+    fn test_config_presets() {
+        let presets = [
+            Config::embedded(),
+            Config::desktop(),
+            Config::server(),
+            Config::measurement(),
+        ];
+
+        for (i, preset) in presets.iter().enumerate() {
+            for other in &presets[i + 1..] {
+                assert_ne!(preset, other);
+            }
+
+            assert!(preset.servers.is_empty());
+        }
+
+        assert_eq!(ValidationProfile::Lenient, presets[0].validation);
+        assert_eq!(0.1, presets[0].poll_jitter());
+        assert_eq!(
+            0.5,
+            Config {
+                poll_jitter_percent: 90,
+                ..Config::default()
+            }
+            .poll_jitter()
+        );
+        assert!(presets[3].poll_interval() < presets[2].poll_interval());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_config_from_toml() {
+        let config = Config::from_toml(
+            "servers = [\"pool.ntp.org\"]\ntimeout_ms = 500\n",
+        )
+        .unwrap();
+
+        assert_eq!(vec!["pool.ntp.org".to_string()], config.servers);
+        assert_eq!(500, config.timeout_ms);
+        assert_eq!(64, config.poll_interval_secs);
+    }
+}
@@ -0,0 +1,348 @@
+//! Control socket of the synchronization service
+//!
+//! A Unix domain socket accepting one [`Command`] per connection, as a
+//! single text line, so a running [`SyncService`] can be queried and
+//! reconfigured without restarting it:
+//!
+//! ```text
+//! status
+//! sync-now
+//! add-server <host>[:<port>]
+//! remove-server <host>[:<port>]
+//! interval <seconds>
+//! ```
+//!
+//! The answer starts with an `ok` or `error: <message>` line, followed
+//! by the `Name=value` lines of the status or the measured offset
+//!
+//! ```rust,no_run
+//! use std::sync::{Arc, Mutex};
+//!
+//! use sntprs::control::{self, Command};
+//! use sntprs::service::SyncService;
+//!
+//! let service = Arc::new(Mutex::new(SyncService::new("pool.ntp.org", 123)));
+//!
+//! control::serve("/tmp/sntprs.sock", service).unwrap();
+//!
+//! let status = control::send("/tmp/sntprs.sock", &Command::Status).unwrap();
+//! ```
+
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::proto::NTP_PORT;
+use crate::service::{Status, SyncService};
+
+/// Default path of the control socket
+pub const DEFAULT_SOCKET: &str = "/run/sntprs/control.sock";
+
+/// Time a client is given to send its command and read the answer
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request sent over the control socket
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// Report the service state
+    Status,
+    /// Synchronize right away
+    SyncNow,
+    /// Add a fallback server
+    AddServer(String, u32),
+    /// Remove a server
+    RemoveServer(String, u32),
+    /// Change the polling interval
+    SetInterval(Duration),
+}
+
+impl FromStr for Command {
+    type Err = io::Error;
+
+    fn from_str(line: &str) -> io::Result<Command> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid command: {}", line),
+            )
+        };
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("status"), None) => Command::Status,
+            (Some("sync-now"), None) => Command::SyncNow,
+            (Some("add-server"), Some(server)) => {
+                let (server, port) =
+                    parse_server(server).ok_or_else(invalid)?;
+                Command::AddServer(server, port)
+            }
+            (Some("remove-server"), Some(server)) => {
+                let (server, port) =
+                    parse_server(server).ok_or_else(invalid)?;
+                Command::RemoveServer(server, port)
+            }
+            (Some("interval"), Some(secs)) => {
+                let secs = secs.parse().map_err(|_| invalid())?;
+
+                if secs == 0 {
+                    return Err(invalid());
+                }
+
+                Command::SetInterval(Duration::from_secs(secs))
+            }
+            _ => return Err(invalid()),
+        };
+
+        if words.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(command)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Status => write!(f, "status"),
+            Command::SyncNow => write!(f, "sync-now"),
+            Command::AddServer(server, port) => {
+                write!(f, "add-server {}", format_server(server, *port))
+            }
+            Command::RemoveServer(server, port) => {
+                write!(f, "remove-server {}", format_server(server, *port))
+            }
+            Command::SetInterval(interval) => {
+                write!(f, "interval {}", interval.as_secs())
+            }
+        }
+    }
+}
+
+fn parse_server(server: &str) -> Option<(String, u32)> {
+    if let Some(rest) = server.strip_prefix('[') {
+        let (host, port) = rest.split_once(']')?;
+        let port = match port.strip_prefix(':') {
+            Some(port) => port.parse().ok()?,
+            None if port.is_empty() => NTP_PORT,
+            None => return None,
+        };
+
+        return Some((host.to_string(), port));
+    }
+
+    match server.split_once(':') {
+        Some((host, port)) if !port.contains(':') => {
+            Some((host.to_string(), port.parse().ok()?))
+        }
+        _ => Some((server.to_string(), NTP_PORT)),
+    }
+}
+
+fn format_server(server: &str, port: u32) -> String {
+    if server.contains(':') {
+        format!("[{}]:{}", server, port)
+    } else {
+        format!("{}:{}", server, port)
+    }
+}
+
+/// Returns the `Name=value` lines describing `status`, named after the
+/// `timedatectl show-timesync` fields
+fn status_lines(status: &Status) -> String {
+    let last_poll = status
+        .last_poll
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_micros());
+    let interval = status.poll_interval.unwrap_or_default();
+    let (offset, delay) = status
+        .last_result
+        .map_or((0, 0), |result| (result.offset(), result.roundtrip()));
+
+    format!(
+        "ServerName={}\nPollIntervalUSec={}\nLastPollUSec={}\n\
+         OffsetUSec={}\nDelayUSec={}\nJitterUSec={}\nReach={}\n\
+         NTPSynchronized={}\n",
+        format_server(&status.server, status.port),
+        interval.as_micros(),
+        last_poll,
+        offset,
+        delay,
+        status.jitter.unwrap_or(0),
+        status.reach,
+        if status.synchronized { "yes" } else { "no" }
+    )
+}
+
+/// Run `command` on `service`, returning the answer body. The service
+/// is not locked during the exchange of a synchronization
+fn execute(
+    service: &Mutex<SyncService>,
+    command: Command,
+) -> io::Result<String> {
+    match command {
+        Command::Status => Ok(status_lines(&service.lock().unwrap().status())),
+        Command::SyncNow => {
+            let pending = service.lock().unwrap().begin_sync();
+            let outcome = pending.send();
+
+            service
+                .lock()
+                .unwrap()
+                .finish_sync(outcome)
+                .map(|result| format!("{}\n", result.offset()))
+        }
+        Command::AddServer(server, port) => {
+            service.lock().unwrap().add_server(&server, port);
+            Ok(String::new())
+        }
+        Command::RemoveServer(server, port) => service
+            .lock()
+            .unwrap()
+            .remove_server(&server, port)
+            .map(|_| String::new()),
+        Command::SetInterval(interval) => {
+            service.lock().unwrap().set_interval(interval);
+            Ok(String::new())
+        }
+    }
+}
+
+fn handle(stream: UnixStream, service: &Mutex<SyncService>) -> io::Result<()> {
+    let mut line = String::new();
+
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let answer = line
+        .trim()
+        .parse()
+        .and_then(|command| execute(service, command));
+    let mut stream = &stream;
+
+    match answer {
+        Ok(body) => write!(stream, "ok\n{}", body),
+        Err(err) => writeln!(stream, "error: {}", err),
+    }
+}
+
+/// Listen on `path` for commands to run on `service`, from a background
+/// thread. A stale socket file left at `path` is replaced, and the new
+/// one is restricted to its owner
+pub fn serve<P: AsRef<Path>>(
+    path: P,
+    service: Arc<Mutex<SyncService>>,
+) -> io::Result<thread::JoinHandle<()>> {
+    let path = path.as_ref();
+
+    if UnixStream::connect(path).is_err() {
+        let _ = fs::remove_file(path);
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result =
+                stream.and_then(|stream| handle(stream, service.as_ref()));
+
+            if let Err(err) = result {
+                debug!("Control connection failed: {}", err);
+            }
+        }
+    }))
+}
+
+/// Send `command` to the service listening on `path`, returning the
+/// answer body
+pub fn send<P: AsRef<Path>>(path: P, command: &Command) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    let mut answer = String::new();
+
+    writeln!(stream, "{}", command)?;
+    stream.read_to_string(&mut answer)?;
+
+    let (status, body) = answer.split_once('\n').unwrap_or((&answer, ""));
+
+    match status.strip_prefix("error: ") {
+        Some(message) => Err(io::Error::other(message.to_string())),
+        None if status == "ok" => Ok(body.to_string()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected answer: {}", status),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod control_tests {
+    use crate::control::{self, Command};
+    use crate::service::SyncService;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_control_socket() {
+        let commands = [
+            Command::Status,
+            Command::SyncNow,
+            Command::AddServer("::1".to_string(), 1123),
+            Command::RemoveServer("pool.ntp.org".to_string(), 123),
+            Command::SetInterval(Duration::from_secs(64)),
+        ];
+
+        for command in &commands {
+            assert_eq!(*command, command.to_string().parse().unwrap());
+        }
+
+        assert!("interval 0".parse::<Command>().is_err());
+
+        let path = std::env::temp_dir()
+            .join(format!("sntprs-control-{}.sock", std::process::id()));
+        let mut service = SyncService::new("localhost", 123);
+
+        service.add_server("pool.ntp.org", 123);
+
+        let service = Arc::new(Mutex::new(service));
+
+        control::serve(&path, service.clone()).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+
+        assert_eq!(0o600, mode & 0o777);
+
+        for command in &commands[2..] {
+            control::send(&path, command).unwrap();
+        }
+
+        let status = control::send(&path, &Command::Status).unwrap();
+
+        assert!(status.starts_with("ServerName=localhost:123\n"));
+        assert!(status.contains("PollIntervalUSec=64000000\n"));
+        assert_eq!(
+            vec![("localhost".to_string(), 123), ("::1".to_string(), 1123)],
+            service.lock().unwrap().servers()
+        );
+        assert!(control::send(
+            &path,
+            &Command::RemoveServer("::1".to_string(), 123)
+        )
+        .is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,202 @@
+//! D-Bus interface of the synchronization service
+//!
+//! Exposes the state of a [`SyncService`] as the properties of the
+//! `org.sntprs.Sync1` interface, along with a `SyncNow()` method, so
+//! desktop environments and other services can query and trigger the
+//! synchronization as they do with systemd-timesyncd. The properties
+//! of a running service are read back with [`query_status`]
+//!
+//! ```rust,no_run
+//! use std::sync::{Arc, Mutex};
+//!
+//! use sntprs::service::SyncService;
+//!
+//! let service = Arc::new(Mutex::new(SyncService::new("pool.ntp.org", 123)));
+//! let _connection = sntprs::dbus::serve_system(service).unwrap();
+//!
+//! loop {
+//!     std::thread::park();
+//! }
+//! ```
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use zbus::blocking::fdo::PropertiesProxy;
+use zbus::blocking::{connection, Connection};
+use zbus::names::InterfaceName;
+use zbus::zvariant::Value;
+use zbus::{fdo, interface};
+
+use crate::service::{Status, SyncService};
+
+/// Well-known name requested on the bus
+pub const BUS_NAME: &str = "org.sntprs.Sync1";
+/// Path of the object implementing the interface
+pub const OBJECT_PATH: &str = "/org/sntprs/Sync1";
+
+const PROPERTIES: [&str; 8] = [
+    "ServerName",
+    "PollIntervalUSec",
+    "LastPollUSec",
+    "OffsetUSec",
+    "DelayUSec",
+    "JitterUSec",
+    "Reach",
+    "NTPSynchronized",
+];
+
+fn to_io(err: zbus::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+struct SyncInterface {
+    service: Arc<Mutex<SyncService>>,
+}
+
+impl SyncInterface {
+    fn status(&self) -> Status {
+        self.service.lock().unwrap().status()
+    }
+}
+
+#[interface(name = "org.sntprs.Sync1")]
+impl SyncInterface {
+    /// Synchronize right away, returning the offset in microseconds.
+    /// The exchange runs on a blocking thread without holding the
+    /// service lock, keeping the bus and the properties responsive
+    async fn sync_now(&self) -> fdo::Result<i64> {
+        let service = self.service.clone();
+
+        blocking::unblock(move || {
+            let pending = service.lock().unwrap().begin_sync();
+            let outcome = pending.send();
+
+            service.lock().unwrap().finish_sync(outcome)
+        })
+        .await
+        .map(|result| result.offset())
+        .map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+
+    /// Server as `host:port`
+    #[zbus(property)]
+    fn server_name(&self) -> String {
+        let status = self.status();
+
+        format!("{}:{}", status.server, status.port)
+    }
+
+    /// Polling interval in microseconds, 0 when not configured
+    #[zbus(property, name = "PollIntervalUSec")]
+    fn poll_interval_usec(&self) -> u64 {
+        let interval = self.status().poll_interval.unwrap_or_default();
+
+        interval.as_micros() as u64
+    }
+
+    /// Time of the last request in microseconds since the epoch,
+    /// 0 when none was sent
+    #[zbus(property, name = "LastPollUSec")]
+    fn last_poll_usec(&self) -> u64 {
+        self.status()
+            .last_poll
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_micros() as u64)
+    }
+
+    /// Last measured offset in microseconds
+    #[zbus(property, name = "OffsetUSec")]
+    fn offset_usec(&self) -> i64 {
+        self.status()
+            .last_result
+            .map_or(0, |result| result.offset())
+    }
+
+    /// Last measured roundtrip in microseconds
+    #[zbus(property, name = "DelayUSec")]
+    fn delay_usec(&self) -> u64 {
+        self.status()
+            .last_result
+            .map_or(0, |result| result.roundtrip())
+    }
+
+    /// Jitter of the offsets in microseconds
+    #[zbus(property, name = "JitterUSec")]
+    fn jitter_usec(&self) -> u64 {
+        self.status().jitter.unwrap_or(0)
+    }
+
+    /// Reachability register of the server
+    #[zbus(property)]
+    fn reach(&self) -> u8 {
+        self.status().reach.bits()
+    }
+
+    /// Whether the clock is synchronized
+    #[zbus(property, name = "NTPSynchronized")]
+    fn ntp_synchronized(&self) -> bool {
+        self.status().synchronized
+    }
+}
+
+/// Serve `service` on the system bus, until the returned connection
+/// is dropped
+pub fn serve_system(
+    service: Arc<Mutex<SyncService>>,
+) -> io::Result<Connection> {
+    serve(connection::Builder::system().map_err(to_io)?, service)
+}
+
+/// Serve `service` on the session bus, until the returned connection
+/// is dropped
+pub fn serve_session(
+    service: Arc<Mutex<SyncService>>,
+) -> io::Result<Connection> {
+    serve(connection::Builder::session().map_err(to_io)?, service)
+}
+
+fn serve(
+    builder: connection::Builder<'_>,
+    service: Arc<Mutex<SyncService>>,
+) -> io::Result<Connection> {
+    builder
+        .name(BUS_NAME)
+        .and_then(|builder| {
+            builder.serve_at(OBJECT_PATH, SyncInterface { service })
+        })
+        .and_then(|builder| builder.build())
+        .map_err(to_io)
+}
+
+/// Returns the properties of the service served on `connection` as
+/// name and value pairs, formatted as `timedatectl show-timesync` does
+pub fn query_status(
+    connection: &Connection,
+) -> io::Result<Vec<(String, String)>> {
+    let proxy = PropertiesProxy::builder(connection)
+        .destination(BUS_NAME)
+        .and_then(|builder| builder.path(OBJECT_PATH))
+        .and_then(|builder| builder.build())
+        .map_err(to_io)?;
+    let interface = InterfaceName::from_static_str_unchecked(BUS_NAME);
+    let values = proxy.get_all(interface).map_err(|err| to_io(err.into()))?;
+
+    Ok(PROPERTIES
+        .iter()
+        .filter_map(|name| {
+            let value = match &**values.get(*name)? {
+                Value::Bool(true) => "yes".to_string(),
+                Value::Bool(false) => "no".to_string(),
+                Value::U8(reach) => format!("{:o}", reach),
+                Value::U64(value) => value.to_string(),
+                Value::I64(value) => value.to_string(),
+                Value::Str(value) => value.to_string(),
+                value => value.to_string(),
+            };
+
+            Some((name.to_string(), value))
+        })
+        .collect())
+}
@@ -0,0 +1,120 @@
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::{NtpResult, NtpTimestamp, SourceCheck};
+
+/// Time spent in the phases of a request
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Timings {
+    /// Server name resolution, zero when served from the cache
+    pub dns: Duration,
+    /// Socket creation and request sending
+    pub send: Duration,
+    /// Wait for the response
+    pub wait: Duration,
+    /// Response parsing and validation
+    pub processing: Duration,
+    /// Attempts on other addresses before the one that answered
+    pub retries: Duration,
+    /// Number of requests sent before the one that was answered
+    pub retransmissions: u32,
+    /// Timeout the attempts, sending and wait included, had to fit in
+    pub timeout: Duration,
+}
+
+impl Timings {
+    /// Returns the share of the timeout consumed by the attempts, from
+    /// the first request to the response, 1.0 meaning all of it
+    pub fn budget_used(&self) -> f64 {
+        if self.timeout.is_zero() {
+            return 0.0;
+        }
+
+        let used = self.retries + self.send + self.wait;
+
+        used.as_secs_f64() / self.timeout.as_secs_f64()
+    }
+}
+
+/// Raw timestamps of a request, as used to compute offset and delay
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Timestamps {
+    /// T1, client transmit time
+    pub t1: NtpTimestamp,
+    /// T2, server receive time
+    pub t2: NtpTimestamp,
+    /// T3, server transmit time
+    pub t3: NtpTimestamp,
+    /// T4, client receive time
+    pub t4: NtpTimestamp,
+}
+
+impl Timestamps {
+    /// Returns the timestamps as system times, in the T1-T4 order
+    pub fn system_times(&self) -> [SystemTime; 4] {
+        [self.t1, self.t2, self.t3, self.t4].map(NtpTimestamp::to_system_time)
+    }
+}
+
+/// SNTP request result with details about the exchange
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DetailedResult {
+    /// Result of the request
+    pub result: NtpResult,
+    /// Address of the server that answered
+    pub server: SocketAddr,
+    /// Time spent in the phases of the request
+    pub timings: Timings,
+    /// Raw timestamps of the exchange
+    pub timestamps: Timestamps,
+    /// Policy the response source was checked with
+    pub source_check: SourceCheck,
+}
+
+impl DetailedResult {
+    /// Returns the result of the request
+    pub fn result(&self) -> &NtpResult {
+        &self.result
+    }
+
+    /// Returns the address of the server that answered
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Returns the time spent in the phases of the request
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// Returns the raw timestamps of the exchange
+    pub fn timestamps(&self) -> Timestamps {
+        self.timestamps
+    }
+
+    /// Returns the policy the response source was checked with
+    pub fn source_check(&self) -> SourceCheck {
+        self.source_check
+    }
+}
+
+impl From<DetailedResult> for NtpResult {
+    fn from(val: DetailedResult) -> Self {
+        val.result
+    }
+}
+
+impl Debug for DetailedResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DetailedResult")
+            .field("result", &self.result)
+            .field("server", &self.server)
+            .field("timings", &self.timings)
+            .field("timestamps", &self.timestamps)
+            .field("source_check", &self.source_check)
+            .finish()
+    }
+}
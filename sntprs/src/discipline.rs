@@ -0,0 +1,337 @@
+//! Local clock frequency discipline
+//!
+//! Instead of repeatedly stepping or slewing the clock phase, the measured
+//! offsets are used to estimate the drift of the local oscillator, which is
+//! then compensated by adjusting the kernel clock frequency.
+//!
+//! On devices whose crystal drift follows the temperature, a sensor can
+//! be read along with every sample: once enough samples span at least
+//! 2 degrees, the drift is predicted from a linear model of the
+//! frequency against the temperature
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::Instant;
+
+use log::debug;
+
+#[cfg(not(feature = "measure-only"))]
+use crate::utils;
+
+/// Maximum frequency correction accepted by the kernel, in PPM
+pub const MAX_FREQUENCY_PPM: f64 = 500.0;
+
+const DEFAULT_CAPACITY: usize = 8;
+const USEC_IN_SEC: f64 = 1_000_000.0;
+/// Temperature span, in degrees, below which the sensor noise would
+/// dominate the fitted coefficient
+const MIN_TEMPERATURE_SPAN: f64 = 2.0;
+/// Consecutive sample pairs needed to fit the temperature model
+const MIN_TEMPERATURE_PAIRS: usize = 4;
+
+/// Reads a temperature, e.g. of the SoC in degrees Celsius
+pub type TemperatureSensor = Box<dyn Fn() -> Option<f64> + Send>;
+
+/// Frequency discipline state for the local clock
+pub struct Discipline {
+    samples: VecDeque<(Instant, i64, Option<f64>)>,
+    capacity: usize,
+    frequency: f64,
+    sensor: Option<TemperatureSensor>,
+}
+
+impl Discipline {
+    /// Create new discipline keeping up to 8 offset samples
+    pub fn new() -> Self {
+        Discipline::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Create new discipline keeping up to `capacity` offset samples
+    pub fn with_capacity(capacity: usize) -> Self {
+        Discipline {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2),
+            frequency: 0.0,
+            sensor: None,
+        }
+    }
+
+    /// Read `sensor` along with every sample to compensate the drift
+    /// for the temperature
+    pub fn temperature<F>(mut self, sensor: F) -> Self
+    where
+        F: Fn() -> Option<f64> + Send + 'static,
+    {
+        self.sensor = Some(Box::new(sensor));
+        self
+    }
+
+    /// Record a clock offset (in microseconds) measured right now
+    pub fn add_sample(&mut self, offset: i64) {
+        self.add_sample_at(Instant::now(), offset);
+    }
+
+    /// Record a clock offset (in microseconds) measured at the given instant
+    pub fn add_sample_at(&mut self, at: Instant, offset: i64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        let temperature = self.read_temperature();
+
+        self.samples.push_back((at, offset, temperature));
+    }
+
+    fn read_temperature(&self) -> Option<f64> {
+        self.sensor.as_ref().and_then(|sensor| sensor())
+    }
+
+    /// Returns the residual drift of the local clock in PPM, estimated as
+    /// the least squares slope of the recorded offsets, or predicted for
+    /// the current temperature when a temperature model is available.
+    /// A positive value means the local clock runs slow
+    pub fn drift(&self) -> Option<f64> {
+        if let Some((base, coefficient)) = self.temperature_model() {
+            let temperature = self
+                .read_temperature()
+                .or_else(|| self.samples.back().and_then(|s| s.2));
+
+            if let Some(temperature) = temperature {
+                return Some(base + coefficient * temperature);
+            }
+        }
+
+        let (first, _, _) = *self.samples.front()?;
+        let points = self.samples.iter().map(|(at, offset, _)| {
+            let x = at.duration_since(first).as_secs_f64();
+            let y = *offset as f64 / USEC_IN_SEC;
+
+            (x, y)
+        });
+
+        least_squares(points).map(|(slope, _)| slope * USEC_IN_SEC)
+    }
+
+    /// Returns the drift in PPM at 0 degrees and its change in PPM per
+    /// degree, fitted on the drift between consecutive samples against
+    /// their mean temperature, or `None` until four pairs of samples
+    /// span at least 2 degrees
+    pub fn temperature_model(&self) -> Option<(f64, f64)> {
+        let pairs = self.samples.iter().zip(self.samples.iter().skip(1));
+        let points: Vec<(f64, f64)> = pairs
+            .filter_map(|((at_a, offset_a, a), (at_b, offset_b, b))| {
+                let secs = at_b.duration_since(*at_a).as_secs_f64();
+
+                if secs == 0.0 {
+                    return None;
+                }
+
+                let drift = (offset_b - offset_a) as f64 / secs;
+
+                Some(((a.as_ref()? + b.as_ref()?) / 2.0, drift))
+            })
+            .collect();
+
+        let (min, max) = points.iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(min, max), &(temperature, _)| {
+                (min.min(temperature), max.max(temperature))
+            },
+        );
+
+        if points.len() < MIN_TEMPERATURE_PAIRS
+            || max - min < MIN_TEMPERATURE_SPAN
+        {
+            return None;
+        }
+
+        least_squares(points.into_iter()).map(|(slope, base)| (base, slope))
+    }
+
+    /// Discard the recorded samples, e.g. after the system resumed from
+    /// suspension or the clock was stepped
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Returns the frequency correction applied by the last update, in PPM
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Returns the frequency correction the recorded samples call for,
+    /// in PPM, limited to the range accepted by the kernel
+    pub fn target_frequency(&self) -> Option<f64> {
+        self.drift().map(|drift| {
+            (self.frequency + drift)
+                .clamp(-MAX_FREQUENCY_PPM, MAX_FREQUENCY_PPM)
+        })
+    }
+
+    /// Apply the estimated drift to the kernel clock frequency, on top of
+    /// the correction the kernel currently applies.
+    /// Returns the new frequency correction in PPM, or `None` if not enough
+    /// samples have been recorded yet.
+    /// Recorded samples are discarded once the new frequency is applied,
+    /// since they were measured with the previous one
+    #[cfg(not(feature = "measure-only"))]
+    pub fn update(&mut self) -> io::Result<Option<f64>> {
+        if self.drift().is_some() {
+            // The correction may have been left by a previous run or
+            // changed by another daemon
+            self.frequency = utils::current_frequency()?;
+        }
+
+        self.update_with(utils::adjust_frequency)
+    }
+
+    /// Same as `Discipline::update`, applying the new frequency
+    /// correction with `adjust` instead of the kernel clock, e.g. to a
+    /// simulated clock. Still available in `measure-only` builds
+    pub fn update_with<F>(&mut self, adjust: F) -> io::Result<Option<f64>>
+    where
+        F: FnOnce(f64) -> io::Result<()>,
+    {
+        let frequency = match self.target_frequency() {
+            Some(frequency) => frequency,
+            None => return Ok(None),
+        };
+
+        debug!(
+            "Frequency correction: {:.3} ppm -> {:.3} ppm",
+            self.frequency, frequency
+        );
+        adjust(frequency)?;
+        self.frequency = frequency;
+        self.samples.clear();
+
+        Ok(Some(frequency))
+    }
+}
+
+/// Returns the slope and intercept of the least squares line through
+/// `points`, or `None` if they share the same abscissa
+fn least_squares<I>(points: I) -> Option<(f64, f64)>
+where
+    I: Iterator<Item = (f64, f64)> + Clone,
+{
+    let n = points.clone().count() as f64;
+    let (sum_x, sum_y) = points
+        .clone()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+    let (cov, var) = points.fold((0.0, 0.0), |(cov, var), (x, y)| {
+        let dx = x - mean_x;
+
+        (cov + dx * (y - mean_y), var + dx * dx)
+    });
+
+    if n < 2.0 || var == 0.0 {
+        return None;
+    }
+
+    let slope = cov / var;
+
+    Some((slope, mean_y - slope * mean_x))
+}
+
+impl Default for Discipline {
+    fn default() -> Self {
+        Discipline::new()
+    }
+}
+
+#[cfg(test)]
+mod discipline_tests {
+    use crate::discipline::{Discipline, MAX_FREQUENCY_PPM};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_drift_estimation() {
+        let mut discipline = Discipline::new();
+        let start = Instant::now();
+
+        assert!(discipline.drift().is_none());
+
+        // 10 us every 1 s, i.e. the local clock is 10 PPM slow
+        for i in 0..4 {
+            discipline
+                .add_sample_at(start + Duration::from_secs(i), 10 * i as i64);
+        }
+
+        let drift = discipline.drift().unwrap();
+
+        assert!((drift - 10.0).abs() < 1e-6);
+        assert!((discipline.target_frequency().unwrap() - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_frequency_limit() {
+        let mut discipline = Discipline::with_capacity(2);
+        let start = Instant::now();
+
+        discipline.add_sample_at(start, 0);
+        discipline.add_sample_at(start + Duration::from_secs(1), -1_000);
+        discipline.add_sample_at(start + Duration::from_secs(2), -3_000);
+
+        assert!((discipline.drift().unwrap() + 2_000.0).abs() < 1e-6);
+        assert_eq!(Some(-MAX_FREQUENCY_PPM), discipline.target_frequency());
+    }
+
+    #[test]
+    fn test_temperature_compensation() {
+        let temperature = Arc::new(Mutex::new(20.0));
+        let sensor = temperature.clone();
+        let mut discipline = Discipline::new()
+            .temperature(move || Some(*sensor.lock().unwrap()));
+        let start = Instant::now();
+        let mut offset = 0.0;
+
+        assert!(discipline.temperature_model().is_none());
+
+        // 2 PPM at 0 degrees, plus 0.5 PPM per degree
+        for (i, celsius) in [20.0, 22.0, 24.0, 26.0, 24.0].iter().enumerate() {
+            let previous = *temperature.lock().unwrap();
+
+            offset += 10.0 * (2.0 + 0.5 * (previous + celsius) / 2.0);
+            *temperature.lock().unwrap() = *celsius;
+            discipline.add_sample_at(
+                start + Duration::from_secs(10 * i as u64),
+                offset.round() as i64,
+            );
+        }
+
+        let (base, coefficient) = discipline.temperature_model().unwrap();
+
+        assert!((base - 2.0).abs() < 0.1);
+        assert!((coefficient - 0.5).abs() < 0.01);
+
+        *temperature.lock().unwrap() = 30.0;
+
+        assert!((discipline.drift().unwrap() - 17.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_temperature_noise() {
+        let temperature = Arc::new(Mutex::new(20.0));
+        let sensor = temperature.clone();
+        let mut discipline = Discipline::new()
+            .temperature(move || Some(*sensor.lock().unwrap()));
+        let start = Instant::now();
+
+        // 10 PPM with a few us of jitter, the sensor noise only
+        let noise = [(20.0, 0), (20.4, 3), (19.7, -2), (20.2, 4), (19.9, -3)];
+
+        for (i, (celsius, jitter)) in noise.iter().enumerate() {
+            *temperature.lock().unwrap() = *celsius;
+            discipline.add_sample_at(
+                start + Duration::from_secs(10 * i as u64),
+                100 * i as i64 + jitter,
+            );
+        }
+
+        assert!(discipline.temperature_model().is_none());
+        assert!((discipline.drift().unwrap() - 10.0).abs() < 1.0);
+    }
+}
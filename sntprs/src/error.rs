@@ -0,0 +1,179 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+
+use crate::{Reason, Violation};
+
+/// Errors detected by the client itself, carried as the inner error
+/// of the returned [`io::Error`]s.
+/// They hold no heap data, so failing requests allocate only when
+/// converted to an [`io::Error`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Error {
+    /// The server port does not fit in 16 bits
+    InvalidPort(u32),
+    /// No address accepted the request
+    NotResponding,
+    /// The server name did not resolve in time
+    ResolveTimeout,
+    /// Binding the fixed source port requires elevated privileges, with
+    /// the error code the system reported
+    PrivilegedPort { port: u16, os_error: Option<i32> },
+    /// The response came from another address or port
+    AddressMismatch {
+        expected: SocketAddr,
+        received: SocketAddr,
+    },
+    /// The response size is neither a bare packet nor one with a MAC
+    PacketSize(usize),
+    /// The response failed a sanity check
+    Validation(Violation),
+    /// Another process holds the clock lock
+    Busy,
+}
+
+impl Error {
+    /// Returns the kind of the [`io::Error`] the error is converted to
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::InvalidPort(_) => io::ErrorKind::InvalidInput,
+            Error::NotResponding => io::ErrorKind::AddrNotAvailable,
+            Error::ResolveTimeout => io::ErrorKind::TimedOut,
+            Error::PrivilegedPort { .. } => io::ErrorKind::PermissionDenied,
+            Error::Busy => io::ErrorKind::ResourceBusy,
+            Error::AddressMismatch { .. }
+            | Error::PacketSize(_)
+            | Error::Validation(_) => io::ErrorKind::InvalidData,
+        }
+    }
+
+    /// Returns the category of a rejected response, or `None` for
+    /// errors unrelated to the response content
+    pub fn reason(&self) -> Option<Reason> {
+        match self {
+            Error::AddressMismatch { .. } => Some(Reason::Unsolicited),
+            Error::PacketSize(_) => Some(Reason::Malformed),
+            Error::Validation(violation) => Some(violation.reason()),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the same request may succeed later: timeouts and
+    /// bad answers are worth another try, while invalid settings or a
+    /// kiss-o'-death are not
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::InvalidPort(_) | Error::PrivilegedPort { .. } => false,
+            _ => self.reason() != Some(Reason::KissOfDeath),
+        }
+    }
+
+    /// Returns whether the server answered wrongly, so another server
+    /// should be tried. Unsolicited responses are not blamed on the
+    /// server, as anybody on the path may send them
+    pub fn is_server_fault(&self) -> bool {
+        !matches!(self.reason(), None | Some(Reason::Unsolicited))
+    }
+
+    /// Returns the client error carried by `err`, if any
+    pub fn from_io(err: &io::Error) -> Option<Error> {
+        err.get_ref()?.downcast_ref::<Error>().copied()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidPort(port) => write!(f, "Invalid port {}", port),
+            Error::NotResponding => write!(f, "SNTP servers not responding"),
+            Error::ResolveTimeout => {
+                write!(f, "Server name resolution timed out")
+            }
+            Error::PrivilegedPort { port, os_error } => {
+                write!(
+                    f,
+                    "Binding source port {} requires elevated privileges",
+                    port
+                )?;
+
+                match os_error {
+                    Some(code) => {
+                        write!(f, ": {}", io::Error::from_raw_os_error(*code))
+                    }
+                    None => Ok(()),
+                }
+            }
+            Error::AddressMismatch { expected, received } => write!(
+                f,
+                "SNTP response port / address mismatch: expected {}, got {}",
+                expected, received
+            ),
+            Error::PacketSize(size) => {
+                write!(f, "Incorrect NTP packet size read: {}", size)
+            }
+            Error::Validation(violation) => write!(f, "{}", violation),
+            Error::Busy => write!(f, "Clock being set by another process"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use crate::proto::KISS_DENY;
+    use crate::{Error, Reason, Violation};
+    use std::io;
+
+    #[test]
+    fn test_error_round_trip() {
+        let err = Error::Validation(Violation::KissOfDeath(KISS_DENY));
+        let io_err = io::Error::from(err);
+
+        assert_eq!(io::ErrorKind::InvalidData, io_err.kind());
+        assert_eq!(Some(err), Error::from_io(&io_err));
+        assert_eq!(Some(Reason::KissOfDeath), err.reason());
+        assert_eq!("Kiss-o'-death: DENY", io_err.to_string());
+        assert_eq!(None, Error::from_io(&io::Error::other("other")));
+    }
+
+    #[test]
+    fn test_error_classification() {
+        let kod = Error::Validation(Violation::KissOfDeath(KISS_DENY));
+        let unsync = Error::Validation(Violation::Stratum(16));
+
+        assert!(!kod.is_retryable());
+        assert!(kod.is_server_fault());
+        assert!(unsync.is_retryable());
+        assert!(unsync.is_server_fault());
+        assert!(Error::NotResponding.is_retryable());
+        assert!(!Error::NotResponding.is_server_fault());
+        assert!(!Error::InvalidPort(65536).is_retryable());
+        assert!(!Error::InvalidPort(65536).is_server_fault());
+        assert!(Error::PacketSize(12).is_server_fault());
+    }
+
+    #[test]
+    fn test_privileged_port() {
+        let err = Error::PrivilegedPort {
+            port: 123,
+            os_error: Some(1),
+        };
+
+        assert!(!err.is_retryable());
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert_eq!(
+            format!(
+                "Binding source port 123 requires elevated privileges: {}",
+                io::Error::from_raw_os_error(1)
+            ),
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Sample export for offline analysis
+//!
+//! Collected [`Sample`]s are written as CSV, with a header row and
+//! RFC 3339 timestamps, or as Parquet when the `parquet` feature is
+//! enabled, both readable by pandas or duckdb as is
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::time::UNIX_EPOCH;
+//!
+//! use sntprs::export::write_csv;
+//! use sntprs::store::{FileStore, SampleStore};
+//!
+//! let store = FileStore::open("samples.tsv").unwrap();
+//! let samples = store.samples(UNIX_EPOCH).unwrap();
+//!
+//! write_csv(File::create("samples.csv").unwrap(), &samples).unwrap();
+//! ```
+
+use std::io;
+use std::io::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::store::Sample;
+
+const CSV_HEADER: &str = "timestamp,server,offset_us,delay_us,stratum";
+
+/// Writer of samples in CSV format
+pub struct CsvWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Create new writer, writing the header row right away
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", CSV_HEADER)?;
+        Ok(CsvWriter { writer })
+    }
+
+    /// Write a sample as a row
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        let time = DateTime::<Utc>::from(sample.time)
+            .to_rfc3339_opts(SecondsFormat::Micros, true);
+
+        writeln!(
+            self.writer,
+            "{},{},{},{},{}",
+            time,
+            escape(&sample.server),
+            sample.offset,
+            sample.roundtrip,
+            sample.stratum
+        )
+    }
+
+    /// Flush and return the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Write `samples` to `writer` in CSV format
+pub fn write_csv<W: Write>(writer: W, samples: &[Sample]) -> io::Result<()> {
+    let mut csv = CsvWriter::new(writer)?;
+
+    for sample in samples {
+        csv.write(sample)?;
+    }
+
+    csv.into_inner().map(|_| ())
+}
+
+/// Quote a field holding separators or quotes
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub use self::parquet_export::write_parquet;
+
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use std::io;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+    use parquet::errors::ParquetError;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    use crate::store::Sample;
+
+    const SCHEMA: &str = "
+        message sample {
+            REQUIRED INT64 timestamp (TIMESTAMP(MICROS, true));
+            REQUIRED BYTE_ARRAY server (UTF8);
+            REQUIRED INT64 offset_us;
+            REQUIRED INT64 delay_us;
+            REQUIRED INT32 stratum (INTEGER(8, false));
+        }
+    ";
+
+    fn to_io(err: ParquetError) -> io::Error {
+        io::Error::other(err)
+    }
+
+    /// Write `samples` to `writer` in Parquet format, as a single
+    /// row group with the same columns as the CSV export
+    pub fn write_parquet<W>(writer: W, samples: &[Sample]) -> io::Result<()>
+    where
+        W: Write + Send,
+    {
+        let schema = Arc::new(parse_message_type(SCHEMA).map_err(to_io)?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut file =
+            SerializedFileWriter::new(writer, schema, props).map_err(to_io)?;
+        let mut row_group = file.next_row_group().map_err(to_io)?;
+        let mut idx = 0;
+
+        while let Some(mut column) = row_group.next_column().map_err(to_io)? {
+            match idx {
+                0 => {
+                    let values: Vec<i64> = samples
+                        .iter()
+                        .map(|s| {
+                            let since_epoch = s
+                                .time
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default();
+
+                            since_epoch.as_micros() as i64
+                        })
+                        .collect();
+
+                    column.typed::<Int64Type>().write_batch(&values, None, None)
+                }
+                1 => {
+                    let values: Vec<ByteArray> = samples
+                        .iter()
+                        .map(|s| ByteArray::from(s.server.as_str()))
+                        .collect();
+
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, None, None)
+                }
+                2 => {
+                    let values: Vec<i64> =
+                        samples.iter().map(|s| s.offset).collect();
+
+                    column.typed::<Int64Type>().write_batch(&values, None, None)
+                }
+                3 => {
+                    let values: Vec<i64> =
+                        samples.iter().map(|s| s.roundtrip as i64).collect();
+
+                    column.typed::<Int64Type>().write_batch(&values, None, None)
+                }
+                _ => {
+                    let values: Vec<i32> =
+                        samples.iter().map(|s| i32::from(s.stratum)).collect();
+
+                    column.typed::<Int32Type>().write_batch(&values, None, None)
+                }
+            }
+            .map_err(to_io)?;
+
+            column.close().map_err(to_io)?;
+            idx += 1;
+        }
+
+        row_group.close().map_err(to_io)?;
+        file.close().map(|_| ()).map_err(to_io)
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use crate::export::write_csv;
+    use crate::store::Sample;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_csv_export() {
+        let sample = Sample {
+            time: UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000),
+            server: "a,b".to_string(),
+            offset: -42,
+            roundtrip: 1_500,
+            stratum: 2,
+        };
+        let mut out = Vec::new();
+
+        write_csv(&mut out, &[sample]).unwrap();
+
+        assert_eq!(
+            "timestamp,server,offset_us,delay_us,stratum\n\
+             2023-11-14T22:13:20.250000Z,\"a,b\",-42,1500,2\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_export() {
+        use crate::export::write_parquet;
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+        use std::fs::File;
+
+        let samples = [
+            Sample {
+                time: UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000),
+                server: "a,b".to_string(),
+                offset: -42,
+                roundtrip: 1_500,
+                stratum: 2,
+            },
+            Sample {
+                time: UNIX_EPOCH + Duration::from_secs(1_700_000_064),
+                server: "time.example.com".to_string(),
+                offset: 17,
+                roundtrip: 900,
+                stratum: 1,
+            },
+        ];
+        let path = std::env::temp_dir()
+            .join(format!("sntprs-export-{}.parquet", std::process::id()));
+
+        write_parquet(File::create(&path).unwrap(), &samples).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap());
+        let rows: Vec<_> = reader
+            .unwrap()
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(samples.len(), rows.len());
+
+        for (sample, row) in samples.iter().zip(&rows) {
+            let since_epoch = sample.time.duration_since(UNIX_EPOCH).unwrap();
+
+            assert_eq!(
+                since_epoch.as_micros() as i64,
+                row.get_timestamp_micros(0).unwrap()
+            );
+            assert_eq!(&sample.server, row.get_string(1).unwrap());
+            assert_eq!(sample.offset, row.get_long(2).unwrap());
+            assert_eq!(sample.roundtrip as i64, row.get_long(3).unwrap());
+            assert_eq!(sample.stratum, row.get_ubyte(4).unwrap());
+        }
+    }
+}
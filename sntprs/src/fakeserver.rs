@@ -0,0 +1,94 @@
+//! In-memory NTP server shared by the unit tests
+
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::proto::{Mode, KISS_DENY};
+use crate::transport::{Exchange, Transport};
+use crate::{NtpPacket, RawPacket};
+
+/// Transport answering every request at once with a stratum 1 response,
+/// except from the silent addresses, never answering, and the kissing
+/// ones, sending a DENY kiss-o'-death. Counts the exchanges
+#[derive(Clone, Default)]
+pub(crate) struct FakeServer {
+    time: Option<u64>,
+    silent: Vec<SocketAddr>,
+    kissing: Vec<SocketAddr>,
+    exchanges: Arc<AtomicUsize>,
+}
+
+impl FakeServer {
+    /// Create new server answering with its own packet creation time
+    pub(crate) fn new() -> Self {
+        FakeServer::default()
+    }
+
+    /// Answer with the given receive and transmit timestamps
+    pub(crate) fn time(mut self, time: u64) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Never answer from `addr`, waiting for the whole timeout
+    pub(crate) fn silent(mut self, addr: SocketAddr) -> Self {
+        self.silent.push(addr);
+        self
+    }
+
+    /// Send a kiss-o'-death from `addr`
+    pub(crate) fn kissing(mut self, addr: SocketAddr) -> Self {
+        self.kissing.push(addr);
+        self
+    }
+
+    /// Returns the number of exchanges performed, by all the clones
+    pub(crate) fn exchanges(&self) -> usize {
+        self.exchanges.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for FakeServer {
+    fn exchange(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Exchange> {
+        self.exchanges.fetch_add(1, Ordering::SeqCst);
+
+        if self.silent.contains(&dest[0]) {
+            thread::sleep(timeout);
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let req = NtpPacket::try_from(request).unwrap();
+        let mut resp = NtpPacket::new();
+
+        resp.set_mode(Mode::Server);
+        if self.kissing.contains(&dest[0]) {
+            resp.stratum = 0;
+            resp.ref_id = KISS_DENY;
+        } else {
+            resp.stratum = 1;
+        }
+        resp.origin_timestamp = req.tx_timestamp;
+        resp.tx_timestamp = self.time.unwrap_or(resp.tx_timestamp);
+        resp.recv_timestamp = resp.tx_timestamp;
+        response[..48].copy_from_slice(&RawPacket::from(&resp));
+
+        Ok(Exchange {
+            server: dest[0],
+            local: dest[0],
+            source: dest[0],
+            size: 48,
+            sent: Instant::now(),
+        })
+    }
+}
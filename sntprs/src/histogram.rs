@@ -0,0 +1,169 @@
+//! Value distribution over long periods
+//!
+//! [`Histogram`] counts values in log-linear buckets, as HDR histograms
+//! do: values below 64 are exact, larger ones share buckets spanning
+//! about 3% of their value. Memory stays bounded however many values are
+//! recorded, so a poller can keep the distribution of its offsets and
+//! roundtrips for months and answer quantile queries such as "99% of the
+//! offsets were within 2 ms"
+
+const PRECISION_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << PRECISION_BITS;
+const EXACT_BUCKETS: u64 = 2 * SUB_BUCKETS;
+
+/// Histogram of non negative values with a bounded relative error
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Histogram {
+    /// Create new empty histogram
+    pub fn new() -> Self {
+        Histogram::default()
+    }
+
+    /// Count one occurrence of `value`
+    pub fn record(&mut self, value: u64) {
+        let idx = index(value);
+
+        if idx >= self.counts.len() {
+            self.counts.resize(idx + 1, 0);
+        }
+
+        self.counts[idx] += 1;
+        self.total += 1;
+        self.max = self.max.max(value);
+    }
+
+    /// Returns the number of recorded values
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the largest recorded value
+    pub fn max(&self) -> Option<u64> {
+        if self.total == 0 {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
+
+    /// Returns the value below which the fraction `q` of the recorded
+    /// values fall, rounded up to the end of its bucket
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let rank = (q.clamp(0.0, 1.0) * self.total as f64).ceil().max(1.0);
+        let mut seen = 0;
+
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+
+            if seen as f64 >= rank {
+                return Some(upper_bound(idx).min(self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+
+    /// Returns the fraction of the recorded values up to `value`
+    pub fn fraction_below(&self, value: u64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+
+        let last = index(value);
+        let below: u64 = self.counts.iter().take(last + 1).sum();
+
+        below as f64 / self.total as f64
+    }
+
+    /// Returns the non empty buckets as their largest value and count,
+    /// in increasing order, for export
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(idx, &count)| (upper_bound(idx), count))
+    }
+
+    /// Add the values recorded by `other`
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+}
+
+fn index(value: u64) -> usize {
+    if value < EXACT_BUCKETS {
+        return value as usize;
+    }
+
+    let exp = u64::from(63 - value.leading_zeros());
+    let mantissa = (value >> (exp - u64::from(PRECISION_BITS))) - SUB_BUCKETS;
+
+    (EXACT_BUCKETS
+        + (exp - u64::from(PRECISION_BITS) - 1) * SUB_BUCKETS
+        + mantissa) as usize
+}
+
+fn upper_bound(idx: usize) -> u64 {
+    let idx = idx as u64;
+
+    if idx < EXACT_BUCKETS {
+        return idx;
+    }
+
+    let shift = (idx - EXACT_BUCKETS) / SUB_BUCKETS + 1;
+    let mantissa = (idx - EXACT_BUCKETS) % SUB_BUCKETS + SUB_BUCKETS;
+    let end = u128::from(mantissa + 1) << shift;
+
+    (end - 1).min(u128::from(u64::MAX)) as u64
+}
+
+#[cfg(test)]
+mod histogram_tests {
+    use crate::histogram::{index, upper_bound, Histogram};
+
+    #[test]
+    fn test_quantiles() {
+        let mut histogram = Histogram::new();
+
+        assert_eq!(None, histogram.quantile(0.5));
+
+        for value in 1..=10_000 {
+            histogram.record(value);
+        }
+
+        let median = histogram.quantile(0.5).unwrap();
+        let p99 = histogram.quantile(0.99).unwrap();
+
+        assert!((5_000..=5_000 * 103 / 100).contains(&median));
+        assert!((9_900..=9_900 * 103 / 100).contains(&p99));
+        assert_eq!(Some(10_000), histogram.quantile(1.0));
+        assert_eq!(Some(1), histogram.quantile(0.0));
+        assert!((histogram.fraction_below(63) - 0.0063).abs() < 1e-9);
+        assert_eq!(10_000, histogram.buckets().map(|(_, c)| c).sum::<u64>());
+
+        for value in [64, 127, 128, 1 << 40, u64::MAX] {
+            assert!(upper_bound(index(value)) >= value);
+            assert!(upper_bound(index(value) - 1) < value);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Holdover estimation
+//!
+//! While the NTP servers are unreachable, the clock offset keeps being
+//! estimated from the last successful synchronization and the known drift
+//! of the local clock, together with an uncertainty bound growing with the
+//! time elapsed since that synchronization
+
+use std::time::{Duration, Instant};
+
+/// Default frequency tolerance of the local clock, in PPM
+pub const DEFAULT_TOLERANCE_PPM: f64 = 15.0;
+
+/// Estimated clock state during holdover
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HoldoverEstimate {
+    /// Estimated current clock offset in microseconds
+    pub offset: i64,
+    /// Maximum error of the estimated offset in microseconds
+    pub uncertainty: u64,
+    /// Time elapsed since the last successful synchronization
+    pub elapsed: Duration,
+}
+
+/// Holdover state of the local clock
+pub struct Holdover {
+    last_sync: Option<(Instant, i64, u64)>,
+    drift: f64,
+    tolerance: f64,
+}
+
+impl Holdover {
+    /// Create new holdover estimator assuming the default clock tolerance
+    pub fn new() -> Self {
+        Holdover::with_tolerance(DEFAULT_TOLERANCE_PPM)
+    }
+
+    /// Create new holdover estimator
+    /// Args:
+    /// * `tolerance` - maximum unaccounted drift of the local clock in PPM
+    pub fn with_tolerance(tolerance: f64) -> Self {
+        Holdover {
+            last_sync: None,
+            drift: 0.0,
+            tolerance: tolerance.abs(),
+        }
+    }
+
+    /// Record a successful synchronization performed right now
+    /// Args:
+    /// * `offset` - measured clock offset in microseconds
+    /// * `error` - maximum error of the measured offset in microseconds
+    /// * `drift` - current drift of the local clock in PPM,
+    ///   as estimated by [`Discipline::drift`](crate::discipline::Discipline::drift)
+    pub fn sync(&mut self, offset: i64, error: u64, drift: f64) {
+        self.sync_at(Instant::now(), offset, error, drift);
+    }
+
+    /// Record a successful synchronization performed at the given instant
+    pub fn sync_at(
+        &mut self,
+        at: Instant,
+        offset: i64,
+        error: u64,
+        drift: f64,
+    ) {
+        self.last_sync = Some((at, offset, error));
+        self.drift = drift;
+    }
+
+    /// Returns the instant of the last successful synchronization
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.last_sync.map(|(at, _, _)| at)
+    }
+
+    /// Returns the clock state estimated for the current instant,
+    /// or `None` if no synchronization happened yet
+    pub fn estimate(&self) -> Option<HoldoverEstimate> {
+        self.estimate_at(Instant::now())
+    }
+
+    /// Returns the clock state estimated for the given instant,
+    /// or `None` if no synchronization happened yet
+    pub fn estimate_at(&self, at: Instant) -> Option<HoldoverEstimate> {
+        let (synced_at, offset, error) = self.last_sync?;
+        let elapsed = at.saturating_duration_since(synced_at);
+        let secs = elapsed.as_secs_f64();
+        let offset = offset as f64 + self.drift * secs;
+        let uncertainty = error as f64 + self.tolerance * secs;
+
+        Some(HoldoverEstimate {
+            offset: offset.round() as i64,
+            uncertainty: uncertainty.ceil() as u64,
+            elapsed,
+        })
+    }
+}
+
+impl Default for Holdover {
+    fn default() -> Self {
+        Holdover::new()
+    }
+}
+
+#[cfg(test)]
+mod holdover_tests {
+    use crate::holdover::Holdover;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_holdover_estimate() {
+        let mut holdover = Holdover::with_tolerance(10.0);
+        let start = Instant::now();
+
+        assert!(holdover.estimate_at(start).is_none());
+
+        holdover.sync_at(start, 100, 50, 2.0);
+
+        let estimate = holdover
+            .estimate_at(start + Duration::from_secs(1_000))
+            .unwrap();
+
+        assert_eq!(2_100, estimate.offset);
+        assert_eq!(10_050, estimate.uncertainty);
+        assert_eq!(Duration::from_secs(1_000), estimate.elapsed);
+    }
+}
@@ -0,0 +1,342 @@
+//! Last known good time
+//!
+//! A correct clock never goes back in time, so a server time earlier
+//! than the last time verified is either an attack rolling the clock
+//! back, e.g. to replay expired certificates, or a server with a wildly
+//! wrong clock. [`LastKnownGood`] keeps the latest verified time in a
+//! file surviving restarts and refuses, or only flags, the results
+//! falling behind it. Once it recorded a time itself, it also refuses
+//! the results running ahead of that time plus the time elapsed since.
+//! Its time also makes a lower bound for
+//! [`NtpClient::plausibility`](crate::NtpClient::plausibility)
+//!
+//! ```rust,no_run
+//! use sntprs::lastgood::LastKnownGood;
+//! use sntprs::NtpClient;
+//!
+//! let mut last_good = LastKnownGood::open("/var/lib/sntp/last-good").unwrap();
+//! let result = NtpClient::new().request("pool.ntp.org", 123).unwrap();
+//!
+//! last_good.verify(&result).unwrap();
+//! ```
+
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::clock::monotonic;
+use crate::NtpResult;
+
+const DEFAULT_TOLERANCE: Duration = Duration::from_secs(60);
+
+/// Error reported when a server time falls behind the last known good
+/// time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rollback {
+    /// Time reported by the server
+    pub time: SystemTime,
+    /// Last known good time
+    pub last_good: SystemTime,
+}
+
+impl Display for Rollback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let behind = self
+            .last_good
+            .duration_since(self.time)
+            .unwrap_or_default()
+            .as_secs();
+
+        write!(
+            f,
+            "Server time {} s behind the last known good time",
+            behind
+        )
+    }
+}
+
+impl std::error::Error for Rollback {}
+
+/// Error reported when a server time runs ahead of the last known good
+/// time plus the time elapsed since it was recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ahead {
+    /// Time reported by the server
+    pub time: SystemTime,
+    /// Latest time expected, tolerance excluded
+    pub latest: SystemTime,
+}
+
+impl Display for Ahead {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ahead = self
+            .time
+            .duration_since(self.latest)
+            .unwrap_or_default()
+            .as_secs();
+
+        write!(
+            f,
+            "Server time {} s ahead of the last known good time",
+            ahead
+        )
+    }
+}
+
+impl std::error::Error for Ahead {}
+
+/// What to do with a result behind, or too far ahead of, the last
+/// known good time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RollbackPolicy {
+    /// Fail with a [`Rollback`] or [`Ahead`] error
+    #[default]
+    Reject,
+    /// Accept the result, reporting it as not verified
+    Flag,
+}
+
+/// Latest verified time, optionally persisted to a file
+pub struct LastKnownGood {
+    path: Option<PathBuf>,
+    time: Option<SystemTime>,
+    /// Monotonic reading when this process recorded the time
+    recorded_at: Option<Duration>,
+    tolerance: Duration,
+    policy: RollbackPolicy,
+}
+
+impl LastKnownGood {
+    /// Create new last known good time kept in memory only
+    pub fn new() -> Self {
+        LastKnownGood {
+            path: None,
+            time: None,
+            recorded_at: None,
+            tolerance: DEFAULT_TOLERANCE,
+            policy: RollbackPolicy::Reject,
+        }
+    }
+
+    /// Load the last known good time stored at `path`, if any, and
+    /// store the later ones there
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let time = match fs::read_to_string(&path) {
+            Ok(content) => {
+                Some(parse_time(content.trim()).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid last known good time: {}", content),
+                    )
+                })?)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(LastKnownGood {
+            path: Some(path),
+            time,
+            ..LastKnownGood::new()
+        })
+    }
+
+    /// Accept server times up to `tolerance` behind the last known good
+    /// time, or ahead of it plus the time elapsed since, 1 minute by
+    /// default
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Select what to do with results behind, or too far ahead of, the
+    /// last known good time. Defaults to [`RollbackPolicy::Reject`]
+    pub fn policy(mut self, policy: RollbackPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the last known good time
+    pub fn time(&self) -> Option<SystemTime> {
+        self.time
+    }
+
+    /// Returns the rollback evidence if `time` falls behind the last
+    /// known good time by more than the tolerance
+    pub fn check(&self, time: SystemTime) -> Option<Rollback> {
+        let last_good = self.time?;
+
+        match last_good.duration_since(time) {
+            Ok(behind) if behind > self.tolerance => {
+                Some(Rollback { time, last_good })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the evidence if `time` runs ahead of the last known good
+    /// time plus the time elapsed since, by more than the tolerance.
+    /// Only bounded once a time was recorded by this instance, as the
+    /// time elapsed while the process was not running is unknown
+    pub fn check_ahead(&self, time: SystemTime) -> Option<Ahead> {
+        let latest = self.time? + monotonic().saturating_sub(self.recorded_at?);
+
+        match time.duration_since(latest) {
+            Ok(ahead) if ahead > self.tolerance => Some(Ahead { time, latest }),
+            _ => None,
+        }
+    }
+
+    /// Record `time` as verified, if later than the last known good time
+    pub fn record(&mut self, time: SystemTime) -> io::Result<()> {
+        if self.time.is_some_and(|last_good| last_good >= time) {
+            return Ok(());
+        }
+
+        self.time = Some(time);
+        self.recorded_at = Some(monotonic());
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let tmp = path.with_extension("tmp");
+
+        fs::write(
+            &tmp,
+            format!(
+                "{}.{:09}\n",
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos()
+            ),
+        )?;
+        fs::rename(tmp, path)
+    }
+
+    /// Forget the last known good time, removing its file, e.g. after
+    /// setting the clock back on purpose
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.time = None;
+        self.recorded_at = None;
+
+        match self.path.as_ref().map(fs::remove_file) {
+            Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the time of `result` against the last known good time,
+    /// recording it when verified. Returns whether it was verified, or
+    /// fails with a [`Rollback`] or [`Ahead`] error of kind
+    /// [`io::ErrorKind::InvalidData`] under [`RollbackPolicy::Reject`]
+    pub fn verify(&mut self, result: &NtpResult) -> io::Result<bool> {
+        let time =
+            UNIX_EPOCH + Duration::new(u64::from(result.sec()), result.nsec());
+        let err: Box<dyn std::error::Error + Send + Sync> =
+            match (self.check(time), self.check_ahead(time)) {
+                (None, None) => {
+                    self.record(time)?;
+                    return Ok(true);
+                }
+                (Some(rollback), _) => Box::new(rollback),
+                (None, Some(ahead)) => Box::new(ahead),
+            };
+
+        match self.policy {
+            RollbackPolicy::Reject => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, err))
+            }
+            RollbackPolicy::Flag => {
+                debug!("{}", err);
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Default for LastKnownGood {
+    fn default() -> Self {
+        LastKnownGood::new()
+    }
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    let (secs, nanos) = value.split_once('.')?;
+
+    Some(UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+#[cfg(test)]
+mod lastgood_tests {
+    use crate::lastgood::{Ahead, LastKnownGood, Rollback, RollbackPolicy};
+    use crate::NtpResult;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_last_known_good() {
+        let path = std::env::temp_dir()
+            .join(format!("sntprs-last-good-{}", std::process::id()));
+        let mut last_good = LastKnownGood::open(&path).unwrap();
+
+        assert!(last_good.verify(&NtpResult::new(2_000, 5, 0, 0)).unwrap());
+        assert!(last_good.verify(&NtpResult::new(1_950, 0, 0, 0)).unwrap());
+
+        let last_good = LastKnownGood::open(&path).unwrap();
+        let time = UNIX_EPOCH + Duration::new(2_000, 5);
+
+        assert_eq!(Some(time), last_good.time());
+
+        let mut last_good = last_good.tolerance(Duration::from_secs(10));
+        let err = last_good.verify(&NtpResult::new(1_950, 0, 0, 0));
+        let rollback = err.unwrap_err().into_inner().unwrap();
+
+        assert_eq!(
+            Rollback {
+                time: UNIX_EPOCH + Duration::from_secs(1_950),
+                last_good: time,
+            },
+            *rollback.downcast::<Rollback>().unwrap()
+        );
+
+        let mut last_good = last_good.policy(RollbackPolicy::Flag);
+
+        assert!(!last_good.verify(&NtpResult::new(1_950, 0, 0, 0)).unwrap());
+
+        last_good.clear().unwrap();
+
+        assert_eq!(None, last_good.time());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_future_time() {
+        let mut last_good =
+            LastKnownGood::new().tolerance(Duration::from_secs(10));
+
+        // Unbounded until a time is recorded
+        assert!(last_good.verify(&NtpResult::new(2_000, 0, 0, 0)).unwrap());
+        assert!(last_good.verify(&NtpResult::new(2_005, 0, 0, 0)).unwrap());
+
+        let err = last_good.verify(&NtpResult::new(3_000, 0, 0, 0));
+        let ahead = err.unwrap_err().into_inner().unwrap();
+        let ahead = *ahead.downcast::<Ahead>().unwrap();
+
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(3_000), ahead.time);
+        assert!(ahead.latest >= UNIX_EPOCH + Duration::from_secs(2_005));
+        assert!(ahead.latest < UNIX_EPOCH + Duration::from_secs(2_006));
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(2_005)),
+            last_good.time()
+        );
+
+        let mut last_good = last_good.policy(RollbackPolicy::Flag);
+
+        assert!(!last_good.verify(&NtpResult::new(3_000, 0, 0, 0)).unwrap());
+    }
+}
@@ -5,13 +5,11 @@
 //!
 //! # Example
 //!
-//! ```rust
-//! use sntpc;
+//! ```rust,no_run
+//! let result = sntprs::request("pool.ntp.org", 123);
 //!
-//! let result = sntpc::request("pool.ntp.org", 123);
-//!
-//! if let Ok(sntpc::NtpResult {
-//!     sec, nsec, roundtrip, offset
+//! if let Ok(sntprs::NtpResult {
+//!     sec, nsec, roundtrip, offset, ..
 //! }) = result {
 //!     println!("NTP server time: {}.{}", sec, nsec);
 //!     println!("Roundtrip time: {}, offset: {}", roundtrip, offset);
@@ -23,53 +21,99 @@
 extern crate arrayref;
 
 
+mod clockverdict;
+mod detailedresult;
+mod error;
+#[cfg(test)]
+mod fakeserver;
+mod ntpclient;
 mod ntppacket;
 mod ntpresult;
-
+mod ntptimestamp;
+mod requestcontext;
+mod resolver;
+mod sampledresult;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod validation;
+
+pub mod batch;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+pub mod broadcast;
+pub mod clock;
+pub mod compliance;
+pub mod config;
+#[cfg(unix)]
+pub mod control;
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+pub mod dbus;
+pub mod discipline;
+pub mod export;
+pub mod histogram;
+pub mod holdover;
+pub mod lastgood;
+pub mod monitor;
+#[cfg(target_os = "linux")]
+pub mod netwatch;
+#[cfg(feature = "nmea")]
+pub mod nmea;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod pool;
+pub mod pps;
+pub mod proto;
+#[cfg(feature = "backoff")]
+pub mod retry;
+pub mod service;
+pub mod source;
+pub mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+pub mod transport;
 pub mod utils;
-
-use crate::ntppacket::RawPacket;
-use crate::ntpresult::NtpResult;
+pub mod v2;
+pub mod vectors;
+pub mod webhook;
+
+use crate::ntppacket::{MAC_SIZES, MAX_MAC_SIZE};
+pub use crate::clockverdict::ClockVerdict;
+pub use crate::detailedresult::{DetailedResult, Timestamps, Timings};
+pub use crate::error::Error;
+pub use crate::ntpclient::{
+    ClientStats, DynNtpClient, ImplausibleTime, NtpClient,
+};
+pub use crate::ntppacket::{NtpPacket, RawPacket, NTP_PACKET_SIZE};
+pub use crate::ntpresult::NtpResult;
+pub use crate::ntptimestamp::{NtpShort, NtpTimestamp};
+pub use crate::requestcontext::RequestContext;
+pub use crate::sampledresult::SampledResult;
+pub use crate::transport::SourcePort;
+pub use crate::validation::{
+    Reason, SourceCheck, ValidationProfile, ValidationReport, Violation,
+};
+use crate::clock::ClockSource;
+use crate::ntptimestamp::{fixed_to_micros, half_sum};
+use crate::proto::NTP_PORT;
+use crate::transport::Transport;
+use crate::validation::validate;
 use log::debug;
 use std::io;
-use std::mem;
-use std::net;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::net::{SocketAddr, UdpSocket};
 use std::str;
+use std::sync::OnceLock;
 use std::time;
 
-use ntppacket::NtpPacket;
-
-const MODE_MASK: u8 = 0b0000_0111;
-const MODE_SHIFT: u8 = 0;
-const VERSION_MASK: u8 = 0b0011_1000;
-const VERSION_SHIFT: u8 = 3;
-const LI_MASK: u8 = 0b1100_0000;
-const LI_SHIFT: u8 = 6;
 const NSEC_IN_SEC: u32 = 1_000_000_000;
-
-
-
-trait NtpNum {
-    type Type;
-
-    fn ntohl(&self) -> Self::Type;
-}
-
-impl NtpNum for u32 {
-    type Type = u32;
-
-    fn ntohl(&self) -> Self::Type {
-        self.to_be()
-    }
-}
-impl NtpNum for u64 {
-    type Type = u64;
-
-    fn ntohl(&self) -> Self::Type {
-        self.to_be()
-    }
-}
+const CHECK_SAMPLES: usize = 3;
+#[cfg(not(feature = "measure-only"))]
+const BOOT_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+#[cfg(not(feature = "measure-only"))]
+const BOOT_ROUNDS: usize = 5;
+#[cfg(not(feature = "measure-only"))]
+const BOOT_RETRY_DELAY: time::Duration = time::Duration::from_secs(1);
 
 /// Send request to a NTP server with the given address
 /// and process the response
@@ -79,128 +123,262 @@ impl NtpNum for u64 {
 ///
 /// # Example
 ///
-/// ```rust
-/// use sntpc;
-///
-/// let result = sntpc::request("time.google.com", 123);
+/// ```rust,no_run
+/// let result = sntprs::request("time.google.com", 123);
 /// // OR
-/// let result = sntpc::request("83.168.200.199", 123);
+/// let result = sntprs::request("83.168.200.199", 123);
 ///
 /// // .. process the result
 /// ```
 pub fn request(pool: &str, port: u32) -> io::Result<NtpResult> {
-    debug!("Pool: {}", pool);
-    let socket = net::UdpSocket::bind("0.0.0.0:0")
-        .expect("Unable to create a UDP socket");
-    let dest = format!("{}:{}", pool, port).to_socket_addrs()?;
-
-    socket
-        .set_read_timeout(Some(time::Duration::new(2, 0)))
-        .expect("Unable to set up socket timeout");
-    let req = NtpPacket::new();
-    let dest = process_request(dest, &req, &socket)?;
-    let mut buf: RawPacket = [0u8; 48];
-    let (response, src) = socket.recv_from(buf.as_mut())?;
-    let recv_timestamp = get_ntp_timestamp();
-    debug!("Response: {}", response);
-
-    if src != dest {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "SNTP response port / address mismatch",
-        ));
-    }
+    NtpClient::new().request(pool, port)
+}
 
-    if response == mem::size_of::<NtpPacket>() {
-        let result = process_response(&req, buf, recv_timestamp);
+/// Perform several spaced exchanges with a NTP server and return
+/// the median offset, smallest delay and offset spread
+///
+/// * `pool` - Server's name or IP address as a string
+/// * `port` - Server's port as an int
+/// * `samples` - Number of exchanges to perform
+/// * `spacing` - Time to wait between two exchanges
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// let result = sntprs::request_sampled(
+///     "time.google.com",
+///     123,
+///     4,
+///     Duration::from_secs(2),
+/// );
+///
+/// if let Ok(result) = result {
+///     println!("Offset: {} +/- {}", result.offset(), result.spread());
+/// }
+/// ```
+pub fn request_sampled(
+    pool: &str,
+    port: u32,
+    samples: usize,
+    spacing: time::Duration,
+) -> io::Result<SampledResult> {
+    NtpClient::new().request_sampled(pool, port, samples, spacing)
+}
 
-        return match result {
-            Ok(result) => {
-                debug!("{:?}", result);
-                Ok(result)
+/// Check whether the system clock is within the given tolerance
+/// by sampling the given NTP servers a few times each
+///
+/// * `servers` - Servers' names or IP addresses
+/// * `tolerance` - Accepted absolute clock offset in microseconds
+///
+/// # Example
+///
+/// ```rust,no_run
+/// let verdict = sntprs::check_system_clock(&["time.google.com"], 100_000);
+///
+/// if let Ok(verdict) = verdict {
+///     println!("Accurate: {}, offset: {}", verdict.is_accurate(), verdict.offset());
+/// }
+/// ```
+pub fn check_system_clock(
+    servers: &[&str],
+    tolerance: u64,
+) -> io::Result<ClockVerdict> {
+    check_clock(&NtpClient::new(), servers, tolerance)
+}
+
+/// Same as `check_system_clock`, sampling the servers with `client`
+fn check_clock<T, C>(
+    client: &NtpClient<T, C>,
+    servers: &[&str],
+    tolerance: u64,
+) -> io::Result<ClockVerdict>
+where
+    T: Transport + Clone + Send + 'static,
+    C: ClockSource + Clone + Send + 'static,
+{
+    let mut results = Vec::with_capacity(servers.len() * CHECK_SAMPLES);
+    let mut last_err = None;
+
+    for server in servers {
+        for _ in 0..CHECK_SAMPLES {
+            match client.request(server, NTP_PORT) {
+                Ok(result) => results.push(result),
+                Err(err) => last_err = Some(err),
             }
-            Err(err_str) => Err(io::Error::new(io::ErrorKind::Other, err_str)),
-        };
+        }
     }
 
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "Incorrect NTP packet size read",
-    ))
+    if results.is_empty() {
+        return Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No NTP servers given")
+        }));
+    }
+
+    let mut offsets: Vec<i64> = results.iter().map(|r| r.offset()).collect();
+
+    offsets.sort_unstable();
+
+    Ok(ClockVerdict {
+        offset: offsets[offsets.len() / 2],
+        max_error: results.iter().map(|r| r.max_error()).min().unwrap_or(0),
+        samples: results.len(),
+        tolerance,
+    })
 }
 
-fn process_request(
-    dest: std::vec::IntoIter<SocketAddr>,
-    req: &NtpPacket,
-    socket: &UdpSocket,
-) -> io::Result<SocketAddr> {
-    for addr in dest {
-        debug!("Address: {}", &addr);
-
-        match send_request(&req, &socket, addr) {
-            Ok(write_bytes) => {
-                assert_eq!(write_bytes, mem::size_of::<NtpPacket>());
-                return Ok(addr);
+/// Synchronize the system clock as early as possible, e.g. from the
+/// init of an embedded device or a container, before the regular
+/// polling starts. The configured servers are queried in turn with a
+/// short timeout for a few rounds, and the clock is stepped once with
+/// the first answer
+///
+/// * `config` - Configuration listing the servers to query
+///
+/// # Example
+///
+/// ```rust,no_run
+/// let config = sntprs::config::Config::from_env().unwrap();
+///
+/// if let Err(err) = sntprs::sync_at_boot(&config) {
+///     eprintln!("Unable to set the clock at boot: {}", err);
+/// }
+/// ```
+#[cfg(not(feature = "measure-only"))]
+pub fn sync_at_boot(config: &config::Config) -> io::Result<NtpResult> {
+    let client = config.client().timeout(config.timeout().min(BOOT_TIMEOUT));
+    let servers = config.servers();
+    let mut last_err = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "No NTP servers configured",
+    );
+
+    if servers.is_empty() {
+        return Err(last_err);
+    }
+
+    for round in 0..BOOT_ROUNDS {
+        if round > 0 {
+            std::thread::sleep(BOOT_RETRY_DELAY);
+        }
+
+        for (server, port) in &servers {
+            match client.request(server, *port) {
+                Ok(result) => {
+                    debug!("Boot time from {}:{}", server, port);
+                    let adjustment =
+                        utils::update_system_time(result.sec(), result.nsec())?;
+
+                    debug!("{}", adjustment);
+                    return Ok(result);
+                }
+                Err(err) => {
+                    debug!("Boot request to {} failed: {}", server, err);
+                    last_err = err;
+                }
             }
-            Err(err) => debug!("{}. Try another one", err),
         }
     }
 
-    Err(io::Error::new(
-        io::ErrorKind::AddrNotAvailable,
-        "SNTP servers not responding",
-    ))
+    Err(last_err)
 }
 
-fn send_request(
-    req: &NtpPacket,
-    socket: &net::UdpSocket,
-    dest: net::SocketAddr,
+/// Send an arbitrary NTP packet to the given address.
+/// No check is made on the packet content, which allows crafting
+/// nonstandard or deliberately malformed requests
+///
+/// * `packet` - Packet to send, fields in host byte order
+/// * `dest` - Destination address
+/// * `socket` - Socket used to send the packet
+///
+/// Returns the number of bytes sent
+pub fn send_raw(
+    packet: &NtpPacket,
+    dest: SocketAddr,
+    socket: &UdpSocket,
 ) -> io::Result<usize> {
-    let buf: RawPacket = req.into();
+    let buf: RawPacket = packet.into();
 
     socket.send_to(&buf, dest)
 }
 
-fn process_response(
-    req: &NtpPacket,
-    resp: RawPacket,
-    recv_timestamp: u64,
-) -> Result<NtpResult, &str> {
-    const SNTP_UNICAST: u8 = 4;
-    const SNTP_BROADCAST: u8 = 5;
-    const LI_MAX_VALUE: u8 = 3;
-    const MSEC_MASK: u64 = 0x0000_0000_ffff_ffff;
-    let shifter = |val, mask, shift| (val & mask) >> shift;
-    let mut packet = NtpPacket::from(resp);
-
-    convert_from_network(&mut packet);
-    #[cfg(debug_assertions)]
-    debug_ntp_packet(&packet);
+/// Receive a single datagram and decode it as an NTP packet,
+/// waiting at most for the socket's read timeout.
+/// No validation is made on the packet content
+///
+/// * `socket` - Socket used to receive the packet
+///
+/// Returns the decoded packet, the datagram size (which exceeds the
+/// packet size when a MAC trailer is present) and the sender address
+pub fn recv_raw(
+    socket: &UdpSocket,
+) -> io::Result<(NtpPacket, usize, SocketAddr)> {
+    let mut buf = [0u8; NTP_PACKET_SIZE + MAX_MAC_SIZE];
+    let (size, src) = socket.recv_from(&mut buf)?;
+    let (packet, _) = NtpPacket::parse(&buf[..size])?;
 
-    if req.tx_timestamp != packet.origin_timestamp {
-        return Err("Incorrect origin timestamp");
-    }
-    // Shift is 0
-    let mode = shifter(packet.li_vn_mode, MODE_MASK, MODE_SHIFT);
-    let li = shifter(packet.li_vn_mode, LI_MASK, LI_SHIFT);
-    let resp_version = shifter(packet.li_vn_mode, VERSION_MASK, VERSION_SHIFT);
-    let req_version = shifter(req.li_vn_mode, VERSION_MASK, VERSION_SHIFT);
-
-    if mode != SNTP_UNICAST && mode != SNTP_BROADCAST {
-        return Err("Incorrect MODE value");
-    }
+    Ok((packet, size, src))
+}
+
+/// Parse and validate a response datagram to the request described by
+/// `context`, and compute the result. Never panics, whatever the
+/// content of `bytes`, so untrusted network data can be fed directly;
+/// the `fuzz` directory holds the matching fuzz target
+///
+/// * `bytes` - Response datagram, possibly followed by a MAC
+/// * `context` - Request the response answers
+///
+/// Returns the first failed check as the error
+pub fn parse_response_bytes(
+    bytes: &[u8],
+    context: &RequestContext,
+) -> Result<NtpResult, Error> {
+    let packet = parse_datagram(bytes)?;
+
+    process_response(
+        &context.request,
+        &packet,
+        context.origin_timestamp,
+        context.recv_timestamp,
+        context.profile,
+    )
+    .map_err(|report| Error::Validation(report.violations[0]))
+}
+
+/// Decode the packet of a response datagram, checking its size leaves
+/// room for either nothing or a MAC after the packet
+fn parse_datagram(bytes: &[u8]) -> Result<NtpPacket, Error> {
+    let (packet, mac_size) = NtpPacket::parse(bytes)?;
 
-    if li > LI_MAX_VALUE {
-        return Err("Incorrect LI value");
+    if mac_size != 0 && !MAC_SIZES.contains(&mac_size) {
+        return Err(Error::PacketSize(bytes.len()));
     }
 
-    if req_version != resp_version {
-        return Err("Incorrect response version");
+    if mac_size > 0 {
+        let key_id = u32::from_be_bytes(*array_ref![bytes, NTP_PACKET_SIZE, 4]);
+
+        debug!("Skipping {} bytes MAC, key ID {}", mac_size, key_id);
     }
 
-    if packet.stratum == 0 {
-        return Err("Incorrect STRATUM headers");
+    Ok(packet)
+}
+
+fn process_response(
+    req: &NtpPacket,
+    packet: &NtpPacket,
+    origin_timestamp: u64,
+    recv_timestamp: u64,
+    profile: ValidationProfile,
+) -> Result<NtpResult, ValidationReport> {
+    #[cfg(debug_assertions)]
+    debug_ntp_packet(packet);
+
+    let report = validate(req, packet, profile);
+
+    if !report.is_valid() {
+        return Err(report);
     }
     //    theta = T(B) - T(A) = 1/2 * [(T2-T1) + (T3-T4)]
     //    and the round-trip delay
@@ -210,52 +388,52 @@ fn process_response(
     //      - T2 = server's RX timestamp
     //      - T3 = server's TX timestamp
     //      - T4 = client's RX timestamp
-    let delta = (recv_timestamp - packet.origin_timestamp) as i64
-        - (packet.tx_timestamp - packet.recv_timestamp) as i64;
-    let theta = ((packet.recv_timestamp as i64
-        - packet.origin_timestamp as i64)
-        + (recv_timestamp as i64 - packet.tx_timestamp as i64))
-        / 2;
+    let (theta, delta) = offset_and_delay(
+        origin_timestamp,
+        packet.recv_timestamp,
+        packet.tx_timestamp,
+        recv_timestamp,
+    );
 
     debug!("Roundtrip delay: {} us. Offset: {} us", delta.abs(), theta);
 
-    let seconds = (packet.tx_timestamp >> 32) as u32;
-    let nsec = (packet.tx_timestamp & MSEC_MASK) as u32;
-    let tx_tm = seconds - NtpPacket::NTP_TIMESTAMP_DELTA;
-
-    Ok(NtpResult::new(tx_tm, nsec, delta.abs() as u64, theta))
+    let t3 = NtpTimestamp::from(packet.tx_timestamp);
+    let nsec = t3.subsec_nanos();
+    let tx_tm = t3.seconds().wrapping_sub(NtpPacket::NTP_TIMESTAMP_DELTA);
+
+    Ok(NtpResult {
+        stratum: packet.stratum,
+        root_delay: packet.root_delay,
+        root_dispersion: packet.root_dispersion,
+        precision: packet.precision,
+        ..NtpResult::new(tx_tm, nsec, delta.unsigned_abs(), theta)
+    })
 }
 
-fn convert_from_network(packet: &mut NtpPacket) {
-    fn ntohl<T: NtpNum>(val: T) -> T::Type {
-        val.ntohl()
-    }
-
-    packet.root_delay = ntohl(packet.root_delay);
-    packet.root_dispersion = ntohl(packet.root_dispersion);
-    packet.ref_id = ntohl(packet.ref_id);
-    packet.ref_timestamp = ntohl(packet.ref_timestamp);
-    packet.origin_timestamp = ntohl(packet.origin_timestamp);
-    packet.recv_timestamp = ntohl(packet.recv_timestamp);
-    packet.tx_timestamp = ntohl(packet.tx_timestamp);
+/// Returns the offset and the roundtrip delay in microseconds computed
+/// from the four timestamps of an exchange
+fn offset_and_delay(t1: u64, t2: u64, t3: u64, t4: u64) -> (i64, i64) {
+    let t1 = NtpTimestamp::from(t1);
+    let t2 = NtpTimestamp::from(t2);
+    let t3 = NtpTimestamp::from(t3);
+    let t4 = NtpTimestamp::from(t4);
+    let delta = fixed_to_micros(t4.diff(t1).saturating_sub(t3.diff(t2)));
+    let theta = fixed_to_micros(half_sum(t2.diff(t1), t3.diff(t4)));
+
+    (theta, delta)
 }
 
 #[cfg(debug_assertions)]
 fn debug_ntp_packet(packet: &NtpPacket) {
-    let shifter = |val, mask, shift| (val & mask) >> shift;
-    let mode = shifter(packet.li_vn_mode, MODE_MASK, MODE_SHIFT);
-    let version = shifter(packet.li_vn_mode, VERSION_MASK, VERSION_SHIFT);
-    let li = shifter(packet.li_vn_mode, LI_MASK, LI_SHIFT);
-
     debug!("{}", (0..52).map(|_| "=").collect::<String>());
-    debug!("| Mode:\t\t{}", mode);
-    debug!("| Version:\t{}", version);
-    debug!("| Leap:\t\t{}", li);
+    debug!("| Mode:\t\t{:?}", packet.mode());
+    debug!("| Version:\t{}", packet.version());
+    debug!("| Leap:\t\t{:?}", packet.leap());
     debug!("| Stratum:\t{}", packet.stratum);
     debug!("| Poll:\t\t{}", packet.poll);
     debug!("| Precision:\t\t{}", packet.precision);
-    debug!("| Root delay:\t\t{}", packet.root_delay);
-    debug!("| Root dispersion:\t{}", packet.root_dispersion);
+    debug!("| Root delay:\t\t{:?}", packet.root_delay());
+    debug!("| Root dispersion:\t{:?}", packet.root_dispersion());
     debug!(
         "| Reference ID:\t\t{}",
         str::from_utf8(&packet.ref_id.to_be_bytes()).unwrap_or("")
@@ -267,21 +445,118 @@ fn debug_ntp_packet(packet: &NtpPacket) {
     debug!("{}", (0..52).map(|_| "=").collect::<String>());
 }
 
+fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+
+    hasher.write_u64(get_ntp_timestamp());
+    hasher.finish()
+}
+
+/// Returns the precision of the system clock as a power of two in seconds,
+/// measured once as the smallest step observed between two clock readings
+fn clock_precision() -> i8 {
+    const SAMPLES: usize = 8;
+    const FALLBACK_PRECISION: i8 = -20;
+    static PRECISION: OnceLock<i8> = OnceLock::new();
+
+    *PRECISION.get_or_init(|| {
+        let step = (0..SAMPLES)
+            .filter_map(|_| {
+                let start = clock::precise_now();
+                let mut now = start;
+
+                while now == start {
+                    now = clock::precise_now();
+                }
+
+                now.duration_since(start).ok()
+            })
+            .min();
+        let precision = match step {
+            Some(step) => step.as_secs_f64().log2().ceil() as i8,
+            None => FALLBACK_PRECISION,
+        };
+
+        debug!("Clock precision: 2^{} s", precision);
+        precision
+    })
+}
+
 fn get_ntp_timestamp() -> u64 {
-    let now_since_unix = time::SystemTime::now()
-        .duration_since(time::SystemTime::UNIX_EPOCH)
-        .unwrap();
-    let timestamp = ((now_since_unix.as_secs()
-        + (u64::from(NtpPacket::NTP_TIMESTAMP_DELTA)))
-        << 32)
-        + u64::from(now_since_unix.subsec_micros());
-
-    timestamp
+    NtpTimestamp::from_system_time(clock::precise_now()).to_bits()
 }
 
 #[cfg(test)]
 mod sntpc_tests {
-    use crate::{NtpResult, NSEC_IN_SEC};
+    use crate::fakeserver::FakeServer;
+    use crate::proto::{LeapIndicator, Mode, Version};
+    use crate::{
+        check_clock, parse_response_bytes, process_response, ClockVerdict,
+        Error, NtpClient, NtpPacket, NtpResult, NtpTimestamp, RawPacket,
+        RequestContext, ValidationProfile, NSEC_IN_SEC,
+    };
+    use std::convert::TryFrom;
+    use std::io;
+    use std::time::{Duration, SystemTime};
+
+    fn timestamp(millis: u64) -> u64 {
+        NtpTimestamp::from_unix(Duration::from_millis(millis)).to_bits()
+    }
+
+    fn exchange(t1: u64, t2: u64, t3: u64, t4: u64) -> NtpResult {
+        let mut req = NtpPacket::new();
+
+        req.tx_timestamp = t1;
+
+        let resp = NtpPacket {
+            li_vn_mode: 0b00_100_100,
+            stratum: 2,
+            origin_timestamp: t1,
+            recv_timestamp: t2,
+            tx_timestamp: t3,
+            ..NtpPacket::new()
+        };
+
+        process_response(
+            &req,
+            &resp,
+            t1,
+            t4,
+            ValidationProfile::Strict,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_packet_header_fields() {
+        let mut packet = NtpPacket::new();
+
+        assert_eq!(0b00_100_011, packet.li_vn_mode);
+
+        packet.set_leap(LeapIndicator::Unsynchronized);
+        packet.set_version(Version::V3);
+        packet.set_mode(Mode::Server);
+
+        assert_eq!(0b11_011_100, packet.li_vn_mode);
+        assert_eq!(LeapIndicator::Unsynchronized, packet.leap());
+        assert_eq!(3, packet.version());
+        assert_eq!(Mode::Server, packet.mode());
+    }
+
+    #[test]
+    fn test_packet_parse() {
+        let packet = NtpPacket::new();
+        let mut buf = RawPacket::from(&packet).to_vec();
+
+        buf.extend_from_slice(&[0; 20]);
+
+        assert_eq!(Ok((packet, 20)), NtpPacket::parse(&buf));
+        assert_eq!(Ok(packet), NtpPacket::try_from(&buf[..48]));
+        assert_eq!(Err(Error::PacketSize(47)), NtpPacket::try_from(&buf[..47]));
+    }
 
     #[test]
     fn test_ntp_result() {
@@ -294,34 +569,168 @@ mod sntpc_tests {
 
         let result2 = NtpResult::new(1, 2, 3, 4);
 
+        assert_ne!(result1, result2);
+        assert_eq!(result2, NtpResult::new(1, 2, 3, 4));
+        assert_eq!(result1, NtpResult::default());
         assert_eq!(1, result2.sec());
         assert_eq!(2, result2.nsec());
         assert_eq!(3, result2.roundtrip());
         assert_eq!(4, result2.offset());
 
-        let residue3 = u32::max_value() / NSEC_IN_SEC;
+        let residue3 = u32::MAX / NSEC_IN_SEC;
         let result3 = NtpResult::new(
-            u32::max_value() - residue3,
-            u32::max_value(),
-            u64::max_value(),
-            i64::max_value(),
+            u32::MAX - residue3,
+            u32::MAX,
+            u64::MAX,
+            i64::MAX,
         );
 
-        assert_eq!(u32::max_value(), result3.sec());
-        assert_eq!(u32::max_value() % NSEC_IN_SEC, result3.nsec());
-        assert_eq!(u64::max_value(), result3.roundtrip());
-        assert_eq!(i64::max_value(), result3.offset());
+        assert_eq!(u32::MAX, result3.sec());
+        assert_eq!(u32::MAX % NSEC_IN_SEC, result3.nsec());
+        assert_eq!(u64::MAX, result3.roundtrip());
+        assert_eq!(i64::MAX, result3.offset());
     }
 
     #[test]
     fn test_ntp_nsec_overflow_result() {
-        let result = NtpResult::new(0, u32::max_value(), 0, 0);
-        let max_value_sec = u32::max_value() / NSEC_IN_SEC;
-        let max_value_nsec = u32::max_value() % NSEC_IN_SEC;
+        let result = NtpResult::new(0, u32::MAX, 0, 0);
+        let max_value_sec = u32::MAX / NSEC_IN_SEC;
+        let max_value_nsec = u32::MAX % NSEC_IN_SEC;
 
         assert_eq!(max_value_sec, result.sec());
         assert_eq!(max_value_nsec, result.nsec());
         assert_eq!(0, result.roundtrip());
         assert_eq!(0, result.offset());
     }
+
+    #[test]
+    fn test_era_end_result() {
+        // Last nanosecond before the Unix epoch, in era 0
+        let t3 = (2_208_988_799 << 32) | 0xffff_ffff;
+        let result = exchange(t3, t3, t3, t3);
+
+        assert_eq!((u32::MAX, 999_999_999), (result.sec(), result.nsec()));
+
+        let context = RequestContext::new(
+            NtpPacket {
+                tx_timestamp: t3,
+                ..NtpPacket::new()
+            },
+            t3,
+        );
+        let resp = NtpPacket {
+            li_vn_mode: 0b00_100_100,
+            stratum: 2,
+            origin_timestamp: t3,
+            recv_timestamp: t3,
+            tx_timestamp: t3,
+            ..NtpPacket::new()
+        };
+
+        assert_eq!(
+            Ok(result),
+            parse_response_bytes(&RawPacket::from(&resp), &context)
+        );
+    }
+
+    #[test]
+    fn test_ntp_result_max_error() {
+        let result = NtpResult {
+            // 0.5 s, 0.25 s, 2^-10 s
+            root_delay: 0x0000_8000,
+            root_dispersion: 0x0000_4000,
+            precision: -10,
+            ..NtpResult::new(0, 0, 1_000, 0)
+        };
+
+        assert_eq!(500 + 250_000 + 250_000 + 977, result.max_error());
+
+        let result = NtpResult {
+            root_delay: u32::MAX,
+            root_dispersion: u32::MAX,
+            precision: 127,
+            ..NtpResult::new(0, 0, u64::MAX, 0)
+        };
+
+        assert_eq!(
+            u64::MAX / 2 + 32_767_999_992 + 65_535_999_984 + 1_000_000,
+            result.max_error()
+        );
+    }
+
+    #[test]
+    fn test_offset_server_behind() {
+        let result = exchange(
+            timestamp(1_000_000),
+            timestamp(990_100),
+            timestamp(990_100),
+            timestamp(1_000_200),
+        );
+
+        assert_eq!(200_000, result.roundtrip());
+        assert_eq!(-10_000_000, result.offset());
+        assert_eq!(990, result.sec());
+        assert_eq!(100_000_000, result.nsec());
+    }
+
+    #[test]
+    fn test_offset_server_ahead() {
+        let result = exchange(
+            timestamp(1_000_000),
+            timestamp(1_010_050),
+            timestamp(1_010_060),
+            timestamp(1_000_110),
+        );
+
+        assert_eq!(100_000, result.roundtrip());
+        assert_eq!(10_000_000, result.offset());
+
+        let result = crate::v2::NtpResult::from(result);
+
+        assert_eq!(Duration::from_millis(100), result.roundtrip());
+        assert_eq!(chrono::TimeDelta::seconds(10), result.offset());
+    }
+
+    #[test]
+    fn test_check_clock() {
+        let servers = ["192.0.2.1", "192.0.2.2"];
+        let server = FakeServer::new();
+        let client = NtpClient::new().transport(server.clone());
+        let verdict = check_clock(&client, &servers, 100_000).unwrap();
+
+        assert_eq!(6, server.exchanges());
+        assert_eq!(6, verdict.samples());
+        assert!(verdict.is_accurate());
+
+        // Server 10 s ahead of the system clock
+        let ahead = NtpTimestamp::from_system_time(SystemTime::now())
+            .wrapping_add(10 << 32);
+        let client = NtpClient::new()
+            .transport(FakeServer::new().time(ahead.to_bits()));
+        let verdict = check_clock(&client, &servers, 100_000).unwrap();
+
+        assert!((verdict.offset() - 10_000_000).abs() < 1_000_000);
+        assert!(!verdict.is_accurate());
+
+        let err = check_clock(&client, &[], 100_000).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn test_clock_verdict() {
+        let verdict = ClockVerdict {
+            offset: -100,
+            max_error: 50,
+            samples: 3,
+            tolerance: 100,
+        };
+
+        assert!(verdict.is_accurate());
+        assert!(!ClockVerdict {
+            offset: 101,
+            ..verdict
+        }
+        .is_accurate());
+    }
 }
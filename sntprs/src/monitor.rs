@@ -0,0 +1,251 @@
+//! Synchronization alerts
+//!
+//! A [`Monitor`] is fed with the outcome of every poll and notifies the
+//! registered observers when the clock offset exceeds a threshold, when the
+//! server stratum degrades or when too many consecutive polls fail.
+//! It also keeps the [`Reach`] register of the server, as shown by
+//! `ntpq -p`
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::NtpResult;
+
+/// Condition reported by a [`Monitor`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Alert {
+    /// Absolute clock offset (in microseconds) exceeded the threshold
+    OffsetExceeded { offset: i64, threshold: u64 },
+    /// Server stratum increased since the previous successful poll
+    StratumDegraded { previous: u8, current: u8 },
+    /// The given number of consecutive polls failed
+    PollsFailed { count: usize },
+    /// No successful synchronization happened within the watchdog window
+    Degraded,
+    /// Synchronization succeeded again after being degraded
+    Recovered,
+    /// The system clock was stepped by the given number of microseconds,
+    /// other than by an adjustment reported to the service
+    ClockStepped { step: i64 },
+    /// The system resumed after being suspended for the given time
+    Resumed { slept: Duration },
+}
+
+/// Reachability shift register of a server: every poll shifts the
+/// register left, setting the lowest bit when it succeeded. Displayed
+/// in octal, `377` meaning that the last eight polls succeeded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Reach(u8);
+
+impl Reach {
+    /// Record the outcome of a poll
+    pub fn update(&mut self, success: bool) {
+        self.0 = (self.0 << 1) | u8::from(success);
+    }
+
+    /// Returns the register, the lowest bit being the latest poll
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns whether any of the last eight polls succeeded
+    pub fn is_reachable(self) -> bool {
+        self.0 != 0
+    }
+
+    /// Returns how many of the last eight polls succeeded
+    pub fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+}
+
+impl Display for Reach {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:o}", self.0)
+    }
+}
+
+type Observer = Box<dyn FnMut(&Alert) + Send>;
+
+/// Watches poll results and raises [`Alert`]s
+pub struct Monitor {
+    offset_threshold: Option<u64>,
+    failure_threshold: Option<usize>,
+    last_stratum: Option<u8>,
+    failures: usize,
+    reach: Reach,
+    observers: Vec<Observer>,
+}
+
+impl Monitor {
+    /// Create new monitor with no thresholds configured
+    pub fn new() -> Self {
+        Monitor {
+            offset_threshold: None,
+            failure_threshold: None,
+            last_stratum: None,
+            failures: 0,
+            reach: Reach::default(),
+            observers: Vec::new(),
+        }
+    }
+
+    /// Raise an alert whenever the absolute offset exceeds `threshold`
+    /// microseconds
+    pub fn offset_threshold(mut self, threshold: u64) -> Self {
+        self.offset_threshold = Some(threshold);
+        self
+    }
+
+    /// Raise an alert once `count` consecutive polls failed
+    pub fn failure_threshold(mut self, count: usize) -> Self {
+        self.failure_threshold = Some(count.max(1));
+        self
+    }
+
+    /// Register a callback invoked for every raised alert
+    pub fn on_alert<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Alert) + Send + 'static,
+    {
+        self.observers.push(Box::new(callback));
+    }
+
+    /// Returns a channel receiving every raised alert
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Alert> {
+        let (tx, rx) = mpsc::channel();
+
+        self.on_alert(move |alert| {
+            let _ = tx.send(alert.clone());
+        });
+
+        rx
+    }
+
+    /// Returns the reachability register of the server
+    pub fn reach(&self) -> Reach {
+        self.reach
+    }
+
+    /// Process the outcome of a poll, notifying observers if needed
+    pub fn observe(&mut self, result: &io::Result<NtpResult>) {
+        self.reach.update(result.is_ok());
+
+        match result {
+            Ok(result) => self.observe_success(result),
+            Err(_) => self.observe_failure(),
+        }
+    }
+
+    fn observe_success(&mut self, result: &NtpResult) {
+        self.failures = 0;
+
+        if let Some(threshold) = self.offset_threshold {
+            if result.offset().unsigned_abs() > threshold {
+                self.notify(Alert::OffsetExceeded {
+                    offset: result.offset(),
+                    threshold,
+                });
+            }
+        }
+
+        let current = result.stratum();
+
+        if let Some(previous) = self.last_stratum.replace(current) {
+            if current > previous {
+                self.notify(Alert::StratumDegraded { previous, current });
+            }
+        }
+    }
+
+    fn observe_failure(&mut self) {
+        self.failures += 1;
+
+        if self.failure_threshold == Some(self.failures) {
+            self.notify(Alert::PollsFailed {
+                count: self.failures,
+            });
+        }
+    }
+
+    pub(crate) fn notify(&mut self, alert: Alert) {
+        debug!("Alert: {:?}", alert);
+
+        for observer in self.observers.iter_mut() {
+            observer(&alert);
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+#[cfg(test)]
+mod monitor_tests {
+    use crate::monitor::{Alert, Monitor};
+    use crate::NtpResult;
+    use std::io;
+
+    fn result(offset: i64, stratum: u8) -> io::Result<NtpResult> {
+        Ok(NtpResult {
+            stratum,
+            ..NtpResult::new(0, 0, 0, offset)
+        })
+    }
+
+    #[test]
+    fn test_monitor_alerts() {
+        let mut monitor =
+            Monitor::new().offset_threshold(100).failure_threshold(2);
+        let alerts = monitor.subscribe();
+
+        monitor.observe(&result(-50, 1));
+        monitor.observe(&result(-150, 2));
+        monitor.observe(&Err(io::Error::other("timeout")));
+        monitor.observe(&Err(io::Error::other("timeout")));
+        monitor.observe(&Err(io::Error::other("timeout")));
+
+        let alerts: Vec<Alert> = alerts.try_iter().collect();
+
+        assert_eq!(
+            vec![
+                Alert::OffsetExceeded {
+                    offset: -150,
+                    threshold: 100
+                },
+                Alert::StratumDegraded {
+                    previous: 1,
+                    current: 2
+                },
+                Alert::PollsFailed { count: 2 },
+            ],
+            alerts
+        );
+    }
+
+    #[test]
+    fn test_monitor_reach() {
+        let mut monitor = Monitor::new();
+
+        monitor.observe(&result(0, 1));
+        monitor.observe(&result(0, 1));
+        monitor.observe(&Err(io::Error::other("timeout")));
+
+        assert_eq!("6", monitor.reach().to_string());
+        assert_eq!(2, monitor.reach().count());
+
+        for _ in 0..8 {
+            monitor.observe(&Err(io::Error::other("timeout")));
+        }
+
+        assert_eq!("0", monitor.reach().to_string());
+        assert_eq!(0, monitor.reach().count());
+    }
+}
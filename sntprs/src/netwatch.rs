@@ -0,0 +1,250 @@
+//! Network change notifications on Linux
+//!
+//! A [`NetworkWatcher`] listens to the kernel routing netlink messages
+//! and reports when a link comes up, an address is assigned or a route
+//! is added, so a laptop joining a network can poll right away instead
+//! of waiting for the next poll interval
+//!
+//! ```rust,no_run
+//! use std::sync::{Arc, Mutex};
+//!
+//! use sntprs::service::SyncService;
+//!
+//! let service = Arc::new(Mutex::new(SyncService::new("pool.ntp.org", 123)));
+//!
+//! sntprs::netwatch::watch(service.clone()).unwrap();
+//!
+//! loop {
+//!     service.lock().unwrap().poll_if_due();
+//!     std::thread::sleep(std::time::Duration::from_secs(1));
+//! }
+//! ```
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::service::SyncService;
+
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+const RTM_NEWLINK: u16 = 16;
+const RTM_NEWADDR: u16 = 20;
+const RTM_NEWROUTE: u16 = 24;
+const HEADER_SIZE: usize = 16;
+// Offset of ifi_flags in the ifinfomsg following the header
+const LINK_FLAGS_OFFSET: usize = HEADER_SIZE + 8;
+const LINK_UP: u32 = (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Netlink socket subscribed to the link, address and route changes
+pub struct NetworkWatcher {
+    socket: OwnedFd,
+}
+
+impl NetworkWatcher {
+    /// Open the netlink socket
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: plain socket creation, the descriptor is owned below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: fd is a freshly created descriptor nothing else owns
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: sockaddr_nl is plain data, valid when zeroed
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+
+        addr.nl_family = libc::AF_NETLINK as _;
+        addr.nl_groups = RTMGRP_LINK
+            | RTMGRP_IPV4_IFADDR
+            | RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_IFADDR
+            | RTMGRP_IPV6_ROUTE;
+
+        // SAFETY: addr is a valid sockaddr_nl of the given size
+        let res = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as _,
+            )
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(NetworkWatcher { socket })
+    }
+
+    /// Wait up to `timeout`, or forever if `None`, for changes. Returns
+    /// whether connectivity may have been gained, i.e. a link came up, an
+    /// address was assigned or a route was added
+    pub fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout =
+            timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+        let ready = loop {
+            // SAFETY: fds is a single valid pollfd
+            let ready = unsafe { libc::poll(&mut fds, 1, timeout) };
+
+            if ready >= 0 {
+                break ready;
+            }
+
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+
+        if ready == 0 {
+            return Ok(false);
+        }
+
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        // SAFETY: buf is writable for its whole length
+        let size = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                0,
+            )
+        };
+
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(gains_connectivity(&buf[..size as usize]))
+    }
+}
+
+/// Returns whether any of the netlink messages in `buf` reports a link
+/// up and running, a new address or a new route
+fn gains_connectivity(mut buf: &[u8]) -> bool {
+    while buf.len() >= HEADER_SIZE {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+
+        match kind {
+            RTM_NEWADDR | RTM_NEWROUTE => return true,
+            // Also sent when a link goes down, check its flags
+            RTM_NEWLINK if link_up(&buf[..len.min(buf.len())]) => return true,
+            _ => (),
+        }
+
+        // Messages are aligned on 4 bytes
+        let len = (len + 3) & !3;
+
+        if len < HEADER_SIZE || len > buf.len() {
+            break;
+        }
+
+        buf = &buf[len..];
+    }
+
+    false
+}
+
+/// Returns whether the RTM_NEWLINK message `msg` reports a link up and
+/// running
+fn link_up(msg: &[u8]) -> bool {
+    msg.get(LINK_FLAGS_OFFSET..LINK_FLAGS_OFFSET + 4)
+        .is_some_and(|flags| {
+            let flags =
+                u32::from_ne_bytes([flags[0], flags[1], flags[2], flags[3]]);
+
+            flags & LINK_UP == LINK_UP
+        })
+}
+
+/// Make a poll of `service` due right away whenever connectivity may
+/// have been gained, from a background thread
+pub fn watch(
+    service: Arc<Mutex<SyncService>>,
+) -> io::Result<thread::JoinHandle<()>> {
+    let watcher = NetworkWatcher::open()?;
+
+    Ok(thread::spawn(move || loop {
+        match watcher.wait(None) {
+            Ok(true) => {
+                debug!("Network changed, polling right away");
+                service.lock().unwrap().schedule_now();
+            }
+            Ok(false) => (),
+            Err(err) => {
+                debug!("Unable to watch the network: {}", err);
+                return;
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod netwatch_tests {
+    use crate::netwatch::{gains_connectivity, RTM_NEWADDR, RTM_NEWLINK};
+    use crate::netwatch::{LINK_FLAGS_OFFSET, LINK_UP};
+
+    fn message(kind: u16, len: u32) -> Vec<u8> {
+        let mut msg = vec![0u8; len as usize];
+
+        msg[..4].copy_from_slice(&len.to_ne_bytes());
+        msg[4..6].copy_from_slice(&kind.to_ne_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_gains_connectivity() {
+        // RTM_DELADDR, then RTM_NEWADDR
+        let mut buf = message(21, 18);
+
+        buf.extend_from_slice(&[0, 0]);
+
+        assert!(!gains_connectivity(&buf));
+
+        buf.extend(message(RTM_NEWADDR, 16));
+
+        assert!(gains_connectivity(&buf));
+        assert!(!gains_connectivity(&message(RTM_NEWADDR, 16)[..8]));
+    }
+
+    #[test]
+    fn test_link_flags() {
+        let mut down = message(RTM_NEWLINK, 32);
+        let mut up = down.clone();
+
+        down[LINK_FLAGS_OFFSET..LINK_FLAGS_OFFSET + 4]
+            .copy_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+        up[LINK_FLAGS_OFFSET..LINK_FLAGS_OFFSET + 4]
+            .copy_from_slice(&LINK_UP.to_ne_bytes());
+
+        assert!(!gains_connectivity(&down));
+        assert!(!gains_connectivity(&message(RTM_NEWLINK, 16)));
+        assert!(gains_connectivity(&up));
+    }
+}
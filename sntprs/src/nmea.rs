@@ -0,0 +1,328 @@
+//! GPS reference clock
+//!
+//! [`NmeaSource`] reads the NMEA sentences of a GPS receiver, usually from
+//! a serial port, and measures the offset of the local clock against the
+//! time of the `RMC` fixes. As a stratum 0 reference it lets air-gapped
+//! networks synchronize without any upstream NTP server.
+//! NMEA time is coarse, as the sentences trail the second they refer to
+//! by tens to hundreds of milliseconds, which [`NmeaSource::delay`]
+//! compensates
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use std::time::Duration;
+//!
+//! use sntprs::nmea::NmeaSource;
+//! use sntprs::source::TimeSource;
+//!
+//! let port = File::open("/dev/ttyUSB0").unwrap();
+//! let gps = NmeaSource::new(BufReader::new(port))
+//!     .delay(Duration::from_millis(120));
+//!
+//! println!("{:?}", gps.sample());
+//! ```
+
+use std::io;
+use std::io::BufRead;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::source::{Provenance, TimeSample, TimeSource, Trust};
+
+/// Default maximum error of the NMEA time, in microseconds
+pub const DEFAULT_MAX_ERROR: u64 = 100_000;
+
+/// Age from which a fix is too old to sample, receivers sending one
+/// per second
+const MAX_FIX_AGE: Duration = Duration::from_secs(2);
+const SECS_IN_DAY: u64 = 86_400;
+
+/// Latest fix read from the receiver
+#[derive(Default)]
+struct Reception {
+    /// Time of the fix and when its sentence was received
+    fix: Option<(SystemTime, SystemTime)>,
+    /// Whether the receiver stream ended or failed
+    closed: bool,
+}
+
+type Shared = (Mutex<Reception>, Condvar);
+
+/// Time source reading the `RMC` sentences of a GPS receiver.
+/// The sentences are read as they arrive by a background thread, so
+/// none gets stale waiting in a buffer until the next sample
+pub struct NmeaSource {
+    shared: Arc<Shared>,
+    name: String,
+    delay: Duration,
+    max_error: u64,
+}
+
+impl NmeaSource {
+    /// Create new source reading the sentences from `reader`, from a
+    /// background thread left once the source is dropped
+    pub fn new<R: BufRead + Send + 'static>(reader: R) -> Self {
+        let shared = Arc::new(Shared::default());
+        let weak = Arc::downgrade(&shared);
+
+        thread::spawn(move || receive(reader, weak));
+
+        NmeaSource {
+            shared,
+            name: "nmea".to_string(),
+            delay: Duration::ZERO,
+            max_error: DEFAULT_MAX_ERROR,
+        }
+    }
+
+    /// Name the source, e.g. after its serial port
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Set the time between the start of a second and the reception of
+    /// the sentence reporting it, as measured for the receiver
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Set the maximum error of the reported time in microseconds.
+    /// Defaults to 100 ms
+    pub fn max_error(mut self, max_error: u64) -> Self {
+        self.max_error = max_error;
+        self
+    }
+}
+
+/// Read the sentences of `reader`, timestamped right as they arrive,
+/// keeping the latest valid fix until the source is dropped
+fn receive<R: BufRead>(mut reader: R, shared: Weak<Shared>) {
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+
+        let read = reader.read_line(&mut line);
+        let received = SystemTime::now();
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let (reception, arrived) = &*shared;
+        let mut reception = reception.lock().unwrap();
+
+        match read {
+            Ok(0) | Err(_) => {
+                reception.closed = true;
+                arrived.notify_all();
+                return;
+            }
+            Ok(_) => {
+                if let Some(time) = parse_rmc(line.trim()) {
+                    debug!("{}", line.trim());
+                    reception.fix = Some((time, received));
+                    arrived.notify_all();
+                }
+            }
+        }
+    }
+}
+
+impl TimeSource for NmeaSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn trust(&self) -> Trust {
+        Trust::High
+    }
+
+    fn provenance(&self) -> Provenance {
+        Provenance::ReferenceClock
+    }
+
+    /// Measure the offset against the latest fix, waiting up to two
+    /// seconds for one when it is older
+    fn sample(&self) -> io::Result<TimeSample> {
+        let (reception, arrived) = &*self.shared;
+        let fresh = |reception: &Reception| {
+            reception.fix.is_some_and(|(_, received)| {
+                received.elapsed().unwrap_or_default() < MAX_FIX_AGE
+            })
+        };
+        let (reception, _) = arrived
+            .wait_timeout_while(
+                reception.lock().unwrap(),
+                MAX_FIX_AGE,
+                |reception| !reception.closed && !fresh(reception),
+            )
+            .unwrap();
+
+        let (time, received) = match reception.fix {
+            Some(fix) if fresh(&reception) => fix,
+            _ if reception.closed => {
+                return Err(io::ErrorKind::UnexpectedEof.into())
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "No valid RMC sentence received",
+                ))
+            }
+        };
+        let time = time + self.delay;
+        let offset = match time.duration_since(received) {
+            Ok(ahead) => ahead.as_micros() as i64,
+            Err(err) => -(err.duration().as_micros() as i64),
+        };
+
+        Ok(TimeSample {
+            source: self.name.clone(),
+            offset,
+            max_error: self.max_error,
+            trust: Trust::High,
+        })
+    }
+}
+
+/// Returns the UTC time of a valid `RMC` sentence, e.g. `$GPRMC` or
+/// `$GNRMC`, or `None` for other sentences, fixes flagged as void or
+/// a checksum mismatch
+pub fn parse_rmc(sentence: &str) -> Option<SystemTime> {
+    let (body, checksum) = sentence.strip_prefix('$')?.split_once('*')?;
+
+    if u8::from_str_radix(checksum, 16).ok()?
+        != body.bytes().fold(0, |sum, byte| sum ^ byte)
+    {
+        return None;
+    }
+
+    let fields: Vec<&str> = body.split(',').collect();
+
+    if fields.len() < 10 || !fields[0].ends_with("RMC") || fields[2] != "A" {
+        return None;
+    }
+
+    let (time, date) = (fields[1], fields[9]);
+    let hour = digits(time, 0, 24)?;
+    let minute = digits(time, 2, 60)?;
+    let second = digits(time, 4, 61)?;
+    let nanos = match time.get(6..) {
+        Some(fraction) if fraction.starts_with('.') => {
+            (fraction.parse::<f64>().ok()? * 1e9) as u32
+        }
+        _ => 0,
+    };
+    let day = digits(date, 0, 32)?;
+    let month = digits(date, 2, 13)?;
+    // Two digit years are taken between 1980 and 2079
+    let year = match digits(date, 4, 100)? {
+        year if year < 80 => 2000 + year,
+        year => 1900 + year,
+    };
+
+    if day == 0 || month == 0 {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * SECS_IN_DAY
+        + u64::from(hour * 3_600 + minute * 60 + second);
+
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Parse two digits at `pos`, below `limit`
+fn digits(field: &str, pos: usize, limit: u32) -> Option<u32> {
+    let value: u32 = field.get(pos..pos + 2)?.parse().ok()?;
+
+    if value < limit {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Returns the days since 1970-01-01 of a date from 1970 on
+fn days_from_civil(year: u32, month: u32, day: u32) -> u64 {
+    let year = u64::from(if month <= 2 { year - 1 } else { year });
+    let (era, yoe) = (year / 400, year % 400);
+    let doy = (153 * u64::from((month + 9) % 12) + 2) / 5 + u64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod nmea_tests {
+    use crate::nmea::{parse_rmc, NmeaSource};
+    use crate::source::TimeSource;
+    use std::io::{BufReader, Cursor, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const FIX: &str = "$GNRMC,000000.50,A,,,,,,,010124,,,A*78\n";
+
+    #[test]
+    fn test_parse_rmc() {
+        let sentence = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,\
+                        230394,003.1,W*6A";
+
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(764_426_119)),
+            parse_rmc(sentence)
+        );
+        assert_eq!(None, parse_rmc(&sentence.replace("*6A", "*6B")));
+        assert_eq!(
+            None,
+            parse_rmc(
+                "$GPRMC,123519,V,4807.038,N,01131.000,E,022.4,084.4,\
+                 230394,003.1,W*7D"
+            )
+        );
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::new(1_704_067_200, 500_000_000)),
+            parse_rmc("$GNRMC,000000.50,A,,,,,,,010124,,,A*78")
+        );
+    }
+
+    #[test]
+    fn test_sentence_reception() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut receiver =
+            TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let gps = NmeaSource::new(BufReader::new(stream));
+
+        receiver.write_all(FIX.as_bytes()).unwrap();
+
+        let sent = SystemTime::now();
+        let time = UNIX_EPOCH + Duration::new(1_704_067_200, 500_000_000);
+        let expected = -(sent.duration_since(time).unwrap().as_micros() as i64);
+
+        // Timestamped on arrival, not when sampled
+        thread::sleep(Duration::from_millis(300));
+
+        let offset = gps.sample().unwrap().offset;
+
+        assert!((offset - expected).abs() < 100_000);
+
+        drop(receiver);
+        thread::sleep(Duration::from_millis(50));
+
+        // The latest fix is still fresh
+        assert!(gps.sample().is_ok());
+
+        let gps = NmeaSource::new(Cursor::new("$GPGGA,123519*00\n"));
+
+        assert!(gps.sample().is_err());
+    }
+}
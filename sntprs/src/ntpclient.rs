@@ -0,0 +1,956 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::clock::{ClockSource, SystemClock};
+use crate::detailedresult::{DetailedResult, Timestamps, Timings};
+use crate::ntppacket::{NtpPacket, RawPacket, MAX_MAC_SIZE, NTP_PACKET_SIZE};
+use crate::resolver::{resolve, DnsCache};
+use crate::sampledresult::SampledResult;
+use crate::trace::{Direction, PacketTrace};
+use crate::transport::{route_source, DynTransport, SourcePort};
+use crate::transport::{Transport, UdpTransport};
+use crate::{
+    get_ntp_timestamp, parse_datagram, process_response, random_u64, Error,
+    NtpResult, NtpTimestamp, SourceCheck, ValidationProfile, NSEC_IN_SEC,
+};
+
+/// Error reported when the server time falls outside the plausibility
+/// window configured with [`NtpClient::plausibility`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImplausibleTime {
+    /// Time reported by the server
+    pub time: SystemTime,
+    /// Lower bound of the plausibility window
+    pub not_before: SystemTime,
+    /// Upper bound of the plausibility window
+    pub not_after: SystemTime,
+}
+
+impl Display for ImplausibleTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        };
+
+        write!(
+            f,
+            "Implausible server time {} outside of [{}, {}]",
+            secs(self.time),
+            secs(self.not_before),
+            secs(self.not_after)
+        )
+    }
+}
+
+impl std::error::Error for ImplausibleTime {}
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+const MOBILE_HAPPY_EYEBALLS: Duration = Duration::from_millis(250);
+const MOBILE_DNS_TTL: Duration = Duration::from_secs(300);
+const MOBILE_TIMEOUT: Duration = Duration::from_secs(3);
+type Tracer = Arc<dyn Fn(&PacketTrace) + Send + Sync>;
+type ResultCache = Arc<Mutex<HashMap<(String, u32), (Instant, NtpResult)>>>;
+
+/// Request counters of a [`NtpClient`] and its clones
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ClientStats {
+    /// Requests sent
+    pub requests: u64,
+    /// Valid responses received
+    pub responses: u64,
+    /// Requests left without a valid response
+    pub failures: u64,
+    /// Results served by [`NtpClient::cached_request`] without a request
+    pub cached: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    responses: AtomicU64,
+    failures: AtomicU64,
+    cached: AtomicU64,
+}
+
+/// Configurable SNTP client.
+/// The datagrams go through the transport `T` and are stamped by the
+/// clock `C`, both statically dispatched. The defaults use UDP sockets
+/// and the system clock.
+///
+/// The client is `Send` and `Sync` with the default transport and
+/// clock: every request opens its own socket, requests from a fixed
+/// source port are serialized, and the caches and [`ClientStats`] are
+/// shared behind locks and atomics. A single client can be kept in the
+/// state of a web server and used from any thread, through an `Arc` or
+/// cheap clones sharing the caches and counters
+#[derive(Clone)]
+pub struct NtpClient<T = UdpTransport, C = SystemClock> {
+    poll: i8,
+    happy_eyeballs: Option<Duration>,
+    dns_cache: Option<Arc<DnsCache>>,
+    dns_timeout: Option<Duration>,
+    timeout: Duration,
+    split_timeout: bool,
+    privacy: bool,
+    full_report: bool,
+    profile: ValidationProfile,
+    source_check: SourceCheck,
+    plausibility: Option<(SystemTime, SystemTime)>,
+    tracer: Option<Tracer>,
+    results: ResultCache,
+    counters: Arc<Counters>,
+    transport: T,
+    clock: C,
+}
+
+/// Client whose transport is chosen at runtime, keeping generics out of
+/// the APIs of plugin systems and configurable applications
+pub type DynNtpClient<C = SystemClock> = NtpClient<DynTransport, C>;
+
+impl NtpClient {
+    /// Create new client with the default settings
+    pub fn new() -> Self {
+        NtpClient {
+            poll: 0,
+            happy_eyeballs: None,
+            dns_cache: None,
+            dns_timeout: Some(DEFAULT_DNS_TIMEOUT),
+            timeout: DEFAULT_TIMEOUT,
+            split_timeout: false,
+            privacy: false,
+            full_report: false,
+            profile: ValidationProfile::Strict,
+            source_check: SourceCheck::Strict,
+            plausibility: None,
+            tracer: None,
+            results: ResultCache::default(),
+            counters: Arc::default(),
+            transport: UdpTransport::new(),
+            clock: SystemClock,
+        }
+    }
+
+    /// Create new client suited to mobile devices, which only measure the
+    /// offset since applications cannot set the clock there: IPv6 and
+    /// IPv4 raced 250 ms apart as networks change, resolved addresses
+    /// cached for 5 minutes to save radio wake-ups, and a 3 s timeout
+    /// tolerating cellular latency. Apply the results with an
+    /// [`NtpClock`](crate::clock::NtpClock)
+    pub fn mobile() -> Self {
+        NtpClient::new()
+            .happy_eyeballs(MOBILE_HAPPY_EYEBALLS)
+            .dns_cache(MOBILE_DNS_TTL)
+            .timeout(MOBILE_TIMEOUT)
+    }
+}
+
+impl<C> NtpClient<UdpTransport, C> {
+    /// Send the requests from the given local address,
+    /// used for servers of the same address family
+    pub fn bind_address(mut self, addr: IpAddr) -> Self {
+        self.transport = self.transport.bind_address(addr);
+        self
+    }
+
+    /// Send the requests from the given local port
+    pub fn source_port(mut self, port: SourcePort) -> Self {
+        self.transport = self.transport.source_port(port);
+        self
+    }
+
+    /// Hand every socket to `hook` once bound and before sending the
+    /// request, to set platform specific options such as `SO_MARK`.
+    /// The request fails with the error returned by the hook
+    pub fn on_socket<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&UdpSocket) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.transport = self.transport.on_socket(hook);
+        self
+    }
+
+    /// Connect every socket to the server, so that only its datagrams
+    /// are received, see [`UdpTransport::connected`]. The shared
+    /// sockets of a [`Batch`](crate::batch::Batch) are never connected
+    pub fn connected(mut self, enabled: bool) -> Self {
+        self.transport = self.transport.connected(enabled);
+        self
+    }
+
+    /// Create the socket the requests are sent from, with the user
+    /// options applied
+    pub(crate) fn open_socket(&self, ipv6: bool) -> io::Result<UdpSocket> {
+        self.transport.open(ipv6)
+    }
+}
+
+impl<T, C> NtpClient<T, C> {
+    /// Send the datagrams through `transport`
+    pub fn transport<U>(self, transport: U) -> NtpClient<U, C> {
+        NtpClient {
+            poll: self.poll,
+            happy_eyeballs: self.happy_eyeballs,
+            dns_cache: self.dns_cache,
+            dns_timeout: self.dns_timeout,
+            timeout: self.timeout,
+            split_timeout: self.split_timeout,
+            privacy: self.privacy,
+            full_report: self.full_report,
+            profile: self.profile,
+            source_check: self.source_check,
+            plausibility: self.plausibility,
+            tracer: self.tracer,
+            results: self.results,
+            counters: self.counters,
+            transport,
+            clock: self.clock,
+        }
+    }
+
+    /// Send the datagrams through `transport`, erasing its type
+    pub fn dyn_transport<U>(self, transport: U) -> DynNtpClient<C>
+    where
+        U: Transport + Send + Sync + 'static,
+    {
+        self.transport(Arc::new(transport) as DynTransport)
+    }
+
+    /// Read the local timestamps from `clock`
+    pub fn clock<D>(self, clock: D) -> NtpClient<T, D> {
+        NtpClient {
+            poll: self.poll,
+            happy_eyeballs: self.happy_eyeballs,
+            dns_cache: self.dns_cache,
+            dns_timeout: self.dns_timeout,
+            timeout: self.timeout,
+            split_timeout: self.split_timeout,
+            privacy: self.privacy,
+            full_report: self.full_report,
+            profile: self.profile,
+            source_check: self.source_check,
+            plausibility: self.plausibility,
+            tracer: self.tracer,
+            results: self.results,
+            counters: self.counters,
+            transport: self.transport,
+            clock,
+        }
+    }
+
+    /// Advertise the given polling interval in the requests, as done when
+    /// polling periodically. The interval is rounded to the nearest power
+    /// of two between 16 s and 36 h
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        const MIN_POLL: i8 = 4;
+        const MAX_POLL: i8 = 17;
+        let log2 = interval.as_secs_f64().max(1.0).log2().round() as i8;
+
+        self.poll = log2.clamp(MIN_POLL, MAX_POLL);
+        self
+    }
+
+    /// When the server name resolves to both IPv6 and IPv4 addresses,
+    /// start an exchange over IPv6 right away and one over IPv4 after
+    /// `delay`, taking the first valid response.
+    /// By default only IPv4 addresses are used in that case
+    pub fn happy_eyeballs(mut self, delay: Duration) -> Self {
+        self.happy_eyeballs = Some(delay);
+        self
+    }
+
+    /// Cache resolved server addresses for `ttl`, resolving them again
+    /// once expired or when none of them answers.
+    /// The cache is shared with the clones of this client
+    pub fn dns_cache(mut self, ttl: Duration) -> Self {
+        self.dns_cache = Some(Arc::new(DnsCache::new(ttl)));
+        self
+    }
+
+    /// Wait at most `timeout` for the server name to resolve, or
+    /// indefinitely with `None`. Defaults to 5 s
+    pub fn dns_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.dns_timeout = timeout;
+        self
+    }
+
+    /// Wait at most `timeout` for the response. When a server name
+    /// resolves to several addresses, they are tried in turn within this
+    /// overall deadline, each given all the time left unless
+    /// [`NtpClient::split_timeout`] is enabled
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Give every address tried an even share of the time left instead
+    /// of all of it, so that an address accepting the request but never
+    /// answering leaves time for the next ones. Disabled by default
+    pub fn split_timeout(mut self, enabled: bool) -> Self {
+        self.split_timeout = enabled;
+        self
+    }
+
+    /// Send a random transmit timestamp instead of the local time.
+    /// The real transmit time is only kept locally, while the server
+    /// is still required to echo back the random value
+    pub fn privacy(mut self, enabled: bool) -> Self {
+        self.privacy = enabled;
+        self
+    }
+
+    /// Run every sanity check on the responses and fail with the whole
+    /// [`crate::ValidationReport`] of kind [`io::ErrorKind::InvalidData`],
+    /// instead of only reporting the first failed check
+    pub fn full_report(mut self, enabled: bool) -> Self {
+        self.full_report = enabled;
+        self
+    }
+
+    /// Select the sanity checks run on the responses.
+    /// Defaults to [`ValidationProfile::Strict`]
+    pub fn validation(mut self, profile: ValidationProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Select where responses may come from relative to the server
+    /// address. Defaults to [`SourceCheck::Strict`]
+    pub fn source_check(mut self, policy: SourceCheck) -> Self {
+        self.source_check = policy;
+        self
+    }
+
+    /// Returns the policy responses sources are checked with
+    pub(crate) fn source_policy(&self) -> SourceCheck {
+        self.source_check
+    }
+
+    /// Reject server times earlier than `not_before` or later than
+    /// `not_before + max_ahead` with an [`ImplausibleTime`] error
+    /// of kind [`io::ErrorKind::InvalidData`].
+    /// A sensible lower bound is the release or build time of the
+    /// application, as a correct clock can never be behind it
+    pub fn plausibility(
+        mut self,
+        not_before: SystemTime,
+        max_ahead: Duration,
+    ) -> Self {
+        self.plausibility = Some((not_before, not_before + max_ahead));
+        self
+    }
+
+    /// Hand every sent and received datagram to `tracer`
+    pub fn on_packet<F>(mut self, tracer: F) -> Self
+    where
+        F: Fn(&PacketTrace) + Send + Sync + 'static,
+    {
+        self.tracer = Some(Arc::new(tracer));
+        self
+    }
+
+    /// Log a hex dump and the decoded header of every sent and received
+    /// datagram at debug level
+    pub fn trace(self) -> Self {
+        self.on_packet(|trace| debug!("{}", trace))
+    }
+
+    /// Returns the counters of this client and its clones
+    pub fn stats(&self) -> ClientStats {
+        let counters = &self.counters;
+
+        ClientStats {
+            requests: counters.requests.load(Ordering::Relaxed),
+            responses: counters.responses.load(Ordering::Relaxed),
+            failures: counters.failures.load(Ordering::Relaxed),
+            cached: counters.cached.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the time to wait for a response
+    pub(crate) fn read_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the time to wait for name resolution
+    pub(crate) fn resolve_timeout(&self) -> Option<Duration> {
+        self.dns_timeout
+    }
+
+    pub(crate) fn trace_packet(
+        &self,
+        local: SocketAddr,
+        direction: Direction,
+        peer: SocketAddr,
+        bytes: &[u8],
+    ) {
+        if let Some(tracer) = &self.tracer {
+            tracer(&PacketTrace {
+                direction,
+                timestamp: get_ntp_timestamp(),
+                local: route_source(local, peer),
+                peer,
+                bytes: bytes.to_vec(),
+            });
+        }
+    }
+
+    fn check_plausibility(&self, result: &NtpResult) -> io::Result<()> {
+        let (not_before, not_after) = match self.plausibility {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let time =
+            UNIX_EPOCH + Duration::new(u64::from(result.sec()), result.nsec());
+
+        if time < not_before || time > not_after {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                ImplausibleTime {
+                    time,
+                    not_before,
+                    not_after,
+                },
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, C> NtpClient<T, C>
+where
+    T: Transport + Clone + Send + 'static,
+    C: ClockSource + Clone + Send + 'static,
+{
+    /// Send request to a NTP server with the given address
+    /// and process the response.
+    /// Requests to an IP address make no heap allocation unless the
+    /// packets are traced, while names go through the system resolver
+    /// and the DNS cache, which do allocate
+    ///
+    /// * `pool` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn request(&self, pool: &str, port: u32) -> io::Result<NtpResult> {
+        self.request_detailed(pool, port).map(NtpResult::from)
+    }
+
+    /// Perform several exchanges with a NTP server, waiting `spacing`
+    /// between them, and summarize the successful ones
+    ///
+    /// * `pool` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    /// * `samples` - Number of exchanges to perform
+    /// * `spacing` - Time to wait between two exchanges
+    pub fn request_sampled(
+        &self,
+        pool: &str,
+        port: u32,
+        samples: usize,
+        spacing: Duration,
+    ) -> io::Result<SampledResult> {
+        let mut results = Vec::with_capacity(samples);
+        let mut last_err = None;
+
+        for i in 0..samples {
+            if i > 0 {
+                thread::sleep(spacing);
+            }
+
+            match self.request(pool, port) {
+                Ok(result) => results.push(result),
+                Err(err) => {
+                    debug!("Sample {} failed: {}", i, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        SampledResult::from_results(&results).ok_or_else(|| {
+            last_err.unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "No samples requested",
+                )
+            })
+        })
+    }
+
+    /// Returns the last result of the server if received less than
+    /// `max_age` ago, moved forward by the time elapsed since, or sends a
+    /// new request. Suits applications asking for the time often but
+    /// only needing it to the second. The results are shared with the
+    /// clones of this client
+    ///
+    /// * `pool` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn cached_request(
+        &self,
+        pool: &str,
+        port: u32,
+        max_age: Duration,
+    ) -> io::Result<NtpResult> {
+        let key = (pool.to_string(), port);
+
+        if let Some((at, result)) = self.results.lock().unwrap().get(&key) {
+            let age = at.elapsed();
+
+            if age < max_age {
+                debug!("Cached result of {}, {:?} old", pool, age);
+                self.counters.cached.fetch_add(1, Ordering::Relaxed);
+                return Ok(aged(result, age));
+            }
+        }
+
+        let result = self.request(pool, port)?;
+
+        self.results
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), result));
+
+        Ok(result)
+    }
+
+    /// Send request to a NTP server with the given address
+    /// and process the response, reporting details about the exchange
+    ///
+    /// * `pool` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn request_detailed(
+        &self,
+        pool: &str,
+        port: u32,
+    ) -> io::Result<DetailedResult> {
+        debug!("Pool: {}", pool);
+        let port = u16::try_from(port).map_err(|_| Error::InvalidPort(port))?;
+        let literal = pool.trim_start_matches('[').trim_end_matches(']');
+
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return self.request_addr(SocketAddr::new(ip, port));
+        }
+
+        if let Some(cache) = &self.dns_cache {
+            if let Some(dest) = cache.get(pool, port) {
+                match self.request_addrs(&dest) {
+                    Ok(result) => return Ok(result),
+                    Err(err) => {
+                        debug!("{}. Resolving {} again", err, pool);
+                        cache.invalidate(pool);
+                    }
+                }
+            }
+        }
+
+        let start = Instant::now();
+        let dest = resolve(pool, port, self.dns_timeout)?;
+        let dns = start.elapsed();
+
+        if let Some(cache) = &self.dns_cache {
+            cache.insert(pool, &dest);
+        }
+
+        let mut result = self.request_addrs(&dest)?;
+
+        result.timings.dns = dns;
+
+        Ok(result)
+    }
+
+    /// Send request to the server at the given address, without
+    /// resolving any name, and process the response
+    pub fn request_addr(&self, addr: SocketAddr) -> io::Result<DetailedResult> {
+        self.exchange(&[addr])
+    }
+
+    fn request_addrs(&self, dest: &[SocketAddr]) -> io::Result<DetailedResult> {
+        let has_v6 = dest.iter().any(SocketAddr::is_ipv6);
+        let has_v4 = dest.iter().any(SocketAddr::is_ipv4);
+
+        if !(has_v6 && has_v4) {
+            return self.exchange(dest);
+        }
+
+        let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+            dest.iter().partition(|addr| addr.is_ipv6());
+
+        match self.happy_eyeballs {
+            Some(delay) => self.race(v6, v4, delay),
+            // IPv6 is only used when it is the sole option
+            None => self.exchange(&v4),
+        }
+    }
+
+    /// Race an exchange over IPv6 with one over IPv4 started after `delay`,
+    /// or as soon as the IPv6 one fails, returning the first valid response
+    fn race(
+        &self,
+        v6: Vec<SocketAddr>,
+        v4: Vec<SocketAddr>,
+        delay: Duration,
+    ) -> io::Result<DetailedResult> {
+        let (result_tx, result_rx) = mpsc::channel();
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let client = self.clone();
+        let v6_result_tx = result_tx.clone();
+
+        thread::spawn(move || {
+            let result = client.exchange(&v6);
+
+            if result.is_err() {
+                let _ = failed_tx.send(());
+            }
+
+            let _ = v6_result_tx.send(result);
+        });
+
+        let client = self.clone();
+
+        thread::spawn(move || {
+            // The IPv6 exchange succeeded when it hung up without failing
+            if let Err(RecvTimeoutError::Disconnected) =
+                failed_rx.recv_timeout(delay)
+            {
+                return;
+            }
+
+            let _ = result_tx.send(client.exchange(&v4));
+        });
+
+        let mut last_err = None;
+
+        for result in result_rx.iter().take(2) {
+            match result {
+                Ok(result) => return Ok(result),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| Error::NotResponding.into()))
+    }
+
+    /// Send request to the given addresses in turn until one answers
+    /// within the timeout, giving each all the time left or an even
+    /// share of it, and process the response
+    fn exchange(&self, dest: &[SocketAddr]) -> io::Result<DetailedResult> {
+        let start = Instant::now();
+        let deadline = start + self.timeout;
+        let mut last_err = None;
+
+        for (i, &addr) in dest.iter().enumerate() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                break;
+            }
+
+            let timeout = if self.split_timeout {
+                remaining / (dest.len() - i) as u32
+            } else {
+                remaining
+            };
+            let retries = start.elapsed();
+
+            self.counters.requests.fetch_add(1, Ordering::Relaxed);
+
+            match self.exchange_addr(addr, timeout) {
+                Ok(mut result) => {
+                    self.counters.responses.fetch_add(1, Ordering::Relaxed);
+                    result.timings.retries = retries;
+                    result.timings.retransmissions = i as u32;
+                    return Ok(result);
+                }
+                Err(err) => {
+                    debug!("{}. Try another one", err);
+                    self.counters.failures.fetch_add(1, Ordering::Relaxed);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| Error::NotResponding.into()))
+    }
+
+    /// Send a fresh request to `addr` and process the response
+    fn exchange_addr(
+        &self,
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<DetailedResult> {
+        let start = Instant::now();
+        let (req, origin_timestamp) = self.new_request();
+        let raw = RawPacket::from(&req);
+        let mut buf = [0u8; NTP_PACKET_SIZE + MAX_MAC_SIZE];
+        let exchange = self.transport.exchange_checked(
+            &[addr],
+            &raw,
+            &mut buf,
+            timeout,
+            self.source_check,
+        )?;
+        let recv_timestamp = self.clock.now().to_bits();
+        let received = Instant::now();
+        let response = &buf[..exchange.size];
+        debug!("Response: {}", exchange.size);
+        self.trace_packet(
+            exchange.local,
+            Direction::Sent,
+            exchange.server,
+            &raw,
+        );
+        self.trace_packet(
+            exchange.local,
+            Direction::Received,
+            exchange.source,
+            response,
+        );
+
+        if !self.source_check.accepts(exchange.server, exchange.source) {
+            return Err(Error::AddressMismatch {
+                expected: exchange.server,
+                received: exchange.source,
+            }
+            .into());
+        }
+
+        let (result, timestamps) =
+            self.process(&req, origin_timestamp, response, recv_timestamp)?;
+
+        Ok(DetailedResult {
+            result,
+            server: exchange.server,
+            timings: Timings {
+                dns: Duration::ZERO,
+                send: exchange.sent - start,
+                wait: received - exchange.sent,
+                processing: received.elapsed(),
+                timeout: self.timeout,
+                ..Timings::default()
+            },
+            timestamps,
+            source_check: self.source_check,
+        })
+    }
+
+    /// Build a new request, returning it along with the local time
+    /// it was created at
+    pub(crate) fn new_request(&self) -> (NtpPacket, u64) {
+        let mut req = NtpPacket::new();
+
+        req.tx_timestamp = self.clock.now().to_bits();
+
+        let origin_timestamp = req.tx_timestamp;
+
+        req.poll = self.poll;
+
+        if self.privacy {
+            req.tx_timestamp = random_u64();
+        }
+
+        (req, origin_timestamp)
+    }
+
+    /// Validate the `response` datagram to `req` and compute the result
+    pub(crate) fn process(
+        &self,
+        req: &NtpPacket,
+        origin_timestamp: u64,
+        response: &[u8],
+        recv_timestamp: u64,
+    ) -> io::Result<(NtpResult, Timestamps)> {
+        let resp = parse_datagram(response)?;
+
+        let timestamps = Timestamps {
+            t1: NtpTimestamp::from(origin_timestamp),
+            t2: NtpTimestamp::from(resp.recv_timestamp),
+            t3: NtpTimestamp::from(resp.tx_timestamp),
+            t4: NtpTimestamp::from(recv_timestamp),
+        };
+        let result = process_response(
+            req,
+            &resp,
+            origin_timestamp,
+            recv_timestamp,
+            self.profile,
+        );
+
+        match result {
+            Ok(result) => {
+                debug!("{:?}", result);
+                self.check_plausibility(&result)?;
+                Ok((result, timestamps))
+            }
+            Err(report) if self.full_report => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, report))
+            }
+            Err(report) => Err(Error::Validation(report.violations[0]).into()),
+        }
+    }
+}
+
+/// Returns `result` with the server time moved forward by `age`
+fn aged(result: &NtpResult, age: Duration) -> NtpResult {
+    let nsec = u64::from(result.nsec()) + u64::from(age.subsec_nanos());
+    let nsec_in_sec = u64::from(NSEC_IN_SEC);
+    let sec = u64::from(result.sec()) + age.as_secs() + nsec / nsec_in_sec;
+
+    NtpResult {
+        sec: sec as u32,
+        nsec: (nsec % nsec_in_sec) as u32,
+        ..*result
+    }
+}
+
+impl Default for NtpClient {
+    fn default() -> Self {
+        NtpClient::new()
+    }
+}
+
+#[cfg(test)]
+mod ntpclient_tests {
+    use crate::fakeserver::FakeServer;
+    use crate::trace::Direction;
+    use crate::{ClientStats, DynNtpClient, NtpClient, NtpTimestamp};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn test_fall_through_silent_address() {
+        let silent: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let other: SocketAddr = "192.0.2.2:123".parse().unwrap();
+        let client = NtpClient::new()
+            .transport(FakeServer::new().silent(silent))
+            .timeout(Duration::from_millis(200));
+
+        // The silent address takes all the time
+        assert!(client.exchange(&[silent, other]).is_err());
+
+        let client = client.split_timeout(true);
+        let start = Instant::now();
+
+        let result = client.exchange(&[silent, other]).unwrap();
+        let timings = result.timings();
+
+        assert_eq!(other, result.server());
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(1, timings.retransmissions);
+        assert!(timings.retries >= Duration::from_millis(100));
+        assert!((0.5..1.0).contains(&timings.budget_used()));
+        assert!(client.exchange(&[silent]).is_err());
+    }
+
+    #[test]
+    fn test_race() {
+        let v6: SocketAddr = "[2001:db8::1]:123".parse().unwrap();
+        let v4: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let delay = Duration::from_millis(50);
+        let server = FakeServer::new();
+        let client = NtpClient::new().transport(server.clone());
+
+        assert_eq!(
+            v6,
+            client.race(vec![v6], vec![v4], delay).unwrap().server()
+        );
+        thread::sleep(delay * 2);
+        assert_eq!(1, server.exchanges());
+
+        // IPv4 starts after the delay while IPv6 gets no answer
+        let server = FakeServer::new().silent(v6);
+        let client = client
+            .transport(server.clone())
+            .timeout(Duration::from_millis(500));
+        let start = Instant::now();
+
+        assert_eq!(
+            v4,
+            client.race(vec![v6], vec![v4], delay).unwrap().server()
+        );
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(2, server.exchanges());
+    }
+
+    #[test]
+    fn test_cached_request() {
+        let silent: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let client =
+            NtpClient::new().transport(FakeServer::new().silent(silent));
+        let max_age = Duration::from_secs(60);
+        let first = client.cached_request("192.0.2.2", 123, max_age).unwrap();
+
+        // Clones share the results, the server is no longer reachable
+        let client = client
+            .clone()
+            .transport(
+                FakeServer::new().silent("192.0.2.2:123".parse().unwrap()),
+            )
+            .timeout(Duration::from_millis(50));
+        let cached = client.cached_request("192.0.2.2", 123, max_age).unwrap();
+
+        assert_eq!(first.offset(), cached.offset());
+        assert!((cached.sec(), cached.nsec()) >= (first.sec(), first.nsec()));
+        assert!(client
+            .cached_request("192.0.2.2", 123, Duration::ZERO)
+            .is_err());
+
+        assert_eq!(
+            ClientStats {
+                requests: 2,
+                responses: 1,
+                failures: 1,
+                cached: 1,
+            },
+            client.stats()
+        );
+    }
+
+    #[test]
+    fn test_privacy() {
+        let (req, origin) = NtpClient::new().privacy(true).new_request();
+
+        assert_ne!(origin, req.tx_timestamp);
+
+        // Server 10 s ahead, echoing the random transmit timestamp
+        let ahead = NtpTimestamp::from_system_time(SystemTime::now())
+            .wrapping_add(10 << 32);
+        let sent = Arc::new(Mutex::new(None));
+        let traced = sent.clone();
+        let client = NtpClient::new()
+            .privacy(true)
+            .transport(FakeServer::new().time(ahead.to_bits()))
+            .on_packet(move |trace| {
+                if trace.direction == Direction::Sent {
+                    *traced.lock().unwrap() = trace.packet();
+                }
+            });
+        let result = client.request_addr("192.0.2.1:123".parse().unwrap());
+        let result = result.unwrap();
+        let sent = sent.lock().unwrap().unwrap();
+
+        assert_ne!(result.timestamps().t1.to_bits(), sent.tx_timestamp);
+        assert!((result.result().offset() - 10_000_000).abs() < 1_000_000);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<NtpClient>();
+        assert_send_sync::<DynNtpClient>();
+        assert_send_sync::<crate::pool::Pool>();
+    }
+}
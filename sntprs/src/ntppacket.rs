@@ -1,50 +1,72 @@
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::fmt::Formatter;
 
-use crate::get_ntp_timestamp;
+use crate::error::Error;
+use crate::proto::{
+    LeapIndicator, Mode, Version, LI_MASK, LI_SHIFT, MODE_MASK, MODE_SHIFT,
+    VERSION_MASK, VERSION_SHIFT,
+};
+use crate::{clock_precision, get_ntp_timestamp, NtpShort};
 use log::debug;
 
+/// Size of an NTP packet without extension fields and MAC
 pub const NTP_PACKET_SIZE: usize = 48;
 
+/// Sizes of the MAC trailer (key identifier and message digest)
+/// a server configured with authentication may append to the packet
+pub const MAC_SIZES: [usize; 3] = [20, 24, 28];
+
+pub const MAX_MAC_SIZE: usize = 28;
+
+/// NTP packet as sent on the wire
 pub type RawPacket = [u8; NTP_PACKET_SIZE];
 
 
-//dividere li_vn_mode in tre campi e aggiornare la conversione da per raw bytes
 //dimensione è 48 bytes
+/// NTP packet header, fields in host byte order
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct NtpPacket {
+    /// Leap indicator, version number and mode, see [`NtpPacket::leap`],
+    /// [`NtpPacket::version`] and [`NtpPacket::mode`]
     pub li_vn_mode: u8,
+    /// Stratum of the sender clock
     pub stratum: u8,
+    /// Poll interval as a power of two in seconds
     pub poll: i8,
+    /// Precision of the sender clock as a power of two in seconds
     pub precision: i8,
+    /// Root delay in NTP short format (16.16)
     pub root_delay: u32,
+    /// Root dispersion in NTP short format (16.16)
     pub root_dispersion: u32,
+    /// Reference identifier
     pub ref_id: u32,
+    /// Reference timestamp in NTP timestamp format (32.32)
     pub ref_timestamp: u64,
+    /// Origin timestamp in NTP timestamp format (32.32)
     pub origin_timestamp: u64,
+    /// Receive timestamp in NTP timestamp format (32.32)
     pub recv_timestamp: u64,
+    /// Transmit timestamp in NTP timestamp format (32.32)
     pub tx_timestamp: u64,
 }
 
 
 impl NtpPacket {
     pub const NTP_TIMESTAMP_DELTA: u32 = 2_208_988_800u32;
-    const SNTP_CLIENT_MODE: u8 = 3;
-    const SNTP_VERSION: u8 = 4 << 3;
-    #[allow(dead_code)]
-    const LI_MASK: u8 = 0b0000_0011;
-    #[allow(dead_code)]
-    const VN_MASK: u8 = 0b0001_1100;
-    #[allow(dead_code)]
-    const MODE_MASK: u8 = 0b1110_0000;
 
+    /// Create new client request carrying the current time
     pub fn new() -> NtpPacket {
         let tx_timestamp = get_ntp_timestamp();
 
         debug!("{}", tx_timestamp);
 
-        NtpPacket {
-            li_vn_mode: NtpPacket::SNTP_CLIENT_MODE | NtpPacket::SNTP_VERSION,
+        let mut packet = NtpPacket {
+            li_vn_mode: 0,
             stratum: 0,
             poll: 0,
-            precision: 0,
+            precision: clock_precision(),
             root_delay: 0,
             root_dispersion: 0,
             ref_id: 0,
@@ -52,7 +74,103 @@ impl NtpPacket {
             origin_timestamp: 0,
             recv_timestamp: 0,
             tx_timestamp,
+        };
+
+        packet.set_version(Version::V4);
+        packet.set_mode(Mode::Client);
+        packet
+    }
+
+    /// Decode the header at the start of `buf`, returning the packet and
+    /// the number of extension field and MAC bytes following it
+    pub fn parse(buf: &[u8]) -> Result<(NtpPacket, usize), Error> {
+        if buf.len() < NTP_PACKET_SIZE {
+            return Err(Error::PacketSize(buf.len()));
         }
+
+        let packet = NtpPacket::from(*array_ref![buf, 0, NTP_PACKET_SIZE]);
+
+        Ok((packet, buf.len() - NTP_PACKET_SIZE))
+    }
+
+    /// Returns the leap indicator
+    pub fn leap(&self) -> LeapIndicator {
+        let leap = (self.li_vn_mode & LI_MASK) >> LI_SHIFT;
+
+        LeapIndicator::try_from(leap).unwrap_or(LeapIndicator::Unsynchronized)
+    }
+
+    /// Returns the version number, as sent: it may not be a known
+    /// [`Version`]
+    pub fn version(&self) -> u8 {
+        (self.li_vn_mode & VERSION_MASK) >> VERSION_SHIFT
+    }
+
+    /// Returns the mode
+    pub fn mode(&self) -> Mode {
+        let mode = (self.li_vn_mode & MODE_MASK) >> MODE_SHIFT;
+
+        Mode::try_from(mode).unwrap_or(Mode::Reserved)
+    }
+
+    /// Returns the root delay
+    pub fn root_delay(&self) -> NtpShort {
+        NtpShort::from_bits(self.root_delay)
+    }
+
+    /// Returns the root dispersion
+    pub fn root_dispersion(&self) -> NtpShort {
+        NtpShort::from_bits(self.root_dispersion)
+    }
+
+    /// Returns the root distance, half the root delay plus the root
+    /// dispersion
+    pub fn root_distance(&self) -> NtpShort {
+        let half_delay = NtpShort::from_bits(self.root_delay / 2);
+
+        half_delay.saturating_add(self.root_dispersion())
+    }
+
+    /// Set the leap indicator
+    pub fn set_leap(&mut self, leap: LeapIndicator) {
+        self.li_vn_mode =
+            (self.li_vn_mode & !LI_MASK) | ((leap as u8) << LI_SHIFT);
+    }
+
+    /// Set the version number
+    pub fn set_version(&mut self, version: Version) {
+        self.li_vn_mode = (self.li_vn_mode & !VERSION_MASK)
+            | ((version as u8) << VERSION_SHIFT);
+    }
+
+    /// Set the mode
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.li_vn_mode =
+            (self.li_vn_mode & !MODE_MASK) | ((mode as u8) << MODE_SHIFT);
+    }
+}
+
+impl Default for NtpPacket {
+    fn default() -> Self {
+        NtpPacket::new()
+    }
+}
+
+impl Debug for NtpPacket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NtpPacket")
+            .field("li_vn_mode", &self.li_vn_mode)
+            .field("stratum", &self.stratum)
+            .field("poll", &self.poll)
+            .field("precision", &self.precision)
+            .field("root_delay", &self.root_delay)
+            .field("root_dispersion", &self.root_dispersion)
+            .field("ref_id", &self.ref_id)
+            .field("ref_timestamp", &self.ref_timestamp)
+            .field("origin_timestamp", &self.origin_timestamp)
+            .field("recv_timestamp", &self.recv_timestamp)
+            .field("tx_timestamp", &self.tx_timestamp)
+            .finish()
     }
 }
 
@@ -63,17 +181,27 @@ impl From<RawPacket> for NtpPacket {
             stratum: val[1],
             poll: val[2] as i8,
             precision: val[3] as i8,
-            root_delay: u32::from_le_bytes(*array_ref![val, 4, 4]),
-            root_dispersion: u32::from_le_bytes(*array_ref![val, 8, 4]),
-            ref_id: u32::from_le_bytes(*array_ref![val, 12, 4]),
-            ref_timestamp: u64::from_le_bytes(*array_ref![val, 16, 8]),
-            origin_timestamp: u64::from_le_bytes(*array_ref![val, 24, 8]),
-            recv_timestamp: u64::from_le_bytes(*array_ref![val, 32, 8]),
-            tx_timestamp: u64::from_le_bytes(*array_ref![val, 40, 8]),
+            root_delay: u32::from_be_bytes(*array_ref![val, 4, 4]),
+            root_dispersion: u32::from_be_bytes(*array_ref![val, 8, 4]),
+            ref_id: u32::from_be_bytes(*array_ref![val, 12, 4]),
+            ref_timestamp: u64::from_be_bytes(*array_ref![val, 16, 8]),
+            origin_timestamp: u64::from_be_bytes(*array_ref![val, 24, 8]),
+            recv_timestamp: u64::from_be_bytes(*array_ref![val, 32, 8]),
+            tx_timestamp: u64::from_be_bytes(*array_ref![val, 40, 8]),
         }
     }
 }
 
+impl TryFrom<&[u8]> for NtpPacket {
+    type Error = Error;
+
+    /// Decode the header at the start of `buf`, ignoring the extension
+    /// fields and MAC following it
+    fn try_from(buf: &[u8]) -> Result<Self, Error> {
+        NtpPacket::parse(buf).map(|(packet, _)| packet)
+    }
+}
+
 impl From<&NtpPacket> for RawPacket {
     fn from(val: &NtpPacket) -> Self {
         let mut tmp_buf = [0u8; NTP_PACKET_SIZE];
@@ -1,9 +1,12 @@
 
 use std::fmt::Debug;
 use std::fmt::Formatter;
-use crate::NSEC_IN_SEC;
+use crate::{NtpShort, NSEC_IN_SEC};
+
+const USEC_IN_SEC: u64 = 1_000_000;
 
 /// SNTP request result representation
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct NtpResult {
     /// NTP server seconds value
     pub sec: u32,
@@ -13,6 +16,14 @@ pub struct NtpResult {
     pub roundtrip: u64,
     /// Offset of the current system time with one received from a NTP server
     pub offset: i64,
+    /// Stratum of the NTP server
+    pub(crate) stratum: u8,
+    /// Root delay reported by the NTP server, in NTP short format (16.16)
+    pub(crate) root_delay: u32,
+    /// Root dispersion reported by the NTP server, in NTP short format (16.16)
+    pub(crate) root_dispersion: u32,
+    /// Precision of the NTP server clock, as a power of two in seconds
+    pub(crate) precision: i8,
 }
 
 impl NtpResult {
@@ -25,13 +36,18 @@ impl NtpResult {
     pub fn new(sec: u32, nsec: u32, roundtrip: u64, offset: i64) -> Self {
         let residue = nsec / NSEC_IN_SEC;
         let nsec = nsec % NSEC_IN_SEC;
-        let sec = sec + residue;
+        // Seconds wrap around with the NTP era
+        let sec = sec.wrapping_add(residue);
 
         NtpResult {
             sec,
             nsec,
             roundtrip,
             offset,
+            stratum: 0,
+            root_delay: 0,
+            root_dispersion: 0,
+            precision: 0,
         }
     }
     /// Returns number of seconds reported by an NTP server
@@ -53,6 +69,44 @@ impl NtpResult {
     pub fn offset(&self) -> i64 {
         self.offset
     }
+
+    /// Returns stratum reported by an NTP server
+    pub fn stratum(&self) -> u8 {
+        self.stratum
+    }
+
+    /// Returns root delay reported by an NTP server
+    pub fn root_delay(&self) -> NtpShort {
+        NtpShort::from_bits(self.root_delay)
+    }
+
+    /// Returns root dispersion reported by an NTP server
+    pub fn root_dispersion(&self) -> NtpShort {
+        NtpShort::from_bits(self.root_dispersion)
+    }
+
+    /// Returns precision of the NTP server clock, as a power of two in
+    /// seconds
+    pub fn precision(&self) -> i8 {
+        self.precision
+    }
+
+    /// Returns maximum error of the system clock offset in microseconds,
+    /// accounting for half the roundtrip, the server's root delay and
+    /// dispersion and the server's clock precision
+    pub fn max_error(&self) -> u64 {
+        let short_to_usec =
+            |val: NtpShort| val.as_duration().as_micros() as u64;
+        // Clock precision coarser than 1 second is taken as 1 second
+        let precision = (2f64.powi(i32::from(self.precision.min(0)))
+            * USEC_IN_SEC as f64)
+            .ceil() as u64;
+
+        (self.roundtrip / 2)
+            .saturating_add(short_to_usec(self.root_delay()) / 2)
+            .saturating_add(short_to_usec(self.root_dispersion()))
+            .saturating_add(precision)
+    }
 }
 
 impl Debug for NtpResult {
@@ -62,6 +116,10 @@ impl Debug for NtpResult {
             .field("nsec", &self.nsec)
             .field("roundtrip", &self.roundtrip)
             .field("offset", &self.offset)
+            .field("stratum", &self.stratum)
+            .field("root_delay", &self.root_delay)
+            .field("root_dispersion", &self.root_dispersion)
+            .field("precision", &self.precision)
             .finish()
     }
 }
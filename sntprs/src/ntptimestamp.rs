@@ -0,0 +1,299 @@
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ntppacket::NtpPacket;
+use crate::NSEC_IN_SEC;
+
+const FRACTION_MASK: u64 = 0x0000_0000_ffff_ffff;
+const USEC_IN_SEC: i128 = 1_000_000;
+const HALF_UNIT: u64 = 1 << 31;
+const SHORT_FRACTION_MASK: u32 = 0x0000_ffff;
+
+/// NTP timestamp in 32.32 fixed point format (seconds and fraction)
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NtpTimestamp(u64);
+
+impl NtpTimestamp {
+    /// Create new timestamp from its 64-bit wire representation
+    pub fn from_bits(bits: u64) -> Self {
+        NtpTimestamp(bits)
+    }
+
+    /// Create new timestamp from a duration since the UNIX epoch,
+    /// wrapping around the era boundary
+    pub fn from_unix(since_epoch: Duration) -> Self {
+        let secs =
+            since_epoch.as_secs() + u64::from(NtpPacket::NTP_TIMESTAMP_DELTA);
+        let fraction = (u64::from(since_epoch.subsec_nanos()) << 32)
+            / u64::from(NSEC_IN_SEC);
+
+        NtpTimestamp((secs << 32) | fraction)
+    }
+
+    /// Create new timestamp from a system time. Times before the UNIX
+    /// epoch, as reported by devices with a dead RTC battery, map to
+    /// era 0 and saturate to zero before 1900
+    pub fn from_system_time(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => NtpTimestamp::from_unix(since_epoch),
+            Err(err) => {
+                let epoch = u64::from(NtpPacket::NTP_TIMESTAMP_DELTA) << 32;
+                let before = err.duration();
+                let fraction = (u64::from(before.subsec_nanos()) << 32)
+                    / u64::from(NSEC_IN_SEC);
+                let bits = before
+                    .as_secs()
+                    .checked_mul(1 << 32)
+                    .and_then(|secs| secs.checked_add(fraction))
+                    .map_or(0, |before| epoch.saturating_sub(before));
+
+                NtpTimestamp(bits)
+            }
+        }
+    }
+
+    /// Returns the 64-bit wire representation of the timestamp
+    pub fn to_bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the system time of the timestamp. Times before the UNIX
+    /// epoch are taken as belonging to era 1, which starts in 2036
+    pub fn to_system_time(self) -> SystemTime {
+        let secs = u64::from(self.seconds());
+        let delta = u64::from(NtpPacket::NTP_TIMESTAMP_DELTA);
+        let unix = if secs >= delta {
+            secs - delta
+        } else {
+            secs + (1 << 32) - delta
+        };
+
+        UNIX_EPOCH + Duration::new(unix, self.subsec_nanos())
+    }
+
+    /// Returns number of seconds since the start of the NTP era
+    pub fn seconds(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    /// Returns fraction of second in units of 2^-32 seconds
+    pub fn fraction(self) -> u32 {
+        (self.0 & FRACTION_MASK) as u32
+    }
+
+    /// Returns fraction of second in nanoseconds, rounded to the nearest
+    /// but kept below 1 second for fractions close to it
+    pub fn subsec_nanos(self) -> u32 {
+        let nanos = u64::from(self.fraction()) * u64::from(NSEC_IN_SEC);
+
+        (((nanos + HALF_UNIT) >> 32) as u32).min(NSEC_IN_SEC - 1)
+    }
+
+    /// Returns the signed difference `self - other` as a 32.32 fixed point
+    /// value. The difference is computed modulo 2^64, so it is correct
+    /// across an era rollover as long as the two timestamps are less than
+    /// 68 years apart
+    pub fn diff(self, other: NtpTimestamp) -> i64 {
+        self.0.wrapping_sub(other.0) as i64
+    }
+
+    /// Returns the timestamp moved by a signed 32.32 fixed point amount,
+    /// wrapping around the era boundary
+    pub fn wrapping_add(self, delta: i64) -> Self {
+        NtpTimestamp(self.0.wrapping_add(delta as u64))
+    }
+}
+
+impl From<u64> for NtpTimestamp {
+    fn from(bits: u64) -> Self {
+        NtpTimestamp::from_bits(bits)
+    }
+}
+
+impl From<NtpTimestamp> for u64 {
+    fn from(timestamp: NtpTimestamp) -> Self {
+        timestamp.to_bits()
+    }
+}
+
+impl Debug for NtpTimestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "NtpTimestamp({}.{:08x})",
+            self.seconds(),
+            self.fraction()
+        )
+    }
+}
+
+/// NTP short format in 16.16 fixed point (seconds and fraction), as
+/// used for the root delay and dispersion
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NtpShort(u32);
+
+impl NtpShort {
+    /// Create new value from its 32-bit wire representation
+    pub fn from_bits(bits: u32) -> Self {
+        NtpShort(bits)
+    }
+
+    /// Returns the 32-bit wire representation of the value
+    pub fn to_bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns number of whole seconds
+    pub fn seconds(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    /// Returns fraction of second in units of 2^-16 seconds
+    pub fn fraction(self) -> u16 {
+        (self.0 & SHORT_FRACTION_MASK) as u16
+    }
+
+    /// Returns the value as a duration, truncated to the nanosecond
+    pub fn as_duration(self) -> Duration {
+        let nanos = (u64::from(self.fraction()) * u64::from(NSEC_IN_SEC)) >> 16;
+
+        Duration::new(u64::from(self.seconds()), nanos as u32)
+    }
+
+    /// Returns the value in seconds
+    pub fn as_seconds_f64(self) -> f64 {
+        f64::from(self.0) / f64::from(1u32 << 16)
+    }
+
+    /// Returns the sum of the values, saturating at the largest one
+    pub fn saturating_add(self, other: NtpShort) -> Self {
+        NtpShort(self.0.saturating_add(other.0))
+    }
+}
+
+impl From<u32> for NtpShort {
+    fn from(bits: u32) -> Self {
+        NtpShort::from_bits(bits)
+    }
+}
+
+impl From<NtpShort> for u32 {
+    fn from(short: NtpShort) -> Self {
+        short.to_bits()
+    }
+}
+
+impl Debug for NtpShort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NtpShort({}.{:04x})", self.seconds(), self.fraction())
+    }
+}
+
+/// Converts a signed 32.32 fixed point value to microseconds,
+/// rounded to the nearest
+pub(crate) fn fixed_to_micros(value: i64) -> i64 {
+    ((i128::from(value) * USEC_IN_SEC + i128::from(HALF_UNIT)) >> 32) as i64
+}
+
+/// Converts a signed 32.32 fixed point value to nanoseconds,
+/// rounded to the nearest
+pub(crate) fn fixed_to_nanos(value: i64) -> i64 {
+    let nanos = i128::from(value) * i128::from(NSEC_IN_SEC);
+
+    ((nanos + i128::from(HALF_UNIT)) >> 32) as i64
+}
+
+/// Returns half of the sum of two signed 32.32 fixed point values,
+/// without overflowing
+pub(crate) fn half_sum(a: i64, b: i64) -> i64 {
+    ((i128::from(a) + i128::from(b)) / 2) as i64
+}
+
+#[cfg(test)]
+mod ntptimestamp_tests {
+    use crate::ntptimestamp::{
+        fixed_to_micros, fixed_to_nanos, half_sum, NtpShort, NtpTimestamp,
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_ntp_timestamp_diff() {
+        let early = NtpTimestamp::from_bits(10 << 32);
+        let late = NtpTimestamp::from_bits((12 << 32) | 0x8000_0000);
+
+        assert_eq!(0x2_8000_0000, late.diff(early));
+        assert_eq!(-0x2_8000_0000, early.diff(late));
+        assert_eq!(late, early.wrapping_add(0x2_8000_0000));
+    }
+
+    #[test]
+    fn test_ntp_timestamp_era_rollover() {
+        let before = NtpTimestamp::from_bits(u64::MAX - (1 << 32) + 1);
+        let after = NtpTimestamp::from_bits(1 << 32);
+
+        assert_eq!(2 << 32, after.diff(before));
+        assert_eq!(i64::MAX, half_sum(i64::MAX, i64::MAX));
+    }
+
+    #[test]
+    fn test_ntp_timestamp_conversions() {
+        let timestamp =
+            NtpTimestamp::from_unix(Duration::new(1_000, 500_000_000));
+
+        assert_eq!(2_208_989_800, timestamp.seconds());
+        assert_eq!(0x8000_0000, timestamp.fraction());
+        assert_eq!(500_000_000, timestamp.subsec_nanos());
+        assert_eq!(
+            999_999_999,
+            NtpTimestamp::from_bits(0xffff_ffff).subsec_nanos()
+        );
+        assert_eq!(1_500_000, fixed_to_micros(0x1_8000_0000));
+        assert_eq!(-1_500_000, fixed_to_micros(-0x1_8000_0000));
+        assert_eq!(1_500_000_001, fixed_to_nanos(0x1_8000_0005));
+        assert_eq!(-1_500_000_001, fixed_to_nanos(-0x1_8000_0005));
+    }
+
+    #[test]
+    fn test_ntp_timestamp_before_unix_epoch() {
+        let before = UNIX_EPOCH - Duration::new(1_000, 500_000_000);
+        let timestamp = NtpTimestamp::from_system_time(before);
+
+        assert_eq!(2_208_987_799, timestamp.seconds());
+        assert_eq!(0x8000_0000, timestamp.fraction());
+
+        let ancient = UNIX_EPOCH - Duration::from_secs(3_000_000_000);
+
+        assert_eq!(0, NtpTimestamp::from_system_time(ancient).to_bits());
+    }
+
+    #[test]
+    fn test_ntp_timestamp_to_system_time() {
+        let time = UNIX_EPOCH + Duration::new(1_000, 500_000_000);
+        let era1 = NtpTimestamp::from_bits(1 << 32);
+
+        assert_eq!(time, NtpTimestamp::from_system_time(time).to_system_time());
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(2_085_978_497),
+            era1.to_system_time()
+        );
+    }
+
+    #[test]
+    fn test_ntp_short() {
+        let short = NtpShort::from_bits(0x0001_8000);
+
+        assert_eq!(1, short.seconds());
+        assert_eq!(0x8000, short.fraction());
+        assert_eq!(Duration::from_millis(1_500), short.as_duration());
+        assert_eq!(1.5, short.as_seconds_f64());
+        assert_eq!(
+            Duration::from_nanos(15_258),
+            NtpShort::from_bits(1).as_duration()
+        );
+        assert_eq!(
+            NtpShort::from_bits(u32::MAX),
+            short.saturating_add(NtpShort::from_bits(u32::MAX))
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! pcap export of NTP exchanges
+//!
+//! [`PcapWriter`] stores traced datagrams in the classic pcap format,
+//! synthesizing the IP and UDP headers, so that exchanges can be inspected
+//! with Wireshark without running a separate capture
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::sync::Mutex;
+//!
+//! use sntprs::pcap::PcapWriter;
+//!
+//! let file = File::create("ntp.pcap").unwrap();
+//! let writer = Mutex::new(PcapWriter::new(file).unwrap());
+//! let client = sntprs::NtpClient::new().on_packet(move |trace| {
+//!     let _ = writer.lock().unwrap().write_trace(trace);
+//! });
+//!
+//! let result = client.request("time.google.com", 123);
+//! ```
+
+use std::io;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::ntppacket::NtpPacket;
+use crate::trace::{Direction, PacketTrace};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 65_535;
+const LINKTYPE_RAW: u32 = 101;
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const UDP_HEADER_SIZE: usize = 8;
+const IP_PROTO_UDP: u8 = 17;
+const IP_TTL: u8 = 64;
+
+/// Writer of traced datagrams in pcap format
+pub struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Create new writer, emitting the pcap file header
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+        Ok(PcapWriter { out })
+    }
+
+    /// Append a traced datagram as an IP/UDP packet
+    pub fn write_trace(&mut self, trace: &PacketTrace) -> io::Result<()> {
+        let (src, dst) = match trace.direction {
+            Direction::Sent => (trace.local, trace.peer),
+            Direction::Received => (trace.peer, trace.local),
+        };
+        let packet = match (src.ip(), dst.ip()) {
+            (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+                ipv4_packet(src_ip, dst_ip, &udp_datagram(src, dst, trace))
+            }
+            (src_ip, dst_ip) => {
+                let src_ip = to_ipv6(src_ip);
+                let dst_ip = to_ipv6(dst_ip);
+                let mut udp = udp_datagram(src, dst, trace);
+                let checksum = udp_checksum_ipv6(src_ip, dst_ip, &udp);
+
+                udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+                ipv6_packet(src_ip, dst_ip, &udp)
+            }
+        };
+        let secs = (trace.timestamp >> 32) as u32;
+        let secs = secs.wrapping_sub(NtpPacket::NTP_TIMESTAMP_DELTA);
+        let usecs = ((trace.timestamp & 0xffff_ffff) * 1_000_000) >> 32;
+
+        self.out.write_all(&secs.to_le_bytes())?;
+        self.out.write_all(&(usecs as u32).to_le_bytes())?;
+        self.out.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.out.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.out.write_all(&packet)?;
+        self.out.flush()
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn udp_datagram(
+    src: SocketAddr,
+    dst: SocketAddr,
+    trace: &PacketTrace,
+) -> Vec<u8> {
+    let len = (UDP_HEADER_SIZE + trace.bytes.len()) as u16;
+    let mut udp = Vec::with_capacity(len as usize);
+
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&len.to_be_bytes());
+    // Checksum is optional over IPv4
+    udp.extend_from_slice(&0u16.to_be_bytes());
+    udp.extend_from_slice(&trace.bytes);
+
+    udp
+}
+
+fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+    let len = (IPV4_HEADER_SIZE + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(len as usize);
+
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0x40, 0, IP_TTL, IP_PROTO_UDP, 0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+
+    let checksum = checksum(&packet, 0);
+
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+fn ipv6_packet(src: Ipv6Addr, dst: Ipv6Addr, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(IPV6_HEADER_SIZE + payload.len());
+
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[IP_PROTO_UDP, IP_TTL]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+fn udp_checksum_ipv6(src: Ipv6Addr, dst: Ipv6Addr, udp: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(40);
+
+    pseudo.extend_from_slice(&src.octets());
+    pseudo.extend_from_slice(&dst.octets());
+    pseudo.extend_from_slice(&(udp.len() as u32).to_be_bytes());
+    pseudo.extend_from_slice(&[0, 0, 0, IP_PROTO_UDP]);
+
+    match checksum(udp, sum_words(&pseudo)) {
+        0 => 0xffff,
+        sum => sum,
+    }
+}
+
+fn sum_words(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|word| match *word {
+            [hi, lo] => u32::from(u16::from_be_bytes([hi, lo])),
+            [hi] => u32::from(hi) << 8,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial + sum_words(data);
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod pcap_tests {
+    use crate::pcap::PcapWriter;
+    use crate::trace::{Direction, PacketTrace};
+
+    #[test]
+    fn test_pcap_ipv4_record() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        let trace = PacketTrace {
+            direction: Direction::Sent,
+            timestamp: 2_208_988_800u64 << 32,
+            local: "10.0.0.1:40000".parse().unwrap(),
+            peer: "10.0.0.2:123".parse().unwrap(),
+            bytes: vec![0x23; 48],
+        };
+
+        writer.write_trace(&trace).unwrap();
+
+        let out = writer.into_inner();
+        let record = &out[24..];
+        let ip = &record[16..];
+
+        assert_eq!(24 + 16 + 20 + 8 + 48, out.len());
+        assert_eq!([0, 0, 0, 0], record[0..4]);
+        assert_eq!(0x45, ip[0]);
+        assert_eq!([10, 0, 0, 1, 10, 0, 0, 2], ip[12..20]);
+        assert_eq!(0, super::checksum(&ip[0..20], 0));
+        assert_eq!(40000u16.to_be_bytes(), ip[20..22]);
+        assert_eq!(123u16.to_be_bytes(), ip[22..24]);
+    }
+}
@@ -0,0 +1,466 @@
+//! Server pool
+//!
+//! [`Pool`] resolves a pool hostname such as `pool.ntp.org` into a set
+//! of servers and polls them all. Every server is scored over time from
+//! its reachability, its delay compared to the fastest server and the
+//! agreement of its offset with the median one. Once its reachability
+//! register is full, a server scoring too low is demoted and replaced by
+//! a fresh address, obtained by resolving the hostname again, at most
+//! once per re-resolution interval. A demoted address may come back
+//! after four re-resolution intervals, and no server is demoted when
+//! all of them fail together, pointing to a local network outage.
+//!
+//! A pool created with [`Pool::zone`] follows the pool.ntp.org
+//! guidelines and spreads the lookups over the numbered subzones of a
+//! vendor zone, `0.vendor.pool.ntp.org` to `3.vendor.pool.ntp.org`
+//!
+//! ```rust,no_run
+//! use sntprs::pool::Pool;
+//!
+//! let mut pool = Pool::new("pool.ntp.org", 123).size(4);
+//!
+//! if let Ok(result) = pool.poll() {
+//!     println!("Offset: {}", result.offset());
+//! }
+//!
+//! for server in pool.servers() {
+//!     println!("{} reach {} score {:.2}", server.addr, server.reach, server.score);
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::monitor::Reach;
+use crate::resolver::resolve;
+use crate::{Error, NtpClient, NtpResult};
+
+const DEFAULT_SIZE: usize = 4;
+const DEFAULT_MIN_SCORE: f64 = 0.5;
+const DEFAULT_RESOLVE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Numbered subzones of a pool.ntp.org zone
+const SUBZONES: usize = 4;
+/// Polls after which the reachability register is full
+const REACH_POLLS: u32 = 8;
+/// Offset distance to the median halving the agreement score, in µs
+const AGREEMENT_SCALE: f64 = 10_000.0;
+/// Re-resolution intervals after which a demoted address may be used
+const DEMOTION_INTERVALS: u32 = 4;
+
+/// State of a server of a [`Pool`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolServer {
+    /// Address of the server
+    pub addr: SocketAddr,
+    /// Reachability register
+    pub reach: Reach,
+    /// Last measured roundtrip in microseconds
+    pub delay: Option<u64>,
+    /// Last measured offset in microseconds
+    pub offset: Option<i64>,
+    /// Health score, between 0 and 1
+    pub score: f64,
+    /// Number of polls
+    pub polls: u32,
+}
+
+impl PoolServer {
+    fn new(addr: SocketAddr) -> Self {
+        PoolServer {
+            addr,
+            reach: Reach::default(),
+            delay: None,
+            offset: None,
+            score: 0.0,
+            polls: 0,
+        }
+    }
+}
+
+/// Set of servers resolved from a pool hostname, rotated by health
+pub struct Pool {
+    hostnames: Vec<String>,
+    port: u32,
+    client: NtpClient,
+    size: usize,
+    min_score: f64,
+    resolve_interval: Duration,
+    resolved_at: Option<Instant>,
+    servers: Vec<PoolServer>,
+    /// Demoted addresses, with the time of their demotion
+    demoted: HashMap<IpAddr, Instant>,
+}
+
+impl Pool {
+    /// Create new pool of four servers resolved from `hostname`
+    /// Args:
+    /// * `hostname` - Pool hostname, e.g. `pool.ntp.org`
+    /// * `port` - Servers' port as an int
+    pub fn new(hostname: &str, port: u32) -> Self {
+        Pool {
+            hostnames: vec![hostname.to_string()],
+            port,
+            client: NtpClient::new(),
+            size: DEFAULT_SIZE,
+            min_score: DEFAULT_MIN_SCORE,
+            resolve_interval: DEFAULT_RESOLVE_INTERVAL,
+            resolved_at: None,
+            servers: Vec::new(),
+            demoted: HashMap::new(),
+        }
+    }
+
+    /// Create new pool of four servers resolved from the numbered
+    /// subzones of a pool.ntp.org zone
+    /// Args:
+    /// * `zone` - Vendor zone, e.g. `vendor.pool.ntp.org`
+    /// * `port` - Servers' port as an int
+    pub fn zone(zone: &str, port: u32) -> Self {
+        Pool {
+            hostnames: (0..SUBZONES)
+                .map(|i| format!("{}.{}", i, zone))
+                .collect(),
+            ..Pool::new(zone, port)
+        }
+    }
+
+    /// Use the given client for the requests
+    pub fn client(mut self, client: NtpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Number of distinct servers to poll, at least 1
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// Score under which a server is demoted, 0.5 by default
+    pub fn min_score(mut self, score: f64) -> Self {
+        self.min_score = score;
+        self
+    }
+
+    /// Minimum time between two resolutions of the hostname, one hour
+    /// by default
+    pub fn resolve_interval(mut self, interval: Duration) -> Self {
+        self.resolve_interval = interval;
+        self
+    }
+
+    /// Returns the servers currently polled
+    pub fn servers(&self) -> &[PoolServer] {
+        &self.servers
+    }
+
+    /// Poll every server, then rotate the poor performers out. Returns
+    /// the result of the best scoring server among those that answered
+    pub fn poll(&mut self) -> io::Result<NtpResult> {
+        if self.servers.len() < self.size {
+            self.refill()?;
+        }
+
+        let mut results = Vec::with_capacity(self.servers.len());
+        let mut last_err = None;
+
+        for server in &mut self.servers {
+            let result = self.client.request_addr(server.addr);
+
+            server.polls += 1;
+            server.reach.update(result.is_ok());
+
+            match result {
+                Ok(result) => {
+                    server.delay = Some(result.result().roundtrip());
+                    server.offset = Some(result.result().offset());
+                    results.push((server.addr, result.result));
+                }
+                Err(err) => {
+                    debug!("{}: {}", server.addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        self.rescore();
+
+        let best = results.into_iter().max_by(|(a, _), (b, _)| {
+            self.score_of(*a).total_cmp(&self.score_of(*b))
+        });
+
+        self.demote();
+
+        if let Err(err) = self.refill() {
+            debug!("Unable to refill the pool: {}", err);
+        }
+
+        match best {
+            Some((_, result)) => Ok(result),
+            None => {
+                Err(last_err.unwrap_or_else(|| Error::NotResponding.into()))
+            }
+        }
+    }
+
+    fn score_of(&self, addr: SocketAddr) -> f64 {
+        self.servers
+            .iter()
+            .find(|server| server.addr == addr)
+            .map_or(0.0, |server| server.score)
+    }
+
+    /// Score every server from its reachability, its delay relative to
+    /// the fastest server and its distance to the median offset
+    fn rescore(&mut self) {
+        let reachable = self.servers.iter().filter(|s| s.reach.bits() & 1 == 1);
+        let mut offsets: Vec<i64> =
+            reachable.filter_map(|s| s.offset).collect();
+        let best_delay = self
+            .servers
+            .iter()
+            .filter_map(|server| server.delay)
+            .min()
+            .unwrap_or(0);
+
+        offsets.sort_unstable();
+
+        let median = offsets.get(offsets.len().saturating_sub(1) / 2).copied();
+
+        for server in &mut self.servers {
+            let delay = server.delay.map_or(0.0, |delay| {
+                (best_delay.max(1) as f64 / delay.max(1) as f64).min(1.0)
+            });
+            let agreement = match (server.offset, median) {
+                (Some(offset), Some(median)) => {
+                    let distance = (offset - median).unsigned_abs() as f64;
+
+                    1.0 / (1.0 + distance / AGREEMENT_SCALE)
+                }
+                _ => 0.0,
+            };
+            let reach = f64::from(server.reach.count()) / 8.0;
+
+            server.score = reach * (delay + agreement) / 2.0;
+        }
+    }
+
+    /// Drop the servers polled long enough whose score is too low,
+    /// unless none of the servers answered the last poll
+    fn demote(&mut self) {
+        let min_score = self.min_score;
+        let demoted = &mut self.demoted;
+
+        if self.servers.iter().all(|s| s.reach.bits() & 1 == 0) {
+            return;
+        }
+
+        self.servers.retain(|server| {
+            if server.polls < REACH_POLLS || server.score >= min_score {
+                return true;
+            }
+
+            debug!("Demoting {}, score {:.2}", server.addr, server.score);
+            demoted.insert(server.addr.ip(), Instant::now());
+            false
+        });
+    }
+
+    /// Resolve the hostnames again to bring the pool back to its size
+    /// with addresses neither polled nor demoted, unless they were
+    /// resolved less than the re-resolution interval ago. Failed
+    /// resolutions are retried at the next poll
+    fn refill(&mut self) -> io::Result<()> {
+        let recent = self
+            .resolved_at
+            .is_some_and(|at| at.elapsed() < self.resolve_interval);
+
+        if self.servers.len() >= self.size || recent {
+            return Ok(());
+        }
+
+        let port = u16::try_from(self.port)
+            .map_err(|_| Error::InvalidPort(self.port))?;
+        let timeout = self.client.resolve_timeout();
+        let mut resolved = Vec::with_capacity(self.hostnames.len());
+        let mut last_err = None;
+        let expiry = self.resolve_interval * DEMOTION_INTERVALS;
+
+        self.demoted.retain(|_, at| at.elapsed() < expiry);
+
+        for hostname in &self.hostnames {
+            match resolve(hostname, port, timeout) {
+                Ok(addrs) => {
+                    self.resolved_at = Some(Instant::now());
+                    resolved.push(addrs.into_iter());
+                }
+                Err(err) => {
+                    debug!("Unable to resolve {}: {}", hostname, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        // Take the addresses of the subzones in turn
+        while self.servers.len() < self.size {
+            let round: Vec<SocketAddr> =
+                resolved.iter_mut().filter_map(Iterator::next).collect();
+
+            if round.is_empty() {
+                break;
+            }
+
+            for addr in round {
+                let known =
+                    self.servers.iter().any(|s| s.addr.ip() == addr.ip());
+
+                if self.servers.len() < self.size
+                    && !known
+                    && !self.demoted.contains_key(&addr.ip())
+                {
+                    debug!("Adding {} to the pool", addr);
+                    self.servers.push(PoolServer::new(addr));
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if self.servers.is_empty() => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod pool_tests {
+    use crate::pool::{Pool, PoolServer};
+    use crate::NtpClient;
+    use std::net::SocketAddr;
+    use std::thread;
+    use std::time::Duration;
+
+    fn server(addr: &str, reach: u8, delay: u64, offset: i64) -> PoolServer {
+        let mut server = PoolServer::new(addr.parse().unwrap());
+
+        for bit in (0..8).rev() {
+            server.reach.update(reach & (1 << bit) != 0);
+        }
+
+        server.delay = Some(delay);
+        server.offset = Some(offset);
+        server.polls = 8;
+        server
+    }
+
+    #[test]
+    fn test_pool_rotation() {
+        let mut pool = Pool::new("10.0.0.9", 123).size(4);
+
+        pool.servers = vec![
+            server("10.0.0.1:123", 0xff, 20_000, 1_000),
+            server("10.0.0.2:123", 0xff, 40_000, 1_500),
+            server("10.0.0.3:123", 0x07, 20_000, 1_000),
+            server("10.0.0.4:123", 0xff, 20_000, 900_000),
+        ];
+        pool.rescore();
+
+        let scores: Vec<f64> = pool.servers.iter().map(|s| s.score).collect();
+
+        assert!((scores[0] - 1.0).abs() < 1e-9);
+        assert!(scores[1] < scores[0] && scores[1] > 0.5);
+        assert!(scores[2] < 0.5);
+        assert!(scores[3] < 0.6);
+
+        pool.min_score = 0.6;
+        pool.demote();
+        pool.refill().unwrap();
+
+        let addrs: Vec<SocketAddr> =
+            pool.servers.iter().map(|server| server.addr).collect();
+
+        assert_eq!(
+            vec![
+                "10.0.0.1:123".parse::<SocketAddr>().unwrap(),
+                "10.0.0.2:123".parse().unwrap(),
+                "10.0.0.9:123".parse().unwrap(),
+            ],
+            addrs
+        );
+        assert_eq!(2, pool.demoted.len());
+    }
+
+    #[test]
+    fn test_pool_demotion() {
+        let mut pool = Pool::new("10.0.0.1", 123)
+            .size(2)
+            .min_score(0.6)
+            .resolve_interval(Duration::from_millis(20));
+
+        // A local outage: every server failed the last poll
+        pool.servers = vec![
+            server("10.0.0.1:123", 0x0e, 20_000, 1_000),
+            server("10.0.0.2:123", 0x7e, 20_000, 1_000),
+        ];
+        pool.rescore();
+        pool.demote();
+
+        assert_eq!(2, pool.servers.len());
+
+        pool.servers[1].reach.update(true);
+        pool.rescore();
+        pool.demote();
+
+        assert_eq!(1, pool.servers.len());
+        assert!(pool.demoted.contains_key(&"10.0.0.1".parse().unwrap()));
+
+        // Back in the pool once the demotion expired
+        pool.refill().unwrap();
+
+        assert_eq!(1, pool.servers.len());
+
+        thread::sleep(Duration::from_millis(100));
+        pool.refill().unwrap();
+
+        assert_eq!(2, pool.servers.len());
+        assert!(pool.demoted.is_empty());
+    }
+
+    #[test]
+    fn test_pool_zone() {
+        let mut pool = Pool::zone("vendor.pool.ntp.org", 123);
+
+        assert_eq!("2.vendor.pool.ntp.org", pool.hostnames[2]);
+
+        pool.hostnames = vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()];
+        pool.refill().unwrap();
+
+        assert_eq!(2, pool.servers.len());
+
+        // Not resolved again within the interval
+        pool.servers.clear();
+        pool.refill().unwrap();
+
+        assert!(pool.servers.is_empty());
+    }
+
+    #[test]
+    fn test_pool_resolve_failure() {
+        let client =
+            NtpClient::new().dns_timeout(Some(Duration::from_millis(100)));
+        let mut pool = Pool::new("sntprs.invalid", 123).client(client);
+
+        assert!(pool.refill().is_err());
+        assert_eq!(None, pool.resolved_at);
+
+        // Retried at once
+        pool.hostnames = vec!["10.0.0.1".to_string()];
+        pool.refill().unwrap();
+
+        assert_eq!(1, pool.servers.len());
+    }
+}
@@ -0,0 +1,239 @@
+//! Pulse per second input
+//!
+//! A PPS signal marks the start of every second with sub-microsecond
+//! precision but carries no time of day, while NTP servers or a GPS
+//! receiver tell the time of day with a much coarser accuracy.
+//! [`PpsSource`] combines the two: the coarse source picks the second
+//! the latest pulse started, the pulse gives the exact offset of the
+//! local clock, which can then feed the [`Discipline`] loop.
+//! On Linux, [`KernelPps`] reads the pulses from the kernel PPS API
+//!
+//! [`Discipline`]: crate::discipline::Discipline
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use sntprs::discipline::Discipline;
+//! use sntprs::pps::{KernelPps, PpsSource};
+//! use sntprs::source::{NtpSource, TimeSource};
+//!
+//! let pps = KernelPps::open("/dev/pps0").unwrap();
+//! let source = PpsSource::new(NtpSource::new("pool.ntp.org", 123), pps);
+//! let mut discipline = Discipline::new();
+//!
+//! if let Ok(sample) = source.sample() {
+//!     discipline.add_sample(sample.offset);
+//! }
+//! ```
+
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::source::{Provenance, TimeSample, TimeSource, Trust};
+
+const USEC_IN_SEC: i64 = 1_000_000;
+const DEFAULT_PULSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default maximum error of the pulse timestamps, in microseconds
+pub const DEFAULT_PULSE_ERROR: u64 = 1;
+
+/// Input of the pulses marking the start of every second
+pub trait PpsInput {
+    /// Wait at most `timeout` for the next pulse, returning the local
+    /// time it was captured at
+    fn pulse(&self, timeout: Duration) -> io::Result<SystemTime>;
+}
+
+/// Returns the offset of the local clock in microseconds at the pulse
+/// captured at `pulse`, the pulse starting the second closest to the
+/// time told by `coarse_offset`, or `None` if the coarse offset is
+/// not known within half a second
+pub fn pulse_offset(
+    pulse: SystemTime,
+    coarse_offset: i64,
+    coarse_error: u64,
+) -> Option<i64> {
+    if coarse_error >= USEC_IN_SEC as u64 / 2 {
+        return None;
+    }
+
+    let local = match pulse.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_micros() as i64,
+        Err(err) => -(err.duration().as_micros() as i64),
+    };
+    let coarse = local + coarse_offset;
+    let second = (coarse + USEC_IN_SEC / 2).div_euclid(USEC_IN_SEC);
+
+    Some(second * USEC_IN_SEC - local)
+}
+
+/// Time source refining the offset measured by a coarse source with
+/// the pulses of a PPS input
+pub struct PpsSource<S, P> {
+    coarse: S,
+    input: P,
+    timeout: Duration,
+    pulse_error: u64,
+}
+
+impl<S: TimeSource, P: PpsInput> PpsSource<S, P> {
+    /// Create new source numbering the pulses of `input` with `coarse`
+    pub fn new(coarse: S, input: P) -> Self {
+        PpsSource {
+            coarse,
+            input,
+            timeout: DEFAULT_PULSE_TIMEOUT,
+            pulse_error: DEFAULT_PULSE_ERROR,
+        }
+    }
+
+    /// Wait at most `timeout` for a pulse. Defaults to 2 s
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the maximum error of the pulse timestamps in microseconds,
+    /// including the latency of their capture. Defaults to 1 µs
+    pub fn pulse_error(mut self, error: u64) -> Self {
+        self.pulse_error = error;
+        self
+    }
+}
+
+impl<S: TimeSource, P: PpsInput> TimeSource for PpsSource<S, P> {
+    fn name(&self) -> String {
+        format!("pps+{}", self.coarse.name())
+    }
+
+    fn trust(&self) -> Trust {
+        self.coarse.trust()
+    }
+
+    fn provenance(&self) -> Provenance {
+        Provenance::ReferenceClock
+    }
+
+    fn sample(&self) -> io::Result<TimeSample> {
+        let coarse = self.coarse.sample()?;
+        let pulse = self.input.pulse(self.timeout)?;
+        let offset = pulse_offset(pulse, coarse.offset, coarse.max_error)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Coarse time too uncertain to number the pulses",
+                )
+            })?;
+
+        debug!("PPS offset: {} us, coarse {} us", offset, coarse.offset);
+
+        Ok(TimeSample {
+            source: self.name(),
+            offset,
+            max_error: self.pulse_error,
+            trust: coarse.trust,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::kernel::KernelPps;
+
+#[cfg(target_os = "linux")]
+mod kernel {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::PpsInput;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct PpsKtime {
+        sec: i64,
+        nsec: i32,
+        flags: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct PpsKinfo {
+        assert_sequence: u32,
+        clear_sequence: u32,
+        assert_tu: PpsKtime,
+        clear_tu: PpsKtime,
+        current_mode: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct PpsFdata {
+        info: PpsKinfo,
+        timeout: PpsKtime,
+    }
+
+    /// `_IOWR('p', 0xa4, struct pps_fdata *)`, sized after the pointer
+    /// as declared by the kernel headers
+    const PPS_FETCH: libc::Ioctl =
+        libc::_IOWR::<*const PpsFdata>(b'p' as u32, 0xa4);
+
+    /// PPS input read through the kernel PPS API, e.g. `/dev/pps0`
+    pub struct KernelPps {
+        device: File,
+    }
+
+    impl KernelPps {
+        /// Open the given PPS device
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            Ok(KernelPps {
+                device: File::open(path)?,
+            })
+        }
+    }
+
+    impl PpsInput for KernelPps {
+        fn pulse(&self, timeout: Duration) -> io::Result<SystemTime> {
+            let mut data = PpsFdata::default();
+
+            data.timeout.sec = timeout.as_secs() as i64;
+            data.timeout.nsec = timeout.subsec_nanos() as i32;
+
+            // SAFETY: PPS_FETCH reads and writes a pps_fdata structure
+            let res = unsafe {
+                libc::ioctl(
+                    self.device.as_raw_fd(),
+                    PPS_FETCH,
+                    &mut data as *mut PpsFdata,
+                )
+            };
+
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let assert = data.info.assert_tu;
+
+            Ok(UNIX_EPOCH
+                + Duration::new(assert.sec as u64, assert.nsec as u32))
+        }
+    }
+}
+
+#[cfg(test)]
+mod pps_tests {
+    use crate::pps::pulse_offset;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_pulse_offset() {
+        // Pulse captured 2.3 ms after a local second boundary
+        let pulse = UNIX_EPOCH + Duration::new(1_000, 2_300_000);
+
+        assert_eq!(Some(-2_300), pulse_offset(pulse, 40_000, 100_000));
+        assert_eq!(Some(997_700), pulse_offset(pulse, 900_000, 100_000));
+        assert_eq!(None, pulse_offset(pulse, 0, 600_000));
+    }
+}
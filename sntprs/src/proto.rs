@@ -0,0 +1,168 @@
+//! Protocol values
+//!
+//! Well-known ports, the layout of the first header byte and typed
+//! values of its leap indicator, version and mode fields, along with
+//! the kiss codes carried as reference identifiers, as defined by
+//! RFC 5905
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::convert::TryFrom;
+//!
+//! use sntprs::proto::{Mode, MODE_MASK, MODE_SHIFT};
+//!
+//! let li_vn_mode = 0b00_100_100;
+//! let mode = Mode::try_from((li_vn_mode & MODE_MASK) >> MODE_SHIFT);
+//!
+//! assert_eq!(Ok(Mode::Server), mode);
+//! ```
+
+use std::convert::TryFrom;
+
+/// NTP server port
+pub const NTP_PORT: u32 = 123;
+/// NTS key establishment port, RFC 8915
+pub const NTS_KE_PORT: u32 = 4460;
+
+/// Leap indicator bits of the first header byte
+pub const LI_MASK: u8 = 0b1100_0000;
+/// Position of the leap indicator in the first header byte
+pub const LI_SHIFT: u8 = 6;
+/// Version number bits of the first header byte
+pub const VERSION_MASK: u8 = 0b0011_1000;
+/// Position of the version number in the first header byte
+pub const VERSION_SHIFT: u8 = 3;
+/// Mode bits of the first header byte
+pub const MODE_MASK: u8 = 0b0000_0111;
+/// Position of the mode in the first header byte
+pub const MODE_SHIFT: u8 = 0;
+
+/// Kiss code telling the client it is denied access
+pub const KISS_DENY: u32 = u32::from_be_bytes(*b"DENY");
+/// Kiss code telling the client it is restricted
+pub const KISS_RSTR: u32 = u32::from_be_bytes(*b"RSTR");
+/// Kiss code asking the client to reduce its polling rate
+pub const KISS_RATE: u32 = u32::from_be_bytes(*b"RATE");
+/// Kiss code of a server still initializing
+pub const KISS_INIT: u32 = u32::from_be_bytes(*b"INIT");
+/// Kiss code of a server that just stepped its clock
+pub const KISS_STEP: u32 = u32::from_be_bytes(*b"STEP");
+
+/// Warning of an impending leap second
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum LeapIndicator {
+    /// No warning
+    NoWarning = 0,
+    /// The last minute of the day has 61 seconds
+    InsertSecond = 1,
+    /// The last minute of the day has 59 seconds
+    DeleteSecond = 2,
+    /// The clock is not synchronized
+    Unsynchronized = 3,
+}
+
+/// Protocol version number
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Version {
+    /// NTPv1, RFC 1059
+    V1 = 1,
+    /// NTPv2, RFC 1119
+    V2 = 2,
+    /// NTPv3, RFC 1305
+    V3 = 3,
+    /// NTPv4, RFC 5905
+    V4 = 4,
+}
+
+/// Association mode of the sender
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Mode {
+    /// Reserved
+    Reserved = 0,
+    /// Symmetric active
+    SymmetricActive = 1,
+    /// Symmetric passive
+    SymmetricPassive = 2,
+    /// Client request
+    Client = 3,
+    /// Server reply
+    Server = 4,
+    /// Broadcast
+    Broadcast = 5,
+    /// NTP control message
+    Control = 6,
+    /// Reserved for private use
+    Private = 7,
+}
+
+impl TryFrom<u8> for LeapIndicator {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            0 => Ok(LeapIndicator::NoWarning),
+            1 => Ok(LeapIndicator::InsertSecond),
+            2 => Ok(LeapIndicator::DeleteSecond),
+            3 => Ok(LeapIndicator::Unsynchronized),
+            _ => Err(value),
+        }
+    }
+}
+
+impl TryFrom<u8> for Version {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            1 => Ok(Version::V1),
+            2 => Ok(Version::V2),
+            3 => Ok(Version::V3),
+            4 => Ok(Version::V4),
+            _ => Err(value),
+        }
+    }
+}
+
+impl TryFrom<u8> for Mode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            0 => Ok(Mode::Reserved),
+            1 => Ok(Mode::SymmetricActive),
+            2 => Ok(Mode::SymmetricPassive),
+            3 => Ok(Mode::Client),
+            4 => Ok(Mode::Server),
+            5 => Ok(Mode::Broadcast),
+            6 => Ok(Mode::Control),
+            7 => Ok(Mode::Private),
+            _ => Err(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod proto_tests {
+    use crate::proto::{LeapIndicator, Mode, Version, KISS_RATE};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_proto_values() {
+        for value in 0..8 {
+            assert_eq!(value, Mode::try_from(value).unwrap() as u8);
+        }
+
+        assert_eq!(Err(8), Mode::try_from(8));
+        assert_eq!(Ok(Version::V4), Version::try_from(4));
+        assert_eq!(Err(0), Version::try_from(0));
+        assert_eq!(
+            Ok(LeapIndicator::Unsynchronized),
+            LeapIndicator::try_from(3)
+        );
+        assert_eq!(0x5241_5445, KISS_RATE);
+    }
+}
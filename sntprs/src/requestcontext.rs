@@ -0,0 +1,43 @@
+use crate::{NtpPacket, ValidationProfile};
+
+/// Request a response datagram is checked against by
+/// [`parse_response_bytes`](crate::parse_response_bytes)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RequestContext {
+    /// Request as sent
+    pub request: NtpPacket,
+    /// Local time the request was sent at (T1), in NTP timestamp
+    /// format. Differs from the request transmit timestamp when it was
+    /// randomized for privacy
+    pub origin_timestamp: u64,
+    /// Local time the response was received at (T4), in NTP timestamp
+    /// format
+    pub recv_timestamp: u64,
+    /// Checks the response must pass
+    pub profile: ValidationProfile,
+}
+
+impl RequestContext {
+    /// Create new context for `request`, sent at its transmit timestamp,
+    /// whose response was received at `recv_timestamp`
+    pub fn new(request: NtpPacket, recv_timestamp: u64) -> Self {
+        RequestContext {
+            request,
+            origin_timestamp: request.tx_timestamp,
+            recv_timestamp,
+            profile: ValidationProfile::Strict,
+        }
+    }
+
+    /// Set the local time the request was sent at
+    pub fn origin_timestamp(mut self, timestamp: u64) -> Self {
+        self.origin_timestamp = timestamp;
+        self
+    }
+
+    /// Set the checks the response must pass
+    pub fn profile(mut self, profile: ValidationProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// Resolve `pool`, giving up after `timeout`.
+/// The system resolver cannot be interrupted, so the lookup runs on a
+/// detached helper thread that is left behind when it hangs
+pub(crate) fn resolve(
+    pool: &str,
+    port: u16,
+    timeout: Option<Duration>,
+) -> io::Result<Vec<SocketAddr>> {
+    let timeout = match timeout {
+        // Literal addresses need no lookup
+        Some(timeout) if pool.parse::<IpAddr>().is_err() => timeout,
+        _ => return Ok((pool, port).to_socket_addrs()?.collect()),
+    };
+    let (tx, rx) = mpsc::channel();
+    let name = pool.to_string();
+
+    thread::spawn(move || {
+        let addrs = (name.as_str(), port).to_socket_addrs();
+        let _ = tx.send(addrs.map(Iterator::collect));
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => Err(Error::ResolveTimeout.into()),
+    }
+}
+
+/// Cache of resolved server addresses.
+/// The system resolver does not report the TTL of the DNS records,
+/// so every entry expires after the same configured duration.
+/// Entries are keyed by server name, whatever the port
+pub(crate) struct DnsCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>,
+}
+
+impl DnsCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        DnsCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached addresses of `pool` with the given port,
+    /// if not expired
+    pub(crate) fn get(&self, pool: &str, port: u16) -> Option<Vec<SocketAddr>> {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(pool) {
+            Some((at, addrs)) if at.elapsed() < self.ttl => Some(
+                addrs
+                    .iter()
+                    .map(|addr| SocketAddr::new(addr.ip(), port))
+                    .collect(),
+            ),
+            Some(_) => {
+                entries.remove(pool);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, pool: &str, addrs: &[SocketAddr]) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(pool.to_string(), (Instant::now(), addrs.to_vec()));
+    }
+
+    pub(crate) fn invalidate(&self, pool: &str) {
+        self.entries.lock().unwrap().remove(pool);
+    }
+}
+
+#[cfg(test)]
+mod resolver_tests {
+    use crate::resolver::{resolve, DnsCache};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_dns_cache() {
+        let cache = DnsCache::new(Duration::from_secs(60));
+        let addrs = vec!["10.0.0.1:123".parse().unwrap()];
+        let other_port = vec!["10.0.0.1:1123".parse().unwrap()];
+
+        assert!(cache.get("pool", 123).is_none());
+
+        cache.insert("pool", &addrs);
+
+        assert_eq!(Some(addrs), cache.get("pool", 123));
+        assert_eq!(Some(other_port), cache.get("pool", 1123));
+
+        cache.invalidate("pool");
+
+        assert!(cache.get("pool", 123).is_none());
+        assert!(DnsCache::new(Duration::ZERO).get("pool", 123).is_none());
+    }
+
+    #[test]
+    fn test_resolve_literal() {
+        let addr: SocketAddr = "10.0.0.1:123".parse().unwrap();
+
+        assert_eq!(
+            vec![addr],
+            resolve("10.0.0.1", 123, Some(Duration::ZERO)).unwrap()
+        );
+    }
+}
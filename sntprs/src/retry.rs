@@ -0,0 +1,130 @@
+//! Retries with the `backoff` crate
+//!
+//! [`classify`] sorts the errors of the client into the transient and
+//! permanent errors of the `backoff` crate, so requests can be wrapped
+//! in its retry combinators, blocking or async alike: a timeout or an
+//! unsynchronized server is worth another try, while an invalid port
+//! or a kiss-o'-death is not. A RATE kiss-o'-death only asks to slow
+//! down, so it is retried after [`RATE_RETRY_AFTER`] at least.
+//! [`request`] retries a blocking request
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use backoff::ExponentialBackoff;
+//!
+//! use sntprs::NtpClient;
+//!
+//! let client = NtpClient::new();
+//! let result = sntprs::retry::request(
+//!     &client,
+//!     "pool.ntp.org",
+//!     123,
+//!     ExponentialBackoff::default(),
+//! );
+//! ```
+
+use std::io;
+use std::time::Duration;
+
+use backoff::backoff::Backoff;
+use log::debug;
+
+use crate::proto::KISS_RATE;
+use crate::{Error, NtpClient, NtpResult, Violation};
+
+/// Wait before retrying after a RATE kiss-o'-death, the default poll
+/// interval
+pub const RATE_RETRY_AFTER: Duration = Duration::from_secs(64);
+
+/// Returns whether `err` may not happen again on a later request
+fn is_transient(err: &io::Error) -> bool {
+    match Error::from_io(err) {
+        Some(err) => err.is_retryable(),
+        None => !matches!(
+            err.kind(),
+            io::ErrorKind::InvalidInput
+                | io::ErrorKind::PermissionDenied
+                | io::ErrorKind::Unsupported
+        ),
+    }
+}
+
+/// Returns `err` as a transient or permanent error of the `backoff`
+/// crate, as the result of an operation given to its retry functions
+pub fn classify(err: io::Error) -> backoff::Error<io::Error> {
+    if Error::from_io(&err)
+        == Some(Error::Validation(Violation::KissOfDeath(KISS_RATE)))
+    {
+        backoff::Error::retry_after(err, RATE_RETRY_AFTER)
+    } else if is_transient(&err) {
+        backoff::Error::transient(err)
+    } else {
+        backoff::Error::permanent(err)
+    }
+}
+
+/// Send requests to a NTP server with `client` until one succeeds,
+/// waiting between them as told by `backoff`. Gives up on the first
+/// permanent error, or returns the last error when `backoff` expires
+///
+/// * `pool` - Server's name or IP address as a string
+/// * `port` - Server's port as an int
+pub fn request<B: Backoff>(
+    client: &NtpClient,
+    pool: &str,
+    port: u32,
+    backoff: B,
+) -> io::Result<NtpResult> {
+    backoff::retry_notify(
+        backoff,
+        || client.request(pool, port).map_err(classify),
+        |err, wait| debug!("Retrying in {:?} after: {}", wait, err),
+    )
+    .map_err(|err| match err {
+        backoff::Error::Permanent(err) => err,
+        backoff::Error::Transient { err, .. } => err,
+    })
+}
+
+#[cfg(test)]
+mod retry_tests {
+    use crate::proto::{KISS_DENY, KISS_RATE};
+    use crate::retry::{classify, RATE_RETRY_AFTER};
+    use crate::{Error, Violation};
+    use std::io;
+
+    #[test]
+    fn test_classify() {
+        let transient = [
+            io::Error::from(Error::NotResponding),
+            io::Error::from(Error::Validation(Violation::Stratum(16))),
+            io::Error::from(io::ErrorKind::TimedOut),
+        ];
+        let permanent = [
+            io::Error::from(Error::InvalidPort(65536)),
+            io::Error::from(Error::Validation(Violation::KissOfDeath(
+                KISS_DENY,
+            ))),
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        ];
+
+        for err in transient {
+            assert!(matches!(classify(err), backoff::Error::Transient { .. }));
+        }
+
+        for err in permanent {
+            assert!(matches!(classify(err), backoff::Error::Permanent(_)));
+        }
+
+        let rate = Error::Validation(Violation::KissOfDeath(KISS_RATE));
+
+        assert!(matches!(
+            classify(rate.into()),
+            backoff::Error::Transient {
+                retry_after: Some(RATE_RETRY_AFTER),
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Debug;
+use std::fmt::Formatter;
+
+use crate::NtpResult;
+
+/// Summary of several spaced exchanges with the same server
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SampledResult {
+    /// Median offset in microseconds
+    pub offset: i64,
+    /// Smallest roundtrip delay in microseconds
+    pub delay: u64,
+    /// Difference between the largest and smallest offset in microseconds
+    pub spread: u64,
+    /// Number of successful exchanges the summary is based on
+    pub samples: usize,
+    /// Result of the exchange with the smallest roundtrip delay
+    pub best: NtpResult,
+}
+
+impl SampledResult {
+    /// Summarize the given results, returns `None` if there are none
+    pub(crate) fn from_results(results: &[NtpResult]) -> Option<Self> {
+        let best = *results.iter().min_by_key(|r| r.roundtrip())?;
+        let mut offsets: Vec<i64> =
+            results.iter().map(|r| r.offset()).collect();
+
+        offsets.sort_unstable();
+
+        let first = offsets[0];
+        let last = offsets[offsets.len() - 1];
+
+        Some(SampledResult {
+            offset: offsets[offsets.len() / 2],
+            delay: best.roundtrip(),
+            spread: last.abs_diff(first),
+            samples: results.len(),
+            best,
+        })
+    }
+
+    /// Returns median offset in microseconds
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Returns smallest roundtrip delay in microseconds
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+
+    /// Returns offset spread in microseconds
+    pub fn spread(&self) -> u64 {
+        self.spread
+    }
+
+    /// Returns number of samples the summary is based on
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+}
+
+impl Debug for SampledResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SampledResult")
+            .field("offset", &self.offset)
+            .field("delay", &self.delay)
+            .field("spread", &self.spread)
+            .field("samples", &self.samples)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod sampledresult_tests {
+    use crate::sampledresult::SampledResult;
+    use crate::NtpResult;
+
+    #[test]
+    fn test_sampled_result_summary() {
+        let results = [
+            NtpResult::new(0, 0, 3_000, 150),
+            NtpResult::new(0, 0, 1_000, -50),
+            NtpResult::new(0, 0, 2_000, 100),
+        ];
+        let summary = SampledResult::from_results(&results).unwrap();
+
+        assert_eq!(100, summary.offset());
+        assert_eq!(1_000, summary.delay());
+        assert_eq!(200, summary.spread());
+        assert_eq!(3, summary.samples());
+        assert_eq!(results[1], summary.best);
+        assert!(SampledResult::from_results(&[]).is_none());
+    }
+}
@@ -0,0 +1,706 @@
+//! Periodic synchronization service
+//!
+//! [`SyncService`] performs the requests against a configured NTP server,
+//! keeps track of the last successful synchronization and reports through
+//! its [`Monitor`] when time becomes stale. An exponentially smoothed
+//! offset is kept alongside for consumers displaying a stable value,
+//! and optionally the [`Histogram`]s of the offsets and roundtrips.
+//! The successful synchronizations can be kept in a [`SampleStore`].
+//! When the system clock is stepped between two polls, or the system
+//! resumes from suspension, the offsets measured before are dropped and
+//! an alert is raised. Clock adjustments made from the results of the
+//! service are reported with [`SyncService::clock_adjusted`] so that
+//! they do not raise the alert. Polls are scheduled at the configured
+//! interval, in quick bursts while the server is unreachable when iburst
+//! is enabled, or right away on resume. A poll jitter spreads the polls
+//! of many devices started together, which would otherwise hit the
+//! servers at the same second. A service shared behind a lock can run
+//! the exchange of a synchronization with the lock released, with
+//! [`SyncService::begin_sync`] and [`SyncService::finish_sync`]
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::debug;
+
+use crate::clock::{StepDetector, SuspendDetector};
+use crate::compliance::MIN_POLL_INTERVAL;
+use crate::histogram::Histogram;
+use crate::monitor::{Alert, Monitor, Reach};
+use crate::store::{Sample, SampleStore};
+use crate::{random_u64, NtpClient, NtpResult};
+
+const DEFAULT_SMOOTHING: f64 = 0.25;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(64);
+const IBURST_COUNT: u32 = 8;
+const IBURST_SPACING: Duration = Duration::from_secs(2);
+/// Largest poll jitter, as a fraction of the polling interval
+pub const MAX_POLL_JITTER: f64 = 0.5;
+
+/// Snapshot of the state of a [`SyncService`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Status {
+    /// Name or IP address of the server polled last
+    pub server: String,
+    /// Port of the server polled last
+    pub port: u32,
+    /// Configured polling interval
+    pub poll_interval: Option<Duration>,
+    /// When the last request was sent, successful or not
+    pub last_poll: Option<SystemTime>,
+    /// Outcome of the last successful synchronization
+    pub last_result: Option<NtpResult>,
+    /// Smoothed offset in microseconds
+    pub smoothed_offset: Option<i64>,
+    /// Jitter of the offsets in microseconds
+    pub jitter: Option<u64>,
+    /// Reachability register of the server polled last
+    pub reach: Reach,
+    /// Whether a synchronization succeeded and the watchdog did not
+    /// expire since
+    pub synchronized: bool,
+}
+
+/// Exchange of a synchronization, detached from its [`SyncService`]
+pub struct PendingSync {
+    client: NtpClient,
+    servers: Vec<(String, u32)>,
+}
+
+impl PendingSync {
+    /// Send a request to the first NTP server answering
+    pub fn send(self) -> SyncOutcome {
+        let mut outcome = SyncOutcome {
+            server: None,
+            result: Err(io::ErrorKind::NotFound.into()),
+            failed: Vec::new(),
+        };
+
+        for (server, port) in self.servers {
+            outcome.failed.extend(outcome.server.take());
+            outcome.result = self.client.request(&server, port);
+            outcome.server = Some((server, port));
+
+            if outcome.result.is_ok() {
+                break;
+            }
+        }
+
+        outcome
+    }
+}
+
+/// Outcome of a [`PendingSync`], to hand back to its [`SyncService`]
+pub struct SyncOutcome {
+    server: Option<(String, u32)>,
+    result: io::Result<NtpResult>,
+    /// Servers which failed before the last one was tried
+    failed: Vec<(String, u32)>,
+}
+
+/// Synchronization service bound to NTP servers tried in order, the
+/// next ones serving as fallbacks when the previous ones fail
+pub struct SyncService {
+    servers: Vec<(String, u32)>,
+    /// Reachability register of every server, in the same order
+    reach: Vec<Reach>,
+    current: usize,
+    client: NtpClient,
+    interval: Option<Duration>,
+    poll_jitter: f64,
+    monitor: Monitor,
+    watchdog: Option<Duration>,
+    started: Instant,
+    last_poll: Option<SystemTime>,
+    last_result: Option<NtpResult>,
+    last_success: Option<Instant>,
+    degraded: bool,
+    smoothing: f64,
+    smoothed_offset: Option<f64>,
+    jitter: Option<f64>,
+    step_detector: StepDetector,
+    suspend_detector: SuspendDetector,
+    next_poll: Instant,
+    iburst: bool,
+    burst_remaining: u32,
+    histograms: Option<(Histogram, Histogram)>,
+    store: Option<Box<dyn SampleStore + Send>>,
+}
+
+impl SyncService {
+    /// Create new service for the given NTP server
+    /// Args:
+    /// * `server` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn new(server: &str, port: u32) -> Self {
+        SyncService {
+            servers: vec![(server.to_string(), port)],
+            reach: vec![Reach::default()],
+            current: 0,
+            client: NtpClient::new(),
+            interval: None,
+            poll_jitter: 0.0,
+            monitor: Monitor::new(),
+            watchdog: None,
+            started: Instant::now(),
+            last_poll: None,
+            last_result: None,
+            last_success: None,
+            degraded: false,
+            smoothing: DEFAULT_SMOOTHING,
+            smoothed_offset: None,
+            jitter: None,
+            step_detector: StepDetector::default(),
+            suspend_detector: SuspendDetector::default(),
+            next_poll: Instant::now(),
+            iburst: false,
+            burst_remaining: 0,
+            histograms: None,
+            store: None,
+        }
+    }
+
+    /// Use the given client to send the requests
+    pub fn client(mut self, client: NtpClient) -> Self {
+        self.client = match self.interval {
+            Some(interval) => client.poll_interval(interval),
+            None => client,
+        };
+        self
+    }
+
+    /// Set the interval the service is polled at, which is advertised
+    /// to the server in the requests
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.set_interval(interval);
+        self
+    }
+
+    /// Change the interval the service is polled at
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.client = self.client.clone().poll_interval(interval);
+        self.interval = Some(interval);
+    }
+
+    /// Randomize every polling interval by up to `fraction` of it, more
+    /// or less, e.g. 0.1 for 64 s polls between 57.6 s and 70.4 s.
+    /// Clamped between 0, the default, and 0.5. Polls are never
+    /// scheduled less than 15 s apart, outside of the iburst ones
+    pub fn poll_jitter(mut self, fraction: f64) -> Self {
+        self.poll_jitter = fraction.clamp(0.0, MAX_POLL_JITTER);
+        self
+    }
+
+    /// Add a fallback server, tried after the current ones
+    pub fn add_server(&mut self, server: &str, port: u32) {
+        if !self.servers.iter().any(|(s, p)| s == server && *p == port) {
+            self.servers.push((server.to_string(), port));
+            self.reach.push(Reach::default());
+        }
+    }
+
+    /// Remove a server. The last one cannot be removed
+    pub fn remove_server(&mut self, server: &str, port: u32) -> io::Result<()> {
+        let idx = self
+            .servers
+            .iter()
+            .position(|(s, p)| s == server && *p == port)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Unknown server: {}:{}", server, port),
+                )
+            })?;
+
+        if self.servers.len() == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unable to remove the last server",
+            ));
+        }
+
+        self.servers.remove(idx);
+        self.reach.remove(idx);
+        self.current = 0;
+        Ok(())
+    }
+
+    /// Returns the servers as name and port pairs, in the order they
+    /// are tried
+    pub fn servers(&self) -> &[(String, u32)] {
+        &self.servers
+    }
+
+    /// Returns the configured polling interval
+    pub fn poll_interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Use the given monitor to report alerts
+    pub fn monitor(mut self, monitor: Monitor) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    /// Flip to the degraded state if no synchronization succeeded within
+    /// `window`
+    pub fn watchdog(mut self, window: Duration) -> Self {
+        self.watchdog = Some(window);
+        self
+    }
+
+    /// Set the weight of the latest offset in the smoothed offset,
+    /// between 0 (never updated) and 1 (no smoothing). Defaults to 0.25
+    pub fn smoothing(mut self, factor: f64) -> Self {
+        self.smoothing = factor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the exponentially weighted moving average of the measured
+    /// offsets in microseconds, if any synchronization succeeded
+    pub fn smoothed_offset(&self) -> Option<i64> {
+        self.smoothed_offset.map(|offset| offset.round() as i64)
+    }
+
+    /// Returns the root mean square of the differences between
+    /// successive offsets in microseconds, weighted as the smoothed
+    /// offset, once two synchronizations succeeded
+    pub fn jitter(&self) -> Option<u64> {
+        self.jitter.map(|jitter| jitter.sqrt().round() as u64)
+    }
+
+    /// Poll 8 times 2 s apart when the service starts, and whenever the
+    /// server is unreachable when a poll is due, to converge quickly as
+    /// ntpd's iburst does
+    pub fn iburst(mut self, enabled: bool) -> Self {
+        self.iburst = enabled;
+        self
+    }
+
+    /// Report steps of the system clock larger than `threshold`.
+    /// Defaults to 125 ms
+    pub fn step_threshold(mut self, threshold: Duration) -> Self {
+        self.step_detector = StepDetector::new(threshold);
+        self
+    }
+
+    /// Record the absolute offsets and the roundtrips of the successful
+    /// synchronizations in histograms
+    pub fn histograms(mut self, enabled: bool) -> Self {
+        self.histograms = if enabled {
+            Some((Histogram::new(), Histogram::new()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Returns the histogram of the absolute offsets in microseconds,
+    /// if enabled
+    pub fn offset_histogram(&self) -> Option<&Histogram> {
+        self.histograms.as_ref().map(|(offsets, _)| offsets)
+    }
+
+    /// Returns the histogram of the roundtrips in microseconds,
+    /// if enabled
+    pub fn roundtrip_histogram(&self) -> Option<&Histogram> {
+        self.histograms.as_ref().map(|(_, roundtrips)| roundtrips)
+    }
+
+    /// Write the successful synchronizations into `store`
+    pub fn store<S>(mut self, store: S) -> Self
+    where
+        S: SampleStore + Send + 'static,
+    {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// Returns the stored samples taken at or after `since`,
+    /// or none without a store
+    pub fn history(&self, since: SystemTime) -> io::Result<Vec<Sample>> {
+        match &self.store {
+            Some(store) => store.samples(since),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the monitor used to report alerts
+    pub fn monitor_mut(&mut self) -> &mut Monitor {
+        &mut self.monitor
+    }
+
+    /// Returns the reachability register of the server polled last
+    pub fn reach(&self) -> Reach {
+        self.reach[self.current]
+    }
+
+    /// Returns the instant of the last successful synchronization
+    pub fn last_success(&self) -> Option<Instant> {
+        self.last_success
+    }
+
+    /// Returns whether the service is degraded, i.e. the watchdog expired
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Returns a snapshot of the service state
+    pub fn status(&self) -> Status {
+        let (server, port) = &self.servers[self.current];
+
+        Status {
+            server: server.clone(),
+            port: *port,
+            poll_interval: self.interval,
+            last_poll: self.last_poll,
+            last_result: self.last_result,
+            smoothed_offset: self.smoothed_offset(),
+            jitter: self.jitter(),
+            reach: self.reach(),
+            synchronized: self.last_success.is_some() && !self.degraded,
+        }
+    }
+
+    /// Send a request to the first NTP server answering, and update the
+    /// service state
+    pub fn sync(&mut self) -> io::Result<NtpResult> {
+        let outcome = self.begin_sync().send();
+
+        self.finish_sync(outcome)
+    }
+
+    /// Prepare a synchronization, whose exchange can then be sent
+    /// without borrowing the service, e.g. with the lock of a shared
+    /// service released
+    pub fn begin_sync(&mut self) -> PendingSync {
+        self.check_resume();
+        self.check_clock_step();
+        self.start_burst();
+
+        PendingSync {
+            client: self.client.clone(),
+            servers: self.servers.clone(),
+        }
+    }
+
+    /// Update the service state with the outcome of a synchronization
+    /// started by [`SyncService::begin_sync`]
+    pub fn finish_sync(
+        &mut self,
+        outcome: SyncOutcome,
+    ) -> io::Result<NtpResult> {
+        let SyncOutcome {
+            server,
+            result,
+            failed,
+        } = outcome;
+
+        for server in &failed {
+            if let Some(idx) = self.servers.iter().position(|s| s == server) {
+                self.reach[idx].update(false);
+            }
+        }
+
+        if let Some(idx) = server
+            .as_ref()
+            .and_then(|server| self.servers.iter().position(|s| s == server))
+        {
+            self.current = idx;
+            self.reach[idx].update(result.is_ok());
+        }
+
+        self.last_poll = Some(SystemTime::now());
+        self.next_poll = self.next_poll_after(Instant::now());
+
+        if let Ok(result) = &result {
+            if let Some(previous) = self.last_result {
+                self.update_jitter(previous.offset(), result.offset());
+            }
+
+            self.last_result = Some(*result);
+            self.last_success = Some(Instant::now());
+            self.smooth(result.offset());
+
+            if let Some((offsets, roundtrips)) = &mut self.histograms {
+                offsets.record(result.offset().unsigned_abs());
+                roundtrips.record(result.roundtrip());
+            }
+
+            if let (Some(store), Some((server, _))) = (&mut self.store, &server)
+            {
+                let sample = Sample::new(server, result);
+
+                if let Err(err) = store.push(&sample) {
+                    debug!("Unable to store sample: {}", err);
+                }
+            }
+        }
+
+        self.monitor.observe(&result);
+        self.check_watchdog();
+
+        result
+    }
+
+    /// Report that the system clock was just adjusted from the results
+    /// of this service, so that the step is not taken for an external one
+    pub fn clock_adjusted(&mut self) {
+        self.step_detector.rebase();
+    }
+
+    /// Check whether the system clock was stepped since the previous
+    /// check, dropping the offsets measured before the step and notifying
+    /// the monitor observers. Returns the step in microseconds
+    pub fn check_clock_step(&mut self) -> Option<i64> {
+        let step = self.step_detector.check()?;
+
+        self.drop_offsets();
+        self.monitor.notify(Alert::ClockStepped { step });
+
+        Some(step)
+    }
+
+    /// Check whether the system resumed from suspension since the
+    /// previous check, dropping the offsets measured before, notifying the
+    /// monitor observers and polling right away. Returns how long the
+    /// system was suspended
+    pub fn check_resume(&mut self) -> Option<Duration> {
+        let slept = self.suspend_detector.check()?;
+
+        self.drop_offsets();
+        self.step_detector.reset();
+        self.schedule_now();
+        self.monitor.notify(Alert::Resumed { slept });
+
+        Some(slept)
+    }
+
+    /// Returns when the next poll is due
+    pub fn next_poll(&self) -> Instant {
+        self.next_poll
+    }
+
+    /// Make the next poll due right away
+    pub fn schedule_now(&mut self) {
+        self.next_poll = Instant::now();
+    }
+
+    /// Synchronize if a poll is due, after checking for a resume.
+    /// Meant to be called periodically, e.g. every second, returns the
+    /// outcome of the poll if any
+    pub fn poll_if_due(&mut self) -> Option<io::Result<NtpResult>> {
+        self.check_resume();
+
+        if Instant::now() < self.next_poll {
+            return None;
+        }
+
+        Some(self.sync())
+    }
+
+    fn start_burst(&mut self) {
+        if self.iburst
+            && self.burst_remaining == 0
+            && !self.reach().is_reachable()
+        {
+            self.burst_remaining = IBURST_COUNT;
+        }
+    }
+
+    fn next_poll_after(&mut self, now: Instant) -> Instant {
+        self.burst_remaining = self.burst_remaining.saturating_sub(1);
+
+        if self.burst_remaining > 0 {
+            now + IBURST_SPACING
+        } else {
+            let interval = self.interval.unwrap_or(DEFAULT_INTERVAL);
+            // Uniform between -1 and 1
+            let unit = random_u64() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+
+            let interval = interval.mul_f64(1.0 + unit * self.poll_jitter);
+
+            now + interval.max(MIN_POLL_INTERVAL)
+        }
+    }
+
+    fn drop_offsets(&mut self) {
+        self.last_result = None;
+        self.smoothed_offset = None;
+        self.jitter = None;
+    }
+
+    /// Evaluate the watchdog, notifying the monitor observers when the
+    /// degraded state changes. Returns whether the service is degraded
+    pub fn check_watchdog(&mut self) -> bool {
+        self.check_watchdog_at(Instant::now())
+    }
+
+    fn smooth(&mut self, offset: i64) {
+        let offset = offset as f64;
+
+        self.smoothed_offset = Some(match self.smoothed_offset {
+            Some(previous) => previous + self.smoothing * (offset - previous),
+            None => offset,
+        });
+    }
+
+    fn update_jitter(&mut self, previous: i64, offset: i64) {
+        let square = (offset as f64 - previous as f64).powi(2);
+
+        self.jitter = Some(match self.jitter {
+            Some(jitter) => jitter + self.smoothing * (square - jitter),
+            None => square,
+        });
+    }
+
+    fn check_watchdog_at(&mut self, now: Instant) -> bool {
+        let window = match self.watchdog {
+            Some(window) => window,
+            None => return false,
+        };
+        let reference = self.last_success.unwrap_or(self.started);
+        let degraded = now.saturating_duration_since(reference) > window;
+
+        if degraded != self.degraded {
+            self.degraded = degraded;
+            self.monitor.notify(if degraded {
+                Alert::Degraded
+            } else {
+                Alert::Recovered
+            });
+        }
+
+        self.degraded
+    }
+}
+
+#[cfg(test)]
+mod service_tests {
+    use crate::monitor::Alert;
+    use crate::service::{SyncOutcome, SyncService};
+    use crate::NtpResult;
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_watchdog() {
+        let mut service = SyncService::new("localhost", 123)
+            .watchdog(Duration::from_secs(60));
+        let alerts = service.monitor_mut().subscribe();
+        let start = service.started;
+
+        assert!(!service.check_watchdog_at(start + Duration::from_secs(30)));
+        assert!(service.check_watchdog_at(start + Duration::from_secs(90)));
+
+        service.last_success = Some(start + Duration::from_secs(100));
+
+        assert!(!service.check_watchdog_at(start + Duration::from_secs(120)));
+        assert_eq!(
+            vec![Alert::Degraded, Alert::Recovered],
+            alerts.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_smoothed_offset() {
+        let mut service = SyncService::new("localhost", 123).smoothing(0.5);
+
+        assert_eq!(None, service.smoothed_offset());
+
+        for offset in [1_000, 3_000, -1_000] {
+            service.smooth(offset);
+        }
+
+        assert_eq!(Some(500), service.smoothed_offset());
+        assert_eq!(None, service.jitter());
+
+        service.update_jitter(1_000, 4_000);
+        service.update_jitter(4_000, 3_000);
+
+        assert_eq!(Some(2_236), service.jitter());
+    }
+
+    #[test]
+    fn test_iburst() {
+        let mut service = SyncService::new("localhost", 123)
+            .interval(Duration::from_secs(60))
+            .iburst(true);
+        let now = Instant::now();
+
+        for _ in 0..7 {
+            service.start_burst();
+            assert_eq!(
+                now + Duration::from_secs(2),
+                service.next_poll_after(now)
+            );
+        }
+
+        service.start_burst();
+        assert_eq!(now + Duration::from_secs(60), service.next_poll_after(now));
+
+        // Still unreachable when the next poll is due
+        service.start_burst();
+        assert_eq!(now + Duration::from_secs(2), service.next_poll_after(now));
+    }
+
+    #[test]
+    fn test_reach_per_server() {
+        let mut service = SyncService::new("primary", 123);
+
+        service.add_server("fallback", 123);
+
+        let _ = service.finish_sync(SyncOutcome {
+            server: Some(("fallback".to_string(), 123)),
+            result: Ok(NtpResult::new(0, 0, 0, 0)),
+            failed: vec![("primary".to_string(), 123)],
+        });
+        let _ = service.finish_sync(SyncOutcome {
+            server: Some(("primary".to_string(), 123)),
+            result: Err(io::ErrorKind::TimedOut.into()),
+            failed: Vec::new(),
+        });
+
+        assert_eq!(0, service.reach().bits());
+        assert_eq!(0, service.status().reach.bits());
+        assert_eq!("primary", service.status().server);
+
+        let _ = service.finish_sync(SyncOutcome {
+            server: Some(("fallback".to_string(), 123)),
+            result: Ok(NtpResult::new(0, 0, 0, 0)),
+            failed: vec![("primary".to_string(), 123)],
+        });
+
+        assert_eq!(0b11, service.reach().bits());
+        assert_eq!(0b11, service.status().reach.bits());
+        assert_eq!("fallback", service.status().server);
+    }
+
+    #[test]
+    fn test_poll_jitter() {
+        let mut service = SyncService::new("localhost", 123)
+            .interval(Duration::from_secs(100))
+            .poll_jitter(0.1);
+        let now = Instant::now();
+        let polls: Vec<Instant> =
+            (0..16).map(|_| service.next_poll_after(now)).collect();
+
+        for poll in &polls {
+            assert!(*poll >= now + Duration::from_secs(90));
+            assert!(*poll <= now + Duration::from_secs(110));
+        }
+
+        assert!(polls.iter().any(|poll| *poll != polls[0]));
+
+        let mut service = SyncService::new("localhost", 123)
+            .interval(Duration::from_secs(20))
+            .poll_jitter(1.0);
+
+        for _ in 0..16 {
+            let poll = service.next_poll_after(now);
+
+            assert!(poll >= now + Duration::from_secs(15));
+            assert!(poll <= now + Duration::from_secs(30));
+        }
+    }
+}
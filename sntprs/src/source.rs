@@ -0,0 +1,611 @@
+//! Time sources
+//!
+//! Every way of learning the current time (NTP servers, secure or HTTPS
+//! based fallbacks, a hardware clock) is modelled as a [`TimeSource`]
+//! reporting its trust level. A [`Coordinator`] samples a set of sources
+//! and picks or combines the most trusted answers. As with ntpd, sources
+//! can be preferred, weighted or only monitored through their
+//! [`SelectOptions`]
+//!
+//! When no source answers, [`Coordinator::best_available_time`] degrades
+//! to the holdover estimate, then to the local clock, telling where the
+//! time comes from and how accurate it is
+//!
+//! ```rust,no_run
+//! use sntprs::holdover::Holdover;
+//! use sntprs::source::{Accuracy, Coordinator, NtpSource};
+//!
+//! let mut coordinator = Coordinator::new()
+//!     .source(NtpSource::new("pool.ntp.org", 123))
+//!     .holdover(Holdover::new());
+//! let best = coordinator.best_available_time();
+//!
+//! if best.accuracy <= Accuracy::Medium {
+//!     println!("{:?} from {:?}", best.time, best.provenance);
+//! }
+//! ```
+
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use log::debug;
+
+use crate::holdover::Holdover;
+use crate::NtpClient;
+
+/// Maximum error of the high accuracy class, in microseconds
+const HIGH_ACCURACY: u64 = 1_000;
+/// Maximum error of the medium accuracy class, in microseconds
+const MEDIUM_ACCURACY: u64 = 100_000;
+
+/// How much a time source is trusted, from least to most
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Trust {
+    /// Unauthenticated or coarse source, e.g. a RTC or HTTP date header
+    Low,
+    /// Unauthenticated network source, e.g. a plain NTP server
+    Medium,
+    /// Authenticated source, e.g. a NTS server or a local reference clock
+    High,
+}
+
+/// Where a time comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Provenance {
+    /// Authenticated NTS server
+    Nts,
+    /// Plain NTP server
+    Ntp,
+    /// Local reference clock, e.g. a GPS receiver
+    ReferenceClock,
+    /// Estimate carried over from the last synchronization
+    Holdover,
+    /// Local hardware clock, without any synchronization
+    Rtc,
+    /// Local system clock, without any synchronization
+    SystemClock,
+    /// Source not telling where its time comes from
+    Unknown,
+}
+
+/// Accuracy class of a time, from the most to the least accurate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Accuracy {
+    /// Maximum error within 1 ms
+    High,
+    /// Maximum error within 100 ms
+    Medium,
+    /// Larger but bounded maximum error
+    Low,
+    /// Unbounded error
+    Unknown,
+}
+
+impl Accuracy {
+    /// Returns the class of a maximum error in microseconds
+    pub fn from_max_error(max_error: u64) -> Self {
+        if max_error <= HIGH_ACCURACY {
+            Accuracy::High
+        } else if max_error <= MEDIUM_ACCURACY {
+            Accuracy::Medium
+        } else {
+            Accuracy::Low
+        }
+    }
+}
+
+/// Most trustworthy time available, with its origin
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BestTime {
+    /// Current time, corrected by the offset
+    pub time: SystemTime,
+    /// Offset of the local clock in microseconds
+    pub offset: i64,
+    /// Maximum error of the offset in microseconds, if bounded
+    pub max_error: Option<u64>,
+    /// Name of the source, empty without any
+    pub source: String,
+    /// Where the time comes from
+    pub provenance: Provenance,
+    /// Accuracy class of the time
+    pub accuracy: Accuracy,
+}
+
+/// Clock offset measured by a time source
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimeSample {
+    /// Name of the source
+    pub source: String,
+    /// Offset of the local clock in microseconds
+    pub offset: i64,
+    /// Maximum error of the offset in microseconds
+    pub max_error: u64,
+    /// Trust level of the source
+    pub trust: Trust,
+}
+
+/// How a source takes part in the selection, mirroring the ntpd
+/// `prefer`, `weight` and `noselect` options
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SelectOptions {
+    /// Selected over the other sources of the same trust level
+    pub prefer: bool,
+    /// Weight of the source when combining offsets, at least 1
+    pub weight: u32,
+    /// Only sampled for monitoring, never selected nor combined
+    pub noselect: bool,
+}
+
+impl Default for SelectOptions {
+    fn default() -> Self {
+        SelectOptions {
+            prefer: false,
+            weight: 1,
+            noselect: false,
+        }
+    }
+}
+
+/// Something able to measure the offset of the local clock
+pub trait TimeSource {
+    /// Returns a name identifying the source
+    fn name(&self) -> String;
+
+    /// Returns how much the source is trusted
+    fn trust(&self) -> Trust;
+
+    /// Measure the offset of the local clock
+    fn sample(&self) -> io::Result<TimeSample>;
+
+    /// Returns how the source takes part in the selection
+    fn options(&self) -> SelectOptions {
+        SelectOptions::default()
+    }
+
+    /// Returns where the time of the source comes from,
+    /// [`Provenance::Unknown`] by default
+    fn provenance(&self) -> Provenance {
+        Provenance::Unknown
+    }
+}
+
+/// Time source backed by a NTP server
+pub struct NtpSource {
+    client: NtpClient,
+    server: String,
+    port: u32,
+    trust: Trust,
+    options: SelectOptions,
+}
+
+impl NtpSource {
+    /// Create new source querying the given server with default settings
+    /// Args:
+    /// * `server` - Server's name or IP address as a string
+    /// * `port` - Server's port as an int
+    pub fn new(server: &str, port: u32) -> Self {
+        NtpSource {
+            client: NtpClient::new(),
+            server: server.to_string(),
+            port,
+            trust: Trust::Medium,
+            options: SelectOptions::default(),
+        }
+    }
+
+    /// Use the given client for the requests
+    pub fn client(mut self, client: NtpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Override the trust level, `Trust::Medium` by default
+    pub fn trust(mut self, trust: Trust) -> Self {
+        self.trust = trust;
+        self
+    }
+
+    /// Set how the source takes part in the selection
+    pub fn options(mut self, options: SelectOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl TimeSource for NtpSource {
+    fn name(&self) -> String {
+        format!("ntp://{}:{}", self.server, self.port)
+    }
+
+    fn trust(&self) -> Trust {
+        self.trust
+    }
+
+    fn options(&self) -> SelectOptions {
+        self.options
+    }
+
+    fn provenance(&self) -> Provenance {
+        Provenance::Ntp
+    }
+
+    fn sample(&self) -> io::Result<TimeSample> {
+        let result = self.client.request(&self.server, self.port)?;
+
+        Ok(TimeSample {
+            source: self.name(),
+            offset: result.offset(),
+            max_error: result.max_error(),
+            trust: self.trust,
+        })
+    }
+}
+
+/// Samples a set of time sources and selects among their answers
+#[derive(Default)]
+pub struct Coordinator {
+    sources: Vec<Box<dyn TimeSource + Send + Sync>>,
+    holdover: Option<Holdover>,
+    drift: f64,
+}
+
+impl Coordinator {
+    /// Create new coordinator without sources
+    pub fn new() -> Self {
+        Coordinator::default()
+    }
+
+    /// Add a time source
+    pub fn source<S>(mut self, source: S) -> Self
+    where
+        S: TimeSource + Send + Sync + 'static,
+    {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Fall back to `holdover` when no source answers, see
+    /// [`Coordinator::best_available_time`]
+    pub fn holdover(mut self, holdover: Holdover) -> Self {
+        self.holdover = Some(holdover);
+        self
+    }
+
+    /// Set the drift of the local clock in PPM, as estimated by
+    /// [`Discipline::drift`](crate::discipline::Discipline::drift), used
+    /// by the holdover estimate
+    pub fn set_drift(&mut self, ppm: f64) {
+        self.drift = ppm;
+    }
+
+    /// Returns the most trustworthy time available: the selected sample
+    /// when a source answers, else the holdover estimate since the last
+    /// selection, else the local clock with an unknown accuracy
+    pub fn best_available_time(&mut self) -> BestTime {
+        let selected = best(&self.candidates()).map(|(sample, _)| {
+            let provenance = self
+                .sources
+                .iter()
+                .find(|source| source.name() == sample.source)
+                .map_or(Provenance::Unknown, |source| source.provenance());
+
+            (sample.clone(), provenance)
+        });
+
+        if let Some((sample, provenance)) = selected {
+            if let Some(holdover) = self.holdover.as_mut() {
+                holdover.sync(sample.offset, sample.max_error, self.drift);
+            }
+
+            return BestTime {
+                time: corrected_now(sample.offset),
+                offset: sample.offset,
+                max_error: Some(sample.max_error),
+                source: sample.source,
+                provenance,
+                accuracy: Accuracy::from_max_error(sample.max_error),
+            };
+        }
+
+        match self.holdover.as_ref().and_then(Holdover::estimate) {
+            Some(estimate) => BestTime {
+                time: corrected_now(estimate.offset),
+                offset: estimate.offset,
+                max_error: Some(estimate.uncertainty),
+                source: "holdover".to_string(),
+                provenance: Provenance::Holdover,
+                accuracy: Accuracy::from_max_error(estimate.uncertainty),
+            },
+            None => BestTime {
+                time: SystemTime::now(),
+                offset: 0,
+                max_error: None,
+                source: String::new(),
+                provenance: Provenance::SystemClock,
+                accuracy: Accuracy::Unknown,
+            },
+        }
+    }
+
+    /// Sample every source, including the `noselect` ones, returning
+    /// the successful samples
+    pub fn sample_all(&self) -> Vec<TimeSample> {
+        self.sources
+            .iter()
+            .filter_map(|source| sample(source.as_ref()))
+            .collect()
+    }
+
+    /// Returns the sample with the smallest error among the most
+    /// trusted sources that answered, preferred sources first
+    pub fn select(&self) -> io::Result<TimeSample> {
+        best(&self.candidates())
+            .map(|(sample, _)| sample.clone())
+            .ok_or_else(no_source)
+    }
+
+    /// Returns the average of the most trusted samples weighted by the
+    /// inverse of their squared errors and by their source weights
+    pub fn combine(&self) -> io::Result<TimeSample> {
+        combine(&self.candidates()).ok_or_else(no_source)
+    }
+
+    /// Sample the sources taking part in the selection
+    fn candidates(&self) -> Vec<(TimeSample, SelectOptions)> {
+        self.sources
+            .iter()
+            .filter(|source| !source.options().noselect)
+            .filter_map(|source| {
+                sample(source.as_ref()).map(|s| (s, source.options()))
+            })
+            .collect()
+    }
+}
+
+fn sample(source: &(dyn TimeSource + Send + Sync)) -> Option<TimeSample> {
+    match source.sample() {
+        Ok(sample) => Some(sample),
+        Err(err) => {
+            debug!("{}: {}", source.name(), err);
+            None
+        }
+    }
+}
+
+/// Returns the largest set of samples whose offsets all lie within
+/// `tolerance` microseconds of each other, sorted by offset, if it
+/// gathers at least `quorum` samples. Only the first sample of every
+/// source is counted
+pub fn quorum(
+    samples: &[TimeSample],
+    quorum: usize,
+    tolerance: u64,
+) -> Option<Vec<TimeSample>> {
+    let mut sorted: Vec<TimeSample> = Vec::with_capacity(samples.len());
+    let mut best = 0..0;
+
+    for sample in samples {
+        if !sorted.iter().any(|s| s.source == sample.source) {
+            sorted.push(sample.clone());
+        }
+    }
+
+    let mut start = 0;
+
+    sorted.sort_by_key(|sample| sample.offset);
+
+    for end in 0..sorted.len() {
+        while sorted[start].offset.abs_diff(sorted[end].offset) > tolerance {
+            start += 1;
+        }
+
+        if end + 1 - start > best.len() {
+            best = start..end + 1;
+        }
+    }
+
+    if best.len() < quorum.max(1) {
+        return None;
+    }
+
+    Some(sorted[best].to_vec())
+}
+
+/// Returns the current time corrected by `offset` microseconds
+fn corrected_now(offset: i64) -> SystemTime {
+    let magnitude = Duration::from_micros(offset.unsigned_abs());
+
+    if offset < 0 {
+        SystemTime::now() - magnitude
+    } else {
+        SystemTime::now() + magnitude
+    }
+}
+
+fn no_source() -> io::Error {
+    io::Error::other("No time source answered")
+}
+
+type Candidate = (TimeSample, SelectOptions);
+
+fn most_trusted(samples: &[Candidate]) -> impl Iterator<Item = &Candidate> {
+    let trust = samples.iter().map(|(s, _)| s.trust).max();
+
+    samples.iter().filter(move |(s, _)| Some(s.trust) == trust)
+}
+
+fn best(samples: &[Candidate]) -> Option<&Candidate> {
+    most_trusted(samples)
+        .min_by_key(|(s, options)| (!options.prefer, s.max_error))
+}
+
+fn combine(samples: &[Candidate]) -> Option<TimeSample> {
+    let (best, _) = best(samples)?;
+    let (mut sum, mut weights) = (0.0, 0.0);
+
+    for (sample, options) in most_trusted(samples) {
+        let error = sample.max_error.max(1) as f64;
+        let weight = f64::from(options.weight.max(1)) / (error * error);
+
+        sum += sample.offset as f64 * weight;
+        weights += weight;
+    }
+
+    Some(TimeSample {
+        source: "combined".to_string(),
+        offset: (sum / weights).round() as i64,
+        max_error: best.max_error,
+        trust: best.trust,
+    })
+}
+
+#[cfg(test)]
+mod source_tests {
+    use crate::holdover::Holdover;
+    use crate::source::{
+        best, combine, quorum, Accuracy, Coordinator, NtpSource, Provenance,
+        SelectOptions, TimeSample, TimeSource, Trust,
+    };
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct FakeSource {
+        up: Arc<AtomicBool>,
+    }
+
+    impl TimeSource for FakeSource {
+        fn name(&self) -> String {
+            "fake".to_string()
+        }
+
+        fn trust(&self) -> Trust {
+            Trust::Medium
+        }
+
+        fn provenance(&self) -> Provenance {
+            Provenance::Ntp
+        }
+
+        fn sample(&self) -> io::Result<TimeSample> {
+            if !self.up.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+
+            Ok(TimeSample {
+                source: self.name(),
+                offset: 2_000,
+                max_error: 500,
+                trust: self.trust(),
+            })
+        }
+    }
+
+    fn sample(
+        offset: i64,
+        max_error: u64,
+        trust: Trust,
+    ) -> (TimeSample, SelectOptions) {
+        let sample = TimeSample {
+            source: format!("fake{}", offset),
+            offset,
+            max_error,
+            trust,
+        };
+
+        (sample, SelectOptions::default())
+    }
+
+    #[test]
+    fn test_most_trusted_sources_win() {
+        let samples = [
+            sample(5_000, 10, Trust::Low),
+            sample(100, 1_000, Trust::High),
+            sample(400, 2_000, Trust::High),
+            sample(-200, 500, Trust::Medium),
+        ];
+
+        assert_eq!(Some(&samples[1]), best(&samples));
+
+        let combined = combine(&samples).unwrap();
+
+        assert_eq!(160, combined.offset);
+        assert_eq!(1_000, combined.max_error);
+        assert_eq!(Trust::High, combined.trust);
+        assert!(combine(&[]).is_none());
+    }
+
+    #[test]
+    fn test_prefer_and_weight() {
+        let mut samples = [
+            sample(100, 1_000, Trust::Medium),
+            sample(400, 2_000, Trust::Medium),
+        ];
+
+        samples[1].1 = SelectOptions {
+            prefer: true,
+            weight: 4,
+            noselect: false,
+        };
+
+        assert_eq!(Some(&samples[1]), best(&samples));
+        assert_eq!(250, combine(&samples).unwrap().offset);
+    }
+
+    #[test]
+    fn test_best_available_time() {
+        let up = Arc::new(AtomicBool::new(true));
+        let source = FakeSource { up: up.clone() };
+        let mut coordinator = Coordinator::new().source(source);
+        let ntp = NtpSource::new("localhost", 123).trust(Trust::High);
+
+        assert_eq!(Provenance::Ntp, ntp.provenance());
+
+        let best = coordinator.best_available_time();
+
+        assert_eq!(Provenance::Ntp, best.provenance);
+        assert_eq!(Accuracy::High, best.accuracy);
+        assert_eq!(2_000, best.offset);
+
+        up.store(false, Ordering::SeqCst);
+
+        let best = coordinator.best_available_time();
+
+        assert_eq!(Provenance::SystemClock, best.provenance);
+        assert_eq!(Accuracy::Unknown, best.accuracy);
+
+        let mut coordinator = Coordinator::new()
+            .source(FakeSource { up: up.clone() })
+            .holdover(Holdover::new());
+
+        up.store(true, Ordering::SeqCst);
+        coordinator.best_available_time();
+        up.store(false, Ordering::SeqCst);
+
+        let best = coordinator.best_available_time();
+
+        assert_eq!(Provenance::Holdover, best.provenance);
+        assert_eq!(2_000, best.offset);
+        assert!(best.max_error.unwrap() >= 500);
+        assert_eq!(Accuracy::Medium, Accuracy::from_max_error(100_000));
+    }
+
+    #[test]
+    fn test_quorum() {
+        let samples: Vec<TimeSample> = [900, -40_000, 1_000, 1_050, 30_000]
+            .iter()
+            .map(|offset| sample(*offset, 100, Trust::Medium).0)
+            .collect();
+        let agreeing = quorum(&samples, 3, 200).unwrap();
+        let offsets: Vec<i64> = agreeing.iter().map(|s| s.offset).collect();
+
+        assert_eq!(vec![900, 1_000, 1_050], offsets);
+        assert!(quorum(&samples, 4, 200).is_none());
+        assert!(quorum(&[], 0, 200).is_none());
+
+        // The same server listed twice counts once
+        let twice = [samples[0].clone(), samples[0].clone()];
+
+        assert!(quorum(&twice, 2, 200).is_none());
+    }
+}
@@ -0,0 +1,313 @@
+//! Sample history
+//!
+//! The poller writes every successful synchronization into a
+//! [`SampleStore`], which keeps the history for later queries:
+//! [`MemoryStore`] keeps the latest samples in a ring, [`FileStore`]
+//! appends them to a text file surviving restarts, and `SqliteStore`
+//! keeps them in a SQLite database when the `sqlite` feature is enabled
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::NtpResult;
+
+/// Outcome of a successful synchronization
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sample {
+    /// When the sample was taken
+    pub time: SystemTime,
+    /// Server the sample was taken from
+    pub server: String,
+    /// Clock offset in microseconds
+    pub offset: i64,
+    /// Roundtrip delay in microseconds
+    pub roundtrip: u64,
+    /// Stratum of the server
+    pub stratum: u8,
+}
+
+impl Sample {
+    /// Create new sample of `result`, taken from `server` right now
+    pub fn new(server: &str, result: &NtpResult) -> Self {
+        Sample {
+            time: SystemTime::now(),
+            server: server.to_string(),
+            offset: result.offset(),
+            roundtrip: result.roundtrip(),
+            stratum: result.stratum(),
+        }
+    }
+}
+
+/// Storage of the sample history
+pub trait SampleStore {
+    /// Store a new sample
+    fn push(&mut self, sample: &Sample) -> io::Result<()>;
+
+    /// Returns the stored samples taken at or after `since`,
+    /// oldest first
+    fn samples(&self, since: SystemTime) -> io::Result<Vec<Sample>>;
+}
+
+/// Store keeping the latest samples in memory
+pub struct MemoryStore {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+}
+
+impl MemoryStore {
+    /// Create new store keeping up to `capacity` samples
+    pub fn new(capacity: usize) -> Self {
+        MemoryStore {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+}
+
+impl SampleStore for MemoryStore {
+    fn push(&mut self, sample: &Sample) -> io::Result<()> {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample.clone());
+        Ok(())
+    }
+
+    fn samples(&self, since: SystemTime) -> io::Result<Vec<Sample>> {
+        Ok(self
+            .samples
+            .iter()
+            .filter(|sample| sample.time >= since)
+            .cloned()
+            .collect())
+    }
+}
+
+/// Store appending the samples to a text file, one tab separated
+/// line per sample
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileStore {
+    /// Open the store at `path`, created if missing
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(FileStore { path, file })
+    }
+}
+
+impl SampleStore for FileStore {
+    fn push(&mut self, sample: &Sample) -> io::Result<()> {
+        let time = sample.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        writeln!(
+            self.file,
+            "{}.{:09}\t{}\t{}\t{}\t{}",
+            time.as_secs(),
+            time.subsec_nanos(),
+            sample.server,
+            sample.offset,
+            sample.roundtrip,
+            sample.stratum
+        )?;
+        self.file.flush()
+    }
+
+    fn samples(&self, since: SystemTime) -> io::Result<Vec<Sample>> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut samples = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let sample = parse_line(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid sample: {}", line),
+                )
+            })?;
+
+            if sample.time >= since {
+                samples.push(sample);
+            }
+        }
+
+        Ok(samples)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Sample> {
+    let mut fields = line.split('\t');
+    let (secs, nanos) = fields.next()?.split_once('.')?;
+    let time =
+        UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+
+    Some(Sample {
+        time,
+        server: fields.next()?.to_string(),
+        offset: fields.next()?.parse().ok()?,
+        roundtrip: fields.next()?.parse().ok()?,
+        stratum: fields.next()?.parse().ok()?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::io;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use rusqlite::{params, Connection};
+
+    use super::{Sample, SampleStore};
+
+    fn to_io(err: rusqlite::Error) -> io::Error {
+        io::Error::other(err)
+    }
+
+    fn to_nanos(time: SystemTime) -> i64 {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        since_epoch.as_nanos().min(i64::MAX as u128) as i64
+    }
+
+    /// Store keeping the samples in a SQLite database
+    pub struct SqliteStore {
+        conn: Connection,
+    }
+
+    impl SqliteStore {
+        /// Open the database at `path`, created if missing
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            SqliteStore::with_connection(Connection::open(path).map_err(to_io)?)
+        }
+
+        /// Store the samples in the given database
+        pub fn with_connection(conn: Connection) -> io::Result<Self> {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS samples (
+                    time_ns INTEGER NOT NULL,
+                    server TEXT NOT NULL,
+                    offset INTEGER NOT NULL,
+                    roundtrip INTEGER NOT NULL,
+                    stratum INTEGER NOT NULL
+                )",
+                [],
+            )
+            .map_err(to_io)?;
+
+            Ok(SqliteStore { conn })
+        }
+    }
+
+    impl SampleStore for SqliteStore {
+        fn push(&mut self, sample: &Sample) -> io::Result<()> {
+            self.conn
+                .execute(
+                    "INSERT INTO samples VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        to_nanos(sample.time),
+                        sample.server,
+                        sample.offset,
+                        sample.roundtrip as i64,
+                        sample.stratum,
+                    ],
+                )
+                .map(|_| ())
+                .map_err(to_io)
+        }
+
+        fn samples(&self, since: SystemTime) -> io::Result<Vec<Sample>> {
+            let mut stmt = self
+                .conn
+                .prepare(
+                    "SELECT time_ns, server, offset, roundtrip, stratum
+                     FROM samples WHERE time_ns >= ?1 ORDER BY time_ns",
+                )
+                .map_err(to_io)?;
+            let rows = stmt
+                .query_map([to_nanos(since)], |row| {
+                    let nanos = row.get::<_, i64>(0)? as u64;
+
+                    Ok(Sample {
+                        time: UNIX_EPOCH + Duration::from_nanos(nanos),
+                        server: row.get(1)?,
+                        offset: row.get(2)?,
+                        roundtrip: row.get::<_, i64>(3)? as u64,
+                        stratum: row.get(4)?,
+                    })
+                })
+                .map_err(to_io)?;
+
+            rows.collect::<Result<_, _>>().map_err(to_io)
+        }
+    }
+}
+
+#[cfg(test)]
+mod store_tests {
+    use crate::store::{FileStore, MemoryStore, Sample, SampleStore};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn sample(secs: u64) -> Sample {
+        Sample {
+            time: UNIX_EPOCH + Duration::new(secs, 123),
+            server: "pool.ntp.org".to_string(),
+            offset: -42,
+            roundtrip: 1_500,
+            stratum: 2,
+        }
+    }
+
+    #[test]
+    fn test_stores_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("sntprs-store-{}.tsv", std::process::id()));
+        let mut file = FileStore::open(&path).unwrap();
+        let mut memory = MemoryStore::new(2);
+
+        for secs in 1..=3 {
+            file.push(&sample(secs)).unwrap();
+            memory.push(&sample(secs)).unwrap();
+        }
+
+        let since = UNIX_EPOCH + Duration::from_secs(2);
+
+        assert_eq!(vec![sample(2), sample(3)], file.samples(since).unwrap());
+        assert_eq!(
+            vec![sample(2), sample(3)],
+            memory.samples(UNIX_EPOCH).unwrap()
+        );
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store() {
+        use crate::store::SqliteStore;
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let mut store = SqliteStore::with_connection(conn).unwrap();
+
+        store.push(&sample(5)).unwrap();
+        store.push(&sample(1)).unwrap();
+
+        assert_eq!(
+            vec![sample(1), sample(5)],
+            store.samples(UNIX_EPOCH).unwrap()
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Fuzzing and property testing support
+//!
+//! Implements [`arbitrary::Arbitrary`] for the packet and timestamp types
+//! and provides the matching [`proptest`] strategies, so that parsing,
+//! serialization and validation can be exercised with arbitrary input.
+//!
+//! A [`DriftingClock`] simulates a local clock running off frequency by a
+//! given number of PPM, wandering over time, against a virtual timeline,
+//! so the frequency discipline and holdover estimation can be run for
+//! hours of simulated time in a deterministic test
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use sntprs::discipline::Discipline;
+//! use sntprs::testing::DriftingClock;
+//!
+//! let mut clock = DriftingClock::new(25.0);
+//! let mut discipline = Discipline::new();
+//!
+//! for _ in 0..8 {
+//!     clock.advance(Duration::from_secs(64));
+//!     discipline.add_sample_at(clock.instant(), clock.offset());
+//! }
+//!
+//! discipline
+//!     .update_with(|ppm| {
+//!         clock.set_correction(ppm);
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//!
+//! assert!(clock.frequency().abs() < 0.01);
+//! ```
+
+use std::time::{Duration, Instant};
+
+use arbitrary::{Arbitrary, Unstructured};
+use proptest::prelude::*;
+
+use crate::clock::ClockSource;
+use crate::ntppacket::{NtpPacket, RawPacket, NTP_PACKET_SIZE};
+use crate::NtpTimestamp;
+
+const USEC_IN_SEC: f64 = 1_000_000.0;
+/// UNIX time the simulated timeline starts at, 2024-01-01
+const SIMULATION_EPOCH: u64 = 1_704_067_200;
+
+impl<'a> Arbitrary<'a> for NtpPacket {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let raw: RawPacket = u.arbitrary()?;
+
+        Ok(NtpPacket::from(raw))
+    }
+}
+
+impl<'a> Arbitrary<'a> for NtpTimestamp {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(NtpTimestamp::from_bits(u.arbitrary()?))
+    }
+}
+
+/// Strategy generating raw packets with arbitrary content
+pub fn raw_packet() -> impl Strategy<Value = RawPacket> {
+    proptest::collection::vec(any::<u8>(), NTP_PACKET_SIZE)
+        .prop_map(|bytes| *array_ref![bytes, 0, NTP_PACKET_SIZE])
+}
+
+/// Strategy generating packets with arbitrary fields
+pub fn ntp_packet() -> impl Strategy<Value = NtpPacket> {
+    raw_packet().prop_map(NtpPacket::from)
+}
+
+/// Strategy generating arbitrary timestamps
+pub fn ntp_timestamp() -> impl Strategy<Value = NtpTimestamp> {
+    any::<u64>().prop_map(NtpTimestamp::from_bits)
+}
+
+/// Simulated local clock drifting away from a virtual true time
+#[derive(Clone, Debug)]
+pub struct DriftingClock {
+    start: Instant,
+    elapsed: Duration,
+    ppm: f64,
+    wander: f64,
+    correction: f64,
+    error: f64,
+    seed: u64,
+}
+
+impl DriftingClock {
+    /// Create new clock running fast by `ppm`, or slow if negative
+    pub fn new(ppm: f64) -> Self {
+        DriftingClock {
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            ppm,
+            wander: 0.0,
+            correction: 0.0,
+            error: 0.0,
+            seed: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    /// Let the frequency error take a random walk of up to `ppm` per
+    /// second of simulated time
+    pub fn wander(mut self, ppm: f64) -> Self {
+        self.wander = ppm.abs();
+        self
+    }
+
+    /// Seed of the pseudo-random wander, for reproducible runs
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed.max(1);
+        self
+    }
+
+    /// Advance the simulated time by `by`, in steps of at most one
+    /// second
+    pub fn advance(&mut self, by: Duration) {
+        let mut left = by;
+
+        while left > Duration::ZERO {
+            let step = left.min(Duration::from_secs(1));
+            let secs = step.as_secs_f64();
+
+            self.error += self.frequency() * secs;
+            self.ppm += self.wander * self.noise() * secs;
+            self.elapsed += step;
+            left -= step;
+        }
+    }
+
+    /// Returns uniform noise between -1 and 1, from a xorshift generator
+    fn noise(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+
+        (self.seed >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    /// Returns the simulated instant
+    pub fn instant(&self) -> Instant {
+        self.start + self.elapsed
+    }
+
+    /// Returns the time elapsed on the simulated timeline
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the offset a NTP server would measure in microseconds,
+    /// positive when the local clock is behind
+    pub fn offset(&self) -> i64 {
+        (-self.error).round() as i64
+    }
+
+    /// Returns the remaining frequency error in PPM, once corrected
+    pub fn frequency(&self) -> f64 {
+        self.ppm + self.correction
+    }
+
+    /// Set the frequency correction in PPM, as the kernel clock would,
+    /// e.g. with [`Discipline::update_with`](crate::discipline::Discipline::update_with)
+    pub fn set_correction(&mut self, ppm: f64) {
+        self.correction = ppm;
+    }
+
+    /// Step the clock by `offset` microseconds, as measured by
+    /// [`DriftingClock::offset`]
+    pub fn step(&mut self, offset: i64) {
+        self.error += offset as f64;
+    }
+}
+
+impl ClockSource for DriftingClock {
+    fn now(&self) -> NtpTimestamp {
+        let error = self.error / USEC_IN_SEC;
+        let local = Duration::from_secs(SIMULATION_EPOCH) + self.elapsed;
+        let local = if error < 0.0 {
+            local - Duration::from_secs_f64(-error)
+        } else {
+            local + Duration::from_secs_f64(error)
+        };
+
+        NtpTimestamp::from_unix(local)
+    }
+}
+
+#[cfg(test)]
+mod testing_tests {
+    use crate::clock::ClockSource;
+    use crate::discipline::Discipline;
+    use crate::holdover::Holdover;
+    use crate::ntppacket::{NtpPacket, RawPacket};
+    use crate::testing::{
+        ntp_packet, ntp_timestamp, raw_packet, DriftingClock,
+    };
+    use crate::RequestContext;
+    use proptest::prelude::*;
+    use std::time::Duration;
+
+    const POLL: Duration = Duration::from_secs(64);
+
+    #[test]
+    fn test_discipline_convergence() {
+        let mut clock = DriftingClock::new(-40.0).wander(0.001).seed(7);
+        let mut discipline = Discipline::new();
+        let start = clock.now();
+
+        // One hour of polls, the phase stepped away after each update
+        for _ in 0..56 {
+            clock.advance(POLL);
+            discipline.add_sample_at(clock.instant(), clock.offset());
+
+            let updated = discipline
+                .update_with(|ppm| {
+                    clock.set_correction(ppm);
+                    Ok(())
+                })
+                .unwrap();
+
+            if updated.is_some() {
+                clock.step(clock.offset());
+                discipline.add_sample_at(clock.instant(), 0);
+            }
+        }
+
+        assert!(clock.frequency().abs() < 0.5);
+        assert!(clock.offset().abs() < 1_000);
+        assert!((clock.now().diff(start) >> 32) >= 3_583);
+
+        // Holdover bounds the offset reached without any poll
+        let mut holdover = Holdover::with_tolerance(1.0);
+        let drift = -clock.frequency();
+
+        holdover.sync_at(clock.instant(), clock.offset(), 10, drift);
+        clock.advance(Duration::from_secs(600));
+
+        let estimate = holdover.estimate_at(clock.instant()).unwrap();
+
+        assert!(
+            estimate.offset.abs_diff(clock.offset()) <= estimate.uncertainty
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_raw_packet_round_trip(raw in raw_packet()) {
+            prop_assert_eq!(raw, RawPacket::from(&NtpPacket::from(raw)));
+        }
+
+        #[test]
+        fn test_packet_round_trip(packet in ntp_packet()) {
+            prop_assert_eq!(packet, NtpPacket::from(RawPacket::from(&packet)));
+        }
+
+        #[test]
+        fn test_timestamp_diff(a in ntp_timestamp(), b in ntp_timestamp()) {
+            prop_assert_eq!(a, b.wrapping_add(a.diff(b)));
+        }
+
+        #[test]
+        fn test_parse_response_bytes(
+            request in ntp_packet(),
+            recv in any::<u64>(),
+            bytes in proptest::collection::vec(any::<u8>(), 0..100),
+        ) {
+            let context = RequestContext::new(request, recv);
+
+            let _ = crate::parse_response_bytes(&bytes, &context);
+        }
+    }
+}
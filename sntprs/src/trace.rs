@@ -0,0 +1,112 @@
+//! Packet tracing
+//!
+//! Every datagram sent or received by an [`NtpClient`](crate::NtpClient)
+//! can be handed to a tracing callback, together with its direction,
+//! the local time of the event and the peer address. The [`Display`]
+//! implementation renders a hex dump followed by the decoded header
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+
+use crate::ntppacket::NtpPacket;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Direction of a traced packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Single datagram exchanged with an NTP server
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PacketTrace {
+    /// Whether the datagram was sent or received
+    pub direction: Direction,
+    /// Local time of the event in NTP timestamp format (32.32)
+    pub timestamp: u64,
+    /// Local address of the socket
+    pub local: SocketAddr,
+    /// Address of the NTP server
+    pub peer: SocketAddr,
+    /// Datagram content as on the wire
+    pub bytes: Vec<u8>,
+}
+
+impl PacketTrace {
+    /// Returns the decoded packet header, if the datagram is large enough
+    pub fn packet(&self) -> Option<NtpPacket> {
+        NtpPacket::try_from(self.bytes.as_slice()).ok()
+    }
+}
+
+impl Display for PacketTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+
+        writeln!(
+            f,
+            "{} {} {} {} bytes at {:#018x}",
+            self.local,
+            arrow,
+            self.peer,
+            self.bytes.len(),
+            self.timestamp
+        )?;
+
+        for (i, line) in self.bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let hex: Vec<String> =
+                line.iter().map(|b| format!("{:02x}", b)).collect();
+
+            writeln!(f, "{:04x}  {}", i * BYTES_PER_LINE, hex.join(" "))?;
+        }
+
+        match self.packet() {
+            Some(packet) => write!(f, "{:?}", packet),
+            None => write!(f, "<truncated packet>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use crate::trace::{Direction, PacketTrace};
+    use crate::{NtpPacket, RawPacket};
+
+    fn trace(bytes: Vec<u8>) -> PacketTrace {
+        PacketTrace {
+            direction: Direction::Sent,
+            timestamp: 0xe93c_7f00_8000_0000,
+            local: "10.0.0.1:40000".parse().unwrap(),
+            peer: "192.0.2.1:123".parse().unwrap(),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_trace_display() {
+        let short = trace((0..18).collect()).to_string();
+
+        assert_eq!(
+            "10.0.0.1:40000 -> 192.0.2.1:123 18 bytes at 0xe93c7f0080000000\n\
+             0000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n\
+             0010  10 11\n\
+             <truncated packet>",
+            short
+        );
+
+        let packet = NtpPacket::new();
+        let full = trace(RawPacket::from(&packet).to_vec()).to_string();
+        let lines: Vec<&str> = full.lines().collect();
+
+        assert_eq!(5, lines.len());
+        assert!(lines[1].starts_with("0000  23 00 00 e8"));
+        assert!(lines[3].starts_with("0020  "));
+        assert_eq!(format!("{:?}", packet), lines[4]);
+    }
+}
@@ -0,0 +1,552 @@
+//! Pluggable transport
+//!
+//! [`NtpClient`](crate::NtpClient) is generic over the [`Transport`]
+//! carrying its datagrams and the [`ClockSource`](crate::clock::ClockSource)
+//! stamping them, so that embedded targets and tests can plug their own
+//! implementations without any dynamic dispatch. The default
+//! [`UdpTransport`] uses the sockets of the standard library, while a
+//! [`DynTransport`] lets applications pick the transport at runtime
+//!
+//! ```rust,no_run
+//! use sntprs::clock::SystemClock;
+//! use sntprs::transport::UdpTransport;
+//! use sntprs::NtpClient;
+//!
+//! let client: NtpClient<UdpTransport, SystemClock> = NtpClient::new()
+//!     .transport(UdpTransport::new())
+//!     .clock(SystemClock);
+//! let result = client.request("pool.ntp.org", 123);
+//! ```
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::ntppacket::{MAC_SIZES, NTP_PACKET_SIZE};
+use crate::{random_u64, Error, SourceCheck};
+
+const DYNAMIC_PORT_MIN: u16 = 49_152;
+const DYNAMIC_PORT_MAX: u16 = 65_535;
+const ORIGIN_OFFSET: usize = 24;
+const TRANSMIT_OFFSET: usize = 40;
+
+/// Carrier of the datagrams exchanged with the servers
+pub trait Transport {
+    /// Send `request` to the first address of `dest` accepting it, then
+    /// wait at most `timeout` for a datagram answering it into `response`
+    fn exchange(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Exchange>;
+
+    /// Same as [`exchange`](Transport::exchange), also dropping the
+    /// datagrams whose source `check` rejects while waiting for the
+    /// response. The default implementation leaves the check to the
+    /// caller, which fails the exchange on a rejected source
+    fn exchange_checked(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+        check: SourceCheck,
+    ) -> io::Result<Exchange> {
+        let _ = check;
+
+        self.exchange(dest, request, response, timeout)
+    }
+}
+
+/// Transport chosen at runtime, e.g. plain UDP, SOCKS or NTS,
+/// used by [`DynNtpClient`](crate::DynNtpClient)
+pub type DynTransport = Arc<dyn Transport + Send + Sync>;
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn exchange(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Exchange> {
+        (**self).exchange(dest, request, response, timeout)
+    }
+
+    fn exchange_checked(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+        check: SourceCheck,
+    ) -> io::Result<Exchange> {
+        (**self).exchange_checked(dest, request, response, timeout, check)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn exchange(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Exchange> {
+        (**self).exchange(dest, request, response, timeout)
+    }
+
+    fn exchange_checked(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+        check: SourceCheck,
+    ) -> io::Result<Exchange> {
+        (**self).exchange_checked(dest, request, response, timeout, check)
+    }
+}
+
+/// Datagrams exchanged by a [`Transport`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exchange {
+    /// Address the request was sent to
+    pub server: SocketAddr,
+    /// Address the request was sent from
+    pub local: SocketAddr,
+    /// Address the response came from
+    pub source: SocketAddr,
+    /// Size of the response
+    pub size: usize,
+    /// When the request was sent
+    pub sent: Instant,
+}
+
+/// Local port the requests are sent from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SourcePort {
+    /// Port chosen by the operating system
+    Ephemeral,
+    /// Port picked at random in the dynamic range for every request
+    Random,
+    /// Given port, e.g. 123 for firewalls only passing NTP traffic
+    /// with that source port, which usually requires elevated privileges
+    Fixed(u16),
+}
+
+/// Held while a socket is bound to a fixed source port
+static FIXED_PORT: Mutex<()> = Mutex::new(());
+
+type SocketHook = Arc<dyn Fn(&UdpSocket) -> io::Result<()> + Send + Sync>;
+
+/// Transport opening a new UDP socket for every exchange.
+/// Datagrams too short, not echoing the request transmit timestamp or
+/// coming from a source the [`SourceCheck`] rejects, e.g. stray, late
+/// or spoofed answers, are dropped while waiting for the response
+#[derive(Clone)]
+pub struct UdpTransport {
+    source_port: SourcePort,
+    bind_address: Option<IpAddr>,
+    socket_hook: Option<SocketHook>,
+    connected: bool,
+}
+
+impl UdpTransport {
+    /// Create new transport sending from an ephemeral port
+    pub fn new() -> Self {
+        UdpTransport {
+            source_port: SourcePort::Ephemeral,
+            bind_address: None,
+            socket_hook: None,
+            connected: false,
+        }
+    }
+
+    /// Send the requests from the given local address,
+    /// used for servers of the same address family
+    pub fn bind_address(mut self, addr: IpAddr) -> Self {
+        self.bind_address = Some(addr);
+        self
+    }
+
+    /// Send the requests from the given local port
+    pub fn source_port(mut self, port: SourcePort) -> Self {
+        self.source_port = port;
+        self
+    }
+
+    /// Connect every socket to the server before sending the request,
+    /// so that the kernel drops the datagrams of any other address or
+    /// port. Along with [`SourcePort::Random`], every exchange then goes
+    /// through its own socket on an unpredictable port, which resists
+    /// off-path spoofing best in hostile networks
+    pub fn connected(mut self, enabled: bool) -> Self {
+        self.connected = enabled;
+        self
+    }
+
+    /// Hand every socket to `hook` once bound and before sending the
+    /// request, to set platform specific options such as `SO_MARK`.
+    /// The request fails with the error returned by the hook
+    pub fn on_socket<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&UdpSocket) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.socket_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Create a socket with the user options applied
+    pub(crate) fn open(&self, ipv6: bool) -> io::Result<UdpSocket> {
+        let socket = self.bind(ipv6)?;
+
+        if let Some(hook) = &self.socket_hook {
+            hook(&socket)?;
+        }
+
+        Ok(socket)
+    }
+
+    fn bind(&self, ipv6: bool) -> io::Result<UdpSocket> {
+        const RANDOM_PORT_ATTEMPTS: usize = 8;
+        let ip: IpAddr = match self.bind_address {
+            Some(addr) if addr.is_ipv6() == ipv6 => addr,
+            _ if ipv6 => Ipv6Addr::UNSPECIFIED.into(),
+            _ => Ipv4Addr::UNSPECIFIED.into(),
+        };
+
+        match self.source_port {
+            SourcePort::Ephemeral => UdpSocket::bind((ip, 0)),
+            SourcePort::Fixed(port) => {
+                UdpSocket::bind((ip, port)).map_err(|err| {
+                    if err.kind() != io::ErrorKind::PermissionDenied {
+                        return err;
+                    }
+
+                    Error::PrivilegedPort {
+                        port,
+                        os_error: err.raw_os_error(),
+                    }
+                    .into()
+                })
+            }
+            SourcePort::Random => {
+                let mut last_err = None;
+
+                for _ in 0..RANDOM_PORT_ATTEMPTS {
+                    let range = DYNAMIC_PORT_MAX - DYNAMIC_PORT_MIN + 1;
+                    let port = DYNAMIC_PORT_MIN
+                        + (random_u64() % u64::from(range)) as u16;
+
+                    match UdpSocket::bind((ip, port)) {
+                        Ok(socket) => return Ok(socket),
+                        Err(err) => last_err = Some(err),
+                    }
+                }
+
+                Err(last_err.unwrap())
+            }
+        }
+    }
+}
+
+impl Default for UdpTransport {
+    fn default() -> Self {
+        UdpTransport::new()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn exchange(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Exchange> {
+        self.exchange_checked(
+            dest,
+            request,
+            response,
+            timeout,
+            SourceCheck::Permissive,
+        )
+    }
+
+    fn exchange_checked(
+        &self,
+        dest: &[SocketAddr],
+        request: &[u8],
+        response: &mut [u8],
+        timeout: Duration,
+        check: SourceCheck,
+    ) -> io::Result<Exchange> {
+        // Only one socket at a time can be bound to a fixed port
+        let _guard = match self.source_port {
+            SourcePort::Fixed(_) => Some(FIXED_PORT.lock().unwrap()),
+            _ => None,
+        };
+        let ipv6 = matches!(dest.first(), Some(SocketAddr::V6(_)));
+        let socket = self.open(ipv6)?;
+        let server = send_first(dest, request, &socket, self.connected)?;
+        let sent = Instant::now();
+        let (size, source) = recv_answer(
+            &socket,
+            server,
+            request,
+            response,
+            check,
+            sent + timeout,
+        )?;
+
+        Ok(Exchange {
+            server,
+            local: socket.local_addr()?,
+            source,
+            size,
+            sent,
+        })
+    }
+}
+
+/// Wait until `deadline` for a datagram answering `request` sent to
+/// `server`, from a source accepted by `check`
+fn recv_answer(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    request: &[u8],
+    response: &mut [u8],
+    check: SourceCheck,
+    deadline: Instant,
+) -> io::Result<(usize, SocketAddr)> {
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))?;
+
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (size, source) = socket.recv_from(response)?;
+
+        if !check.accepts(server, source) {
+            debug!("Dropping {} bytes from unexpected {}", size, source);
+            continue;
+        }
+
+        if answers(request, &response[..size]) {
+            return Ok((size, source));
+        }
+
+        debug!("Dropping unexpected {} bytes from {}", size, source);
+    }
+}
+
+/// Returns `local` with the address the system routes datagrams to
+/// `peer` from, when a socket bound to the wildcard address sent them.
+/// Connecting a probe socket sends nothing
+pub(crate) fn route_source(local: SocketAddr, peer: SocketAddr) -> SocketAddr {
+    if !local.ip().is_unspecified() {
+        return local;
+    }
+
+    let probe = UdpSocket::bind((local.ip(), 0)).and_then(|socket| {
+        socket.connect(peer)?;
+        socket.local_addr()
+    });
+
+    match probe {
+        Ok(addr) => SocketAddr::new(addr.ip(), local.port()),
+        Err(_) => local,
+    }
+}
+
+/// Returns whether `response` has the size of a server response and
+/// echoes the transmit timestamp of `request` as its origin timestamp
+fn answers(request: &[u8], response: &[u8]) -> bool {
+    let mac_size = response.len().saturating_sub(NTP_PACKET_SIZE);
+
+    if response.len() < NTP_PACKET_SIZE
+        || request.len() < NTP_PACKET_SIZE
+        || (mac_size != 0 && !MAC_SIZES.contains(&mac_size))
+    {
+        return false;
+    }
+
+    response[ORIGIN_OFFSET..ORIGIN_OFFSET + 8]
+        == request[TRANSMIT_OFFSET..TRANSMIT_OFFSET + 8]
+}
+
+/// Send `request` to the first of `dest` accepting it, connecting
+/// `socket` to it first if `connect` is set
+fn send_first(
+    dest: &[SocketAddr],
+    request: &[u8],
+    socket: &UdpSocket,
+    connect: bool,
+) -> io::Result<SocketAddr> {
+    for &addr in dest {
+        debug!("Address: {}", &addr);
+
+        let sent = if connect {
+            socket.connect(addr).and_then(|_| socket.send(request))
+        } else {
+            socket.send_to(request, addr)
+        };
+
+        match sent {
+            Ok(_) => return Ok(addr),
+            Err(err) => debug!("{}. Try another one", err),
+        }
+    }
+
+    Err(Error::NotResponding.into())
+}
+
+#[cfg(test)]
+mod transport_tests {
+    use crate::clock::ClockSource;
+    use crate::fakeserver::FakeServer;
+    use crate::transport::{route_source, SourcePort, Transport, UdpTransport};
+    use crate::{DynNtpClient, NtpClient, NtpPacket, NtpTimestamp, RawPacket};
+    use std::convert::TryFrom;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+
+    fn local_time() -> NtpTimestamp {
+        NtpTimestamp::from_unix(Duration::from_secs(1_700_000_000))
+    }
+
+    #[derive(Clone)]
+    struct FixedClock;
+
+    impl ClockSource for FixedClock {
+        fn now(&self) -> NtpTimestamp {
+            local_time()
+        }
+    }
+
+    #[test]
+    fn test_client_over_custom_transport_and_clock() {
+        let server = FakeServer::new().time(local_time().to_bits() + (1 << 32));
+        let client =
+            NtpClient::new().transport(server.clone()).clock(FixedClock);
+        let result = client.request("127.0.0.1", 123).unwrap();
+
+        assert_eq!(1_000_000, result.offset());
+        assert_eq!(0, result.roundtrip());
+        assert_eq!(1_700_000_001, result.sec());
+
+        let client: DynNtpClient<FixedClock> =
+            NtpClient::new().dyn_transport(server).clock(FixedClock);
+
+        assert_eq!(1_000_000, client.request("::1", 123).unwrap().offset());
+    }
+
+    #[test]
+    fn test_stray_datagrams_dropped() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 48];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            let req = NtpPacket::try_from(&buf[..]).unwrap();
+            let mut resp = NtpPacket::new();
+
+            server.send_to(&[0; 12], client).unwrap();
+            server.send_to(&RawPacket::from(&resp), client).unwrap();
+            resp.origin_timestamp = req.tx_timestamp;
+            server.send_to(&RawPacket::from(&resp), client).unwrap();
+        });
+
+        let request = RawPacket::from(&NtpPacket::new());
+        let mut response = [0u8; 76];
+        let exchange = UdpTransport::new()
+            .exchange(&[addr], &request, &mut response, Duration::from_secs(2))
+            .unwrap();
+
+        assert_eq!(48, exchange.size);
+        assert_eq!(request[40..48], response[24..32]);
+    }
+
+    #[test]
+    fn test_connected_socket() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let intruder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 48];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            let req = NtpPacket::try_from(&buf[..]).unwrap();
+            let mut resp = NtpPacket::new();
+
+            resp.origin_timestamp = req.tx_timestamp;
+            resp.stratum = 9;
+            intruder.send_to(&RawPacket::from(&resp), client).unwrap();
+            resp.stratum = 1;
+            server.send_to(&RawPacket::from(&resp), client).unwrap();
+        });
+
+        let request = RawPacket::from(&NtpPacket::new());
+        let mut response = [0u8; 76];
+        let exchange = UdpTransport::new()
+            .source_port(SourcePort::Random)
+            .connected(true)
+            .exchange(&[addr], &request, &mut response, Duration::from_secs(2))
+            .unwrap();
+
+        assert_eq!(addr, exchange.source);
+        assert_eq!(1, response[1]);
+    }
+
+    #[test]
+    fn test_spoofed_source_dropped() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let intruder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 48];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            let req = NtpPacket::try_from(&buf[..]).unwrap();
+            let mut resp = NtpPacket::new();
+
+            resp.li_vn_mode = 0b00_100_100;
+            resp.origin_timestamp = req.tx_timestamp;
+            resp.recv_timestamp = req.tx_timestamp;
+            resp.tx_timestamp = req.tx_timestamp;
+            resp.stratum = 9;
+            intruder.send_to(&RawPacket::from(&resp), client).unwrap();
+            resp.stratum = 1;
+            server.send_to(&RawPacket::from(&resp), client).unwrap();
+        });
+
+        let result = NtpClient::new()
+            .timeout(Duration::from_secs(2))
+            .request("127.0.0.1", u32::from(addr.port()))
+            .unwrap();
+
+        assert_eq!(1, result.stratum());
+    }
+
+    #[test]
+    fn test_route_source() {
+        let peer = "127.0.0.1:123".parse().unwrap();
+        let bound = "10.0.0.1:40000".parse().unwrap();
+
+        assert_eq!(bound, route_source(bound, peer));
+        assert_eq!(
+            "127.0.0.1:40000".parse::<SocketAddr>().unwrap(),
+            route_source("0.0.0.0:40000".parse().unwrap(), peer)
+        );
+    }
+}
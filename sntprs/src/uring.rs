@@ -0,0 +1,332 @@
+//! io_uring transport for shared sockets
+//!
+//! Requests are queued on the submission ring and only handed to the
+//! kernel when waiting for responses, while a fixed set of receive
+//! operations stays posted on the socket. Many exchanges then cost a
+//! single system call instead of one per datagram
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem;
+use std::net::{
+    Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket,
+};
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use io_uring::squeue::Entry;
+use io_uring::types::{Fd, SubmitArgs, Timespec};
+use io_uring::{opcode, IoUring};
+use log::debug;
+
+use crate::ntppacket::MAX_MAC_SIZE;
+use crate::NTP_PACKET_SIZE;
+
+const RING_ENTRIES: u32 = 256;
+const RECV_SLOTS: usize = 32;
+const DATAGRAM_SIZE: usize = NTP_PACKET_SIZE + MAX_MAC_SIZE;
+const RECV_TAG: u64 = 1 << 63;
+const CANCEL_TAG: u64 = 1 << 62;
+
+/// Buffers of a single operation, boxed so that the addresses handed
+/// to the kernel stay valid until the operation completes
+struct Slot {
+    buf: [u8; DATAGRAM_SIZE],
+    addr: libc::sockaddr_storage,
+    iov: libc::iovec,
+    msg: libc::msghdr,
+}
+
+impl Slot {
+    fn new() -> Box<Self> {
+        // SAFETY: all-zero bytes are a valid value for these C structs
+        let mut slot: Box<Slot> = Box::new(unsafe { mem::zeroed() });
+
+        slot.iov.iov_base = slot.buf.as_mut_ptr().cast();
+        slot.iov.iov_len = DATAGRAM_SIZE;
+        slot.msg.msg_name =
+            (&mut slot.addr as *mut libc::sockaddr_storage).cast();
+        slot.msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+        slot.msg.msg_iov = &mut slot.iov;
+        slot.msg.msg_iovlen = 1;
+        slot
+    }
+
+    fn set_addr(&mut self, addr: SocketAddr) {
+        // SAFETY: sockaddr_storage is large and aligned enough for both
+        // address families
+        let len = unsafe {
+            match addr {
+                SocketAddr::V4(addr) => {
+                    let sin = &mut *(&mut self.addr as *mut _
+                        as *mut libc::sockaddr_in);
+
+                    sin.sin_family = libc::AF_INET as _;
+                    sin.sin_port = addr.port().to_be();
+                    sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                    mem::size_of::<libc::sockaddr_in>()
+                }
+                SocketAddr::V6(addr) => {
+                    let sin6 = &mut *(&mut self.addr as *mut _
+                        as *mut libc::sockaddr_in6);
+
+                    sin6.sin6_family = libc::AF_INET6 as _;
+                    sin6.sin6_port = addr.port().to_be();
+                    sin6.sin6_addr.s6_addr = addr.ip().octets();
+                    sin6.sin6_flowinfo = addr.flowinfo();
+                    sin6.sin6_scope_id = addr.scope_id();
+                    mem::size_of::<libc::sockaddr_in6>()
+                }
+            }
+        };
+
+        self.msg.msg_namelen = len as _;
+    }
+
+    fn addr(&self) -> Option<SocketAddr> {
+        // SAFETY: the family field tells which structure the kernel wrote
+        unsafe {
+            match i32::from(self.addr.ss_family) {
+                libc::AF_INET => {
+                    let sin =
+                        &*(&self.addr as *const _ as *const libc::sockaddr_in);
+                    let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+
+                    Some(
+                        SocketAddrV4::new(ip, u16::from_be(sin.sin_port))
+                            .into(),
+                    )
+                }
+                libc::AF_INET6 => {
+                    let sin6 =
+                        &*(&self.addr as *const _ as *const libc::sockaddr_in6);
+                    let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+
+                    Some(
+                        SocketAddrV6::new(
+                            ip,
+                            u16::from_be(sin6.sin6_port),
+                            sin6.sin6_flowinfo,
+                            sin6.sin6_scope_id,
+                        )
+                        .into(),
+                    )
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+/// UDP socket driven through an io_uring instance
+pub(crate) struct UringSocket {
+    ring: IoUring,
+    socket: UdpSocket,
+    // Boxed as the slots point into themselves
+    #[allow(clippy::vec_box)]
+    recvs: Vec<Box<Slot>>,
+    sends: HashMap<u64, Box<Slot>>,
+    next_send: u64,
+    ready: VecDeque<(usize, i32)>,
+    outstanding: usize,
+    timeout: Timespec,
+}
+
+impl UringSocket {
+    /// Drive `socket` through a new ring, waking up at least every
+    /// `timeout` while waiting for responses
+    pub(crate) fn new(
+        socket: UdpSocket,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let mut uring = UringSocket {
+            ring: IoUring::new(RING_ENTRIES)?,
+            socket,
+            recvs: (0..RECV_SLOTS).map(|_| Slot::new()).collect(),
+            sends: HashMap::new(),
+            next_send: 0,
+            ready: VecDeque::new(),
+            outstanding: 0,
+            timeout: Timespec::from(timeout),
+        };
+
+        for idx in 0..RECV_SLOTS {
+            uring.post_recv(idx)?;
+        }
+
+        Ok(uring)
+    }
+
+    /// Returns the underlying socket
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Queue a datagram to `addr`, sent with the next wait
+    pub(crate) fn send_to(
+        &mut self,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> io::Result<usize> {
+        let len = buf.len().min(DATAGRAM_SIZE);
+        let mut slot = Slot::new();
+        let id = self.next_send;
+
+        slot.buf[..len].copy_from_slice(&buf[..len]);
+        slot.iov.iov_len = len;
+        slot.set_addr(addr);
+
+        let entry = opcode::SendMsg::new(self.fd(), &slot.msg)
+            .build()
+            .user_data(id);
+
+        self.next_send = (self.next_send + 1) % CANCEL_TAG;
+        self.sends.insert(id, slot);
+
+        if let Err(err) = self.push(&entry) {
+            self.sends.remove(&id);
+            return Err(err);
+        }
+
+        Ok(len)
+    }
+
+    /// Send the queued datagrams and wait for the next one received,
+    /// failing with `TimedOut` if none arrives within the timeout
+    pub(crate) fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            if let Some((idx, res)) = self.ready.pop_front() {
+                let slot = &self.recvs[idx];
+                let received = if res < 0 {
+                    Err(io::Error::from_raw_os_error(-res))
+                } else {
+                    let len = (res as usize).min(buf.len());
+
+                    buf[..len].copy_from_slice(&slot.buf[..len]);
+                    slot.addr().map(|addr| (len, addr)).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Unknown source address family",
+                        )
+                    })
+                };
+
+                self.post_recv(idx)?;
+                return received;
+            }
+
+            let args = SubmitArgs::new().timespec(&self.timeout);
+
+            match self.ring.submitter().submit_with_args(1, &args) {
+                Ok(_) => {}
+                Err(err) if err.raw_os_error() == Some(libc::ETIME) => {
+                    self.drain();
+
+                    if self.ready.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "No datagram received",
+                        ));
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+
+            self.drain();
+        }
+    }
+
+    fn fd(&self) -> Fd {
+        Fd(self.socket.as_raw_fd())
+    }
+
+    fn post_recv(&mut self, idx: usize) -> io::Result<()> {
+        let slot = &mut self.recvs[idx];
+
+        slot.msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+
+        let entry =
+            opcode::RecvMsg::new(Fd(self.socket.as_raw_fd()), &mut slot.msg)
+                .build()
+                .user_data(RECV_TAG | idx as u64);
+
+        self.push(&entry)
+    }
+
+    fn push(&mut self, entry: &Entry) -> io::Result<()> {
+        // SAFETY: the buffers referenced by the entries are boxed and only
+        // released once their completion has been reaped
+        unsafe {
+            if self.ring.submission().push(entry).is_err() {
+                self.ring.submit()?;
+                self.ring.submission().push(entry).map_err(|_| {
+                    io::Error::other("io_uring submission queue is full")
+                })?;
+            }
+        }
+
+        if entry.get_user_data() & CANCEL_TAG == 0 {
+            self.outstanding += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Reap the completions, keeping the received datagrams
+    fn drain(&mut self) {
+        for cqe in self.ring.completion() {
+            let data = cqe.user_data();
+
+            if data & CANCEL_TAG != 0 {
+                continue;
+            }
+
+            self.outstanding -= 1;
+
+            if data & RECV_TAG != 0 {
+                self.ready
+                    .push_back(((data & !RECV_TAG) as usize, cqe.result()));
+            } else if self.sends.remove(&data).is_some() && cqe.result() < 0 {
+                debug!(
+                    "Send failed: {}",
+                    io::Error::from_raw_os_error(-cqe.result())
+                );
+            }
+        }
+    }
+}
+
+impl Drop for UringSocket {
+    fn drop(&mut self) {
+        // Posted operations must complete before their buffers are freed
+        let cancels: Vec<Entry> = (0..RECV_SLOTS)
+            .map(|idx| {
+                opcode::AsyncCancel::new(RECV_TAG | idx as u64)
+                    .build()
+                    .user_data(CANCEL_TAG)
+            })
+            .collect();
+
+        for entry in &cancels {
+            if self.push(entry).is_err() {
+                break;
+            }
+        }
+
+        while self.outstanding > 0 {
+            if self.ring.submit_and_wait(1).is_err() {
+                // Leak the buffers rather than risk the kernel writing
+                // into freed memory
+                mem::forget(mem::take(&mut self.recvs));
+                mem::forget(mem::take(&mut self.sends));
+                return;
+            }
+
+            self.drain();
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Clock setting
+//!
+//! Every code path changing the system clock, compiled out by the
+//! `measure-only` feature
+
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Local, TimeZone, Timelike, Utc};
+use log::debug;
+
+#[cfg(unix)]
+use super::unix::{
+    get_frequency, set_frequency, set_rtc, slew_time, step_time, sync_time,
+};
+#[cfg(windows)]
+use super::windows::{
+    get_frequency, set_frequency, set_rtc, slew_time, step_time, sync_time,
+};
+use crate::Error;
+
+/// Advisory lock held while setting the system time, so that processes
+/// using this crate never step the clock simultaneously. Released when
+/// dropped
+pub struct ClockLock {
+    _file: File,
+}
+
+/// Path of the clock lock used unless [`set_lock_path`] changed it, in a
+/// runtime directory only root can write to
+#[cfg(unix)]
+pub const DEFAULT_LOCK_PATH: &str = "/run/sntprs/clock.lock";
+/// Path of the clock lock used unless [`set_lock_path`] changed it, or
+/// `%ProgramData%` points to another directory
+#[cfg(windows)]
+pub const DEFAULT_LOCK_PATH: &str = r"C:\ProgramData\sntprs\clock.lock";
+
+static LOCK_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Use the lock file at `path` for the clock lock of this process, e.g.
+/// for a process setting the clock with `CAP_SYS_TIME` but not root. All
+/// the processes setting the clock must use the same path
+pub fn set_lock_path<P: Into<PathBuf>>(path: P) {
+    *LOCK_PATH.lock().unwrap() = Some(path.into());
+}
+
+/// Take the clock lock, a file lock on [`DEFAULT_LOCK_PATH`] unless
+/// [`set_lock_path`] changed it. Fails with [`Error::Busy`] if another
+/// process, or another thread, holds it
+pub fn lock_clock() -> io::Result<ClockLock> {
+    lock_clock_at(lock_path())
+}
+
+/// Take the clock lock on the file at `path`, created readable and
+/// writable by its owner only, along with its directory when missing.
+/// Fails with [`Error::Busy`] if another process, or another thread,
+/// holds it
+pub fn lock_clock_at<P: AsRef<Path>>(path: P) -> io::Result<ClockLock> {
+    let path = path.as_ref();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = OpenOptions::new();
+
+    options.create(true).write(true).truncate(false);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let file = options.open(path)?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(ClockLock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(Error::Busy.into()),
+        Err(TryLockError::Error(err)) => Err(err),
+    }
+}
+
+fn lock_path() -> PathBuf {
+    if let Some(path) = LOCK_PATH.lock().unwrap().clone() {
+        return path;
+    }
+
+    #[cfg(windows)]
+    if let Some(data) = std::env::var_os("ProgramData") {
+        return Path::new(&data).join("sntprs").join("clock.lock");
+    }
+
+    PathBuf::from(DEFAULT_LOCK_PATH)
+}
+
+/// How the system time was changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AdjustMethod {
+    /// Set at once
+    Stepped,
+    /// Sped up or slowed down gradually by the kernel
+    Slewed,
+}
+
+/// Change made to the system time, for logging and auditing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClockAdjustment {
+    /// How the time was changed
+    pub method: AdjustMethod,
+    /// System time right before the change
+    pub old_time: SystemTime,
+    /// System time aimed at, reached at once when stepped or at the end
+    /// of the slew
+    pub new_time: SystemTime,
+    /// Error left when the call returns in microseconds, the system time
+    /// minus the time aimed at. The whole offset while slewing
+    pub residual: i64,
+}
+
+impl Display for ClockAdjustment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+        write!(
+            f,
+            "Clock {} from {} to {} UTC, residual {} us",
+            match self.method {
+                AdjustMethod::Stepped => "stepped",
+                AdjustMethod::Slewed => "slewing",
+            },
+            DateTime::<Utc>::from(self.old_time).format(FORMAT),
+            DateTime::<Utc>::from(self.new_time).format(FORMAT),
+            self.residual
+        )
+    }
+}
+
+/// Set up system time based on the given parameters, under the clock
+/// lock. Returns the change made, or fails when the time could not be
+/// set, with kind [`io::ErrorKind::Unsupported`] on Android and iOS and
+/// [`io::ErrorKind::InvalidInput`] for nanoseconds out of range
+/// Args:
+/// * sec - Seconds since UNIX epoch start
+/// * nsec - Fraction of seconds from an NTP response
+pub fn update_system_time(sec: u32, nsec: u32) -> io::Result<ClockAdjustment> {
+    let time =
+        Utc.timestamp_opt(sec as i64, nsec)
+            .single()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid time to set",
+                )
+            })?;
+    let _lock = lock_clock()?;
+    let local_time = time.with_timezone(&Local);
+    debug!(
+        "UTC time: {:02}:{:02}:{:02}",
+        time.hour(),
+        time.minute(),
+        time.second()
+    );
+    debug!(
+        "{} time: {:02}:{:02}:{:02}",
+        local_time.offset(),
+        local_time.hour(),
+        local_time.minute(),
+        local_time.second()
+    );
+
+    let old_time = SystemTime::now();
+    let start = Instant::now();
+
+    sync_time(local_time)?;
+
+    Ok(ClockAdjustment {
+        method: AdjustMethod::Stepped,
+        old_time,
+        new_time: time.into(),
+        residual: residual(time.into(), start),
+    })
+}
+
+/// Offsets from which the clock is stepped rather than slewed, in
+/// microseconds
+const STEP_THRESHOLD: u64 = 128_000;
+
+/// Correct the system time by a measured offset rather than setting it to
+/// the time of a response, leaving out the time elapsed since the
+/// measurement. Offsets under 128 ms are slewed gradually where
+/// supported, larger ones are stepped at once, under the clock lock.
+/// Returns the change made
+/// Args:
+/// * offset_us - Offset of the server time from the system time, in
+///   microseconds
+pub fn adjust_system_time(offset_us: i64) -> io::Result<ClockAdjustment> {
+    let _lock = lock_clock()?;
+    let old_time = SystemTime::now();
+    let start = Instant::now();
+    let magnitude = Duration::from_micros(offset_us.unsigned_abs());
+    let new_time = if offset_us < 0 {
+        old_time - magnitude
+    } else {
+        old_time + magnitude
+    };
+
+    if offset_us.unsigned_abs() < STEP_THRESHOLD {
+        debug!("Slewing the clock by {} us", offset_us);
+
+        match slew_time(offset_us) {
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+            Err(err) => return Err(err),
+            Ok(()) => {
+                return Ok(ClockAdjustment {
+                    method: AdjustMethod::Slewed,
+                    old_time,
+                    new_time,
+                    residual: -offset_us,
+                })
+            }
+        }
+    }
+
+    debug!("Stepping the clock by {} us", offset_us);
+
+    step_time(offset_us)?;
+
+    Ok(ClockAdjustment {
+        method: AdjustMethod::Stepped,
+        old_time,
+        new_time,
+        residual: residual(new_time, start),
+    })
+}
+
+/// Returns the system time minus `target`, advanced by the time elapsed
+/// since `start`, in microseconds
+fn residual(target: SystemTime, start: Instant) -> i64 {
+    let target = target + start.elapsed();
+
+    match SystemTime::now().duration_since(target) {
+        Ok(ahead) => ahead.as_micros() as i64,
+        Err(err) => -(err.duration().as_micros() as i64),
+    }
+}
+
+/// Adjust the kernel clock frequency
+/// Args:
+/// * ppm - Frequency correction in parts per million,
+///   positive values make the clock run faster
+pub fn adjust_frequency(ppm: f64) -> io::Result<()> {
+    debug!("Clock frequency correction: {:.3} ppm", ppm);
+
+    set_frequency(ppm)
+}
+
+/// Returns the kernel clock frequency correction in parts per million,
+/// as left by a previous adjustment
+pub fn current_frequency() -> io::Result<f64> {
+    get_frequency()
+}
+
+/// Write the given time to the hardware real time clock, kept in UTC,
+/// so that it survives power cycles. Only supported on Linux
+/// Args:
+/// * device - Hardware clock device, e.g. `/dev/rtc0`
+/// * sec - Seconds since UNIX epoch start
+/// * nsec - Fraction of seconds, rounded as the clock has a resolution
+///   of one second
+pub fn write_rtc<P: AsRef<Path>>(
+    device: P,
+    sec: u32,
+    nsec: u32,
+) -> io::Result<()> {
+    let sec = i64::from(sec) + i64::from(nsec >= 500_000_000);
+    let time = Utc.timestamp_opt(sec, 0).unwrap();
+
+    debug!("RTC time: {}", time);
+
+    set_rtc(device.as_ref(), time)
+}
+
+#[cfg(test)]
+mod clockset_tests {
+    use crate::utils::clockset::residual;
+    use crate::utils::lock_clock_at;
+    use crate::utils::{update_system_time, AdjustMethod, ClockAdjustment};
+    use crate::Error;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_current_frequency() {
+        use crate::utils::current_frequency;
+
+        // Allowed without privileges, as nothing is changed
+        let ppm = current_frequency().unwrap();
+
+        assert!(ppm.abs() <= crate::discipline::MAX_FREQUENCY_PPM);
+    }
+
+    #[test]
+    fn test_clock_lock() {
+        let dir = std::env::temp_dir()
+            .join(format!("sntprs-clock-lock-{}", std::process::id()));
+        let path = dir.join("clock.lock");
+        let lock = lock_clock_at(&path).unwrap();
+        let err = lock_clock_at(&path).err().unwrap();
+
+        assert_eq!(Some(Error::Busy), Error::from_io(&err));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+
+            assert_eq!(0o600, mode & 0o777);
+        }
+
+        drop(lock);
+
+        assert!(lock_clock_at(&path).is_ok());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_clock_adjustment() {
+        let start = Instant::now();
+        let behind =
+            residual(SystemTime::now() + Duration::from_secs(2), start);
+
+        assert!((-2_010_000..=-1_990_000).contains(&behind));
+
+        let adjustment = ClockAdjustment {
+            method: AdjustMethod::Stepped,
+            old_time: UNIX_EPOCH + Duration::from_secs(1_704_067_200),
+            new_time: UNIX_EPOCH + Duration::from_millis(1_704_067_201_500),
+            residual: 12,
+        };
+
+        assert_eq!(
+            "Clock stepped from 2024-01-01 00:00:00.000000 to \
+             2024-01-01 00:00:01.500000 UTC, residual 12 us",
+            adjustment.to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_time_rejected() {
+        let err = update_system_time(1_704_067_200, 2_000_000_000)
+            .err()
+            .unwrap();
+
+        assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+    }
+}
@@ -1,36 +1,99 @@
-use chrono::{Local, TimeZone, Timelike, Utc};
-use log::debug;
+use std::fmt::{Display, Formatter};
 
-#[cfg(unix)]
-use unix::sync_time;
-#[cfg(windows)]
-use windows::sync_time;
+use chrono::{Local, TimeZone, Utc};
 
-#[cfg(unix)]
+use crate::NtpResult;
+
+#[cfg(not(feature = "measure-only"))]
+pub use clockset::{
+    adjust_frequency, adjust_system_time, current_frequency, lock_clock,
+    lock_clock_at, set_lock_path, update_system_time, write_rtc, AdjustMethod,
+    ClockAdjustment, ClockLock, DEFAULT_LOCK_PATH,
+};
+
+#[cfg(not(feature = "measure-only"))]
+mod clockset;
+#[cfg(all(unix, not(feature = "measure-only")))]
 mod unix;
-#[cfg(windows)]
+#[cfg(all(windows, not(feature = "measure-only")))]
 mod windows;
 
-/// Set up system time based on the given parameters
-/// Args:
-/// * sec - Seconds since UNIX epoch start
-/// * nsec - Fraction of seconds from an NTP response
-pub fn update_system_time(sec: u32, nsec: u32) {
-    let time = Utc.timestamp(sec as i64, nsec);
-    let local_time = time.with_timezone(&Local);
-    debug!(
-        "UTC time: {:02}:{:02}:{:02}",
-        time.hour(),
-        time.minute(),
-        time.second()
-    );
-    debug!(
-        "{} time: {:02}:{:02}:{:02}",
-        local_time.offset(),
-        local_time.hour(),
-        local_time.minute(),
-        local_time.second()
-    );
-
-    sync_time(local_time);
+/// Formatter of a [`NtpResult`] showing the server time in UTC and in
+/// another time zone, along with the offset and the roundtrip
+pub struct ResultDisplay<'a, Tz> {
+    result: &'a NtpResult,
+    zone: Tz,
+}
+
+/// Returns a formatter of `result` in UTC and in the local time zone
+pub fn display_result(result: &NtpResult) -> ResultDisplay<'_, Local> {
+    display_result_in(result, Local)
+}
+
+/// Returns a formatter of `result` in UTC and in the given time zone,
+/// e.g. a `chrono_tz::Tz`
+pub fn display_result_in<Tz: TimeZone>(
+    result: &NtpResult,
+    zone: Tz,
+) -> ResultDisplay<'_, Tz> {
+    ResultDisplay { result, zone }
+}
+
+impl<Tz: TimeZone> Display for ResultDisplay<'_, Tz>
+where
+    Tz::Offset: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+        let time = Utc
+            .timestamp_opt(i64::from(self.result.sec()), self.result.nsec())
+            .single()
+            .ok_or(std::fmt::Error)?;
+        let local = time.with_timezone(&self.zone);
+
+        write!(
+            f,
+            "{} UTC ({} {}), offset {:+.6} s, roundtrip {:.3} ms",
+            time.format(FORMAT),
+            local.format(FORMAT),
+            local.format("%:z"),
+            self.result.offset() as f64 / 1e6,
+            self.result.roundtrip() as f64 / 1e3
+        )
+    }
+}
+
+#[cfg(test)]
+mod utils_tests {
+    use crate::utils::display_result_in;
+    use crate::NtpResult;
+    use chrono::FixedOffset;
+    use std::fmt::Write;
+
+    #[test]
+    fn test_display_result() {
+        let result =
+            NtpResult::new(1_704_067_201, 500_000_000, 125_000, -1_500);
+        let zone = FixedOffset::east_opt(3600).unwrap();
+
+        assert_eq!(
+            "2024-01-01 00:00:01.500 UTC (2024-01-01 01:00:01.500 +01:00), \
+             offset -0.001500 s, roundtrip 125.000 ms",
+            display_result_in(&result, zone).to_string()
+        );
+    }
+
+    #[test]
+    fn test_display_invalid_result() {
+        let result = NtpResult {
+            nsec: 2_000_000_000,
+            ..NtpResult::default()
+        };
+        let mut out = String::new();
+
+        assert!(
+            write!(out, "{}", display_result_in(&result, chrono::Utc)).is_err()
+        );
+    }
 }
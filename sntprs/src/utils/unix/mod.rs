@@ -1,10 +1,54 @@
+use std::io;
+use std::path::Path;
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+)))]
 use std::process::Command;
 
-use chrono::{DateTime, Datelike, Local, Timelike};
+use chrono::{DateTime, Local, Utc};
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+)))]
+use chrono::{Datelike, Timelike};
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+const USEC_IN_SEC: i64 = 1_000_000;
 
 /// Synchronize system time with the platform specific
 /// command line tool
-pub(super) fn sync_time(time: DateTime<Local>) {
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+)))]
+pub(super) fn sync_time(time: DateTime<Local>) -> io::Result<()> {
     let time_str = format!(
         "{}/{}/{} {:02}:{:02}:{:02}",
         time.month(),
@@ -14,15 +58,269 @@ pub(super) fn sync_time(time: DateTime<Local>) {
         time.minute(),
         time.second()
     );
-    let sync_cmd_status = Command::new("date")
-        .args(&["-s", time_str.as_str()])
-        .status()
-        .expect("Unable to execute date command");
+    let status = Command::new("date")
+        .args(["-s", time_str.as_str()])
+        .status()?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "Date command exit status {:?}",
+            status.code()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Mobile platforms do not let applications set the system time,
+/// which is left untouched
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub(super) fn sync_time(_time: DateTime<Local>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Setting the system time is not supported on this platform",
+    ))
+}
+
+/// Set the system time with `clock_settime`, as the `date` command of
+/// these systems does not accept the GNU syntax
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+pub(super) fn sync_time(time: DateTime<Local>) -> io::Result<()> {
+    let ts = libc::timespec {
+        tv_sec: time.timestamp() as libc::time_t,
+        tv_nsec: time.timestamp_subsec_nanos() as libc::c_long,
+    };
+
+    // SAFETY: clock_settime only reads the given timespec
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &ts) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Set the hardware clock time with the `RTC_SET_TIME` ioctl
+#[cfg(target_os = "linux")]
+pub(super) fn set_rtc(device: &Path, time: DateTime<Utc>) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    /// `struct rtc_time`, laid out as `struct tm`
+    #[repr(C)]
+    struct RtcTime {
+        sec: libc::c_int,
+        min: libc::c_int,
+        hour: libc::c_int,
+        mday: libc::c_int,
+        mon: libc::c_int,
+        year: libc::c_int,
+        wday: libc::c_int,
+        yday: libc::c_int,
+        isdst: libc::c_int,
+    }
+
+    /// `_IOW('p', 0x0a, struct rtc_time)`
+    const RTC_SET_TIME: libc::Ioctl = libc::_IOW::<RtcTime>(b'p' as u32, 0x0a);
+
+    let rtc = RtcTime {
+        sec: time.second() as libc::c_int,
+        min: time.minute() as libc::c_int,
+        hour: time.hour() as libc::c_int,
+        mday: time.day() as libc::c_int,
+        mon: time.month0() as libc::c_int,
+        year: time.year() - 1900,
+        wday: time.weekday().num_days_from_sunday() as libc::c_int,
+        yday: time.ordinal0() as libc::c_int,
+        isdst: 0,
+    };
+    let device = OpenOptions::new().write(true).open(device)?;
+
+    // SAFETY: RTC_SET_TIME reads a rtc_time structure
+    let res = unsafe {
+        libc::ioctl(device.as_raw_fd(), RTC_SET_TIME, &rtc as *const RtcTime)
+    };
+
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Hardware clock access is only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub(super) fn set_rtc(_device: &Path, _time: DateTime<Utc>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Hardware clock access is not supported on this platform",
+    ))
+}
+
+/// Set the kernel clock frequency offset with `adjtimex`
+#[cfg(target_os = "linux")]
+pub(super) fn set_frequency(ppm: f64) -> io::Result<()> {
+    // The kernel expects the frequency offset in PPM with 16-bit fraction
+    const PPM_SCALE: f64 = 65_536.0;
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+
+    tx.modes = libc::ADJ_FREQUENCY;
+    tx.freq = (ppm * PPM_SCALE).round() as libc::c_long;
+
+    if unsafe { libc::adjtimex(&mut tx) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Returns the kernel clock frequency offset in PPM, read with
+/// `adjtimex` without changing anything
+#[cfg(target_os = "linux")]
+pub(super) fn get_frequency() -> io::Result<f64> {
+    const PPM_SCALE: f64 = 65_536.0;
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
 
-    if !sync_cmd_status.success() {
-        eprintln!(
-            "Date command exit status {}",
-            sync_cmd_status.code().unwrap()
-        );
+    tx.modes = 0;
+
+    if unsafe { libc::adjtimex(&mut tx) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(tx.freq as f64 / PPM_SCALE)
+}
+
+/// Kernel frequency adjustment is only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub(super) fn get_frequency() -> io::Result<f64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock frequency adjustment is not supported on this platform",
+    ))
+}
+
+/// Kernel frequency adjustment is only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub(super) fn set_frequency(_ppm: f64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock frequency adjustment is not supported on this platform",
+    ))
+}
+
+/// Slew the system time by `offset_us` microseconds with `adjtime`
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+pub(super) fn slew_time(offset_us: i64) -> io::Result<()> {
+    let mut delta = libc::timeval {
+        tv_sec: offset_us.div_euclid(USEC_IN_SEC) as libc::time_t,
+        tv_usec: offset_us.rem_euclid(USEC_IN_SEC) as libc::suseconds_t,
+    };
+
+    // Mutable for Solaris, where adjtime takes a mutable pointer
+    let delta = std::ptr::addr_of_mut!(delta);
+
+    // SAFETY: adjtime only reads the given timeval
+    if unsafe { libc::adjtime(delta, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
     }
+
+    Ok(())
+}
+
+/// Step the system time by `offset_us` microseconds at once with the
+/// `ADJ_SETOFFSET` mode of `adjtimex`
+#[cfg(target_os = "linux")]
+pub(super) fn step_time(offset_us: i64) -> io::Result<()> {
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+
+    tx.modes = libc::ADJ_SETOFFSET;
+    tx.time.tv_sec = offset_us.div_euclid(USEC_IN_SEC) as libc::time_t;
+    tx.time.tv_usec = offset_us.rem_euclid(USEC_IN_SEC) as libc::suseconds_t;
+
+    if unsafe { libc::adjtimex(&mut tx) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Step the system time by `offset_us` microseconds, reading it with
+/// `clock_gettime` right before setting it with `clock_settime`
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+pub(super) fn step_time(offset_us: i64) -> io::Result<()> {
+    const NSEC_IN_SEC: i64 = 1_000_000_000;
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+
+    // SAFETY: clock_gettime only writes the given timespec
+    if unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut ts) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let nsec = ts.tv_nsec as i64 + offset_us.rem_euclid(USEC_IN_SEC) * 1_000;
+
+    ts.tv_sec += (offset_us.div_euclid(USEC_IN_SEC) + nsec / NSEC_IN_SEC)
+        as libc::time_t;
+    ts.tv_nsec = (nsec % NSEC_IN_SEC) as libc::c_long;
+
+    // SAFETY: clock_settime only reads the given timespec
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &ts) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Relative clock adjustments are not supported on the other platforms
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+)))]
+pub(super) fn slew_time(_offset_us: i64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock adjustment is not supported on this platform",
+    ))
+}
+
+/// Relative clock adjustments are not supported on the other platforms
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+)))]
+pub(super) fn step_time(_offset_us: i64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock adjustment is not supported on this platform",
+    ))
 }
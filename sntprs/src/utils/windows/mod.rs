@@ -1,12 +1,14 @@
+use std::io;
+use std::path::Path;
 use std::process::Command;
 
-use chrono::{DateTime, Datelike, Local, Timelike};
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
 
 /// Synchronize system time with the platform specific
 /// command line tool
-pub(super) fn sync_time(time: DateTime<Local>) {
-    let cmd = Command::new("cmd")
-        .args(&[
+pub(super) fn sync_time(time: DateTime<Local>) -> io::Result<()> {
+    let status = Command::new("cmd")
+        .args([
             "/C",
             format!(
                 "powershell Set-Date -Date \"{}/{}/{} {}:{}:{}\"",
@@ -19,16 +21,71 @@ pub(super) fn sync_time(time: DateTime<Local>) {
             )
             .as_str(),
         ])
-        .spawn();
-
-    match cmd {
-        Ok(mut child) => {
-            child
-                .wait()
-                .expect("Time synchronization finished incorrectly");
-        }
-        Err(e) => {
-            eprintln!("Error occurred: {}", e.to_string());
-        }
-    };
+        .status()?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "Set-Date exit status {:?}",
+            status.code()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Step the system time by `offset_us` microseconds with the platform
+/// specific command line tool
+pub(super) fn step_time(offset_us: i64) -> io::Result<()> {
+    // TimeSpan ticks are 100 ns long
+    let status = Command::new("powershell")
+        .args([
+            "-Command",
+            format!(
+                "Set-Date -Adjust ([TimeSpan]::FromTicks({}))",
+                offset_us * 10
+            )
+            .as_str(),
+        ])
+        .status()?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "Set-Date exit status {:?}",
+            status.code()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Slewing the clock is not supported, offsets are stepped instead
+pub(super) fn slew_time(_offset_us: i64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock slewing is not supported on this platform",
+    ))
+}
+
+/// Kernel frequency adjustment is only available on Linux
+pub(super) fn get_frequency() -> io::Result<f64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock frequency adjustment is not supported on this platform",
+    ))
+}
+
+/// Kernel frequency adjustment is only available on Linux
+pub(super) fn set_frequency(_ppm: f64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Clock frequency adjustment is not supported on this platform",
+    ))
+}
+
+/// Hardware clock access is only available on Linux
+pub(super) fn set_rtc(_device: &Path, _time: DateTime<Utc>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Hardware clock access is not supported on this platform",
+    ))
 }
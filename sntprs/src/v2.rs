@@ -0,0 +1,136 @@
+//! Strongly typed results
+//!
+//! [`NtpResult`] carries the same information as the legacy
+//! [`crate::NtpResult`], with the roundtrip and the offset expressed as
+//! [`Duration`] and [`TimeDelta`] instead of bare microsecond counts.
+//!
+//! Converted from a [`DetailedResult`], the roundtrip and the offset are
+//! computed again from the exchange timestamps at nanosecond resolution.
+//! Converted from a legacy result, they keep its microsecond resolution
+
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::TimeDelta;
+
+use crate::ntptimestamp::{fixed_to_nanos, half_sum};
+use crate::{DetailedResult, Timestamps};
+
+/// SNTP request result representation
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NtpResult {
+    /// NTP server seconds value
+    pub sec: u32,
+    /// NTP server nanoseconds value
+    pub nsec: u32,
+    /// Request roundtrip time
+    pub roundtrip: Duration,
+    /// Offset of the current system time with one received from a NTP server,
+    /// positive when the system clock is behind
+    pub offset: TimeDelta,
+    /// Stratum of the NTP server
+    pub stratum: u8,
+    /// Root delay reported by the NTP server, in NTP short format (16.16)
+    pub root_delay: u32,
+    /// Root dispersion reported by the NTP server, in NTP short format (16.16)
+    pub root_dispersion: u32,
+    /// Precision of the NTP server clock, as a power of two in seconds
+    pub precision: i8,
+}
+
+impl NtpResult {
+    /// Returns time reported by an NTP server
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(u64::from(self.sec), self.nsec)
+    }
+
+    /// Returns request's roundtrip time (client -> server -> client)
+    pub fn roundtrip(&self) -> Duration {
+        self.roundtrip
+    }
+
+    /// Returns system clock offset
+    pub fn offset(&self) -> TimeDelta {
+        self.offset
+    }
+
+    /// Returns stratum reported by an NTP server
+    pub fn stratum(&self) -> u8 {
+        self.stratum
+    }
+}
+
+impl From<crate::NtpResult> for NtpResult {
+    fn from(val: crate::NtpResult) -> Self {
+        NtpResult {
+            sec: val.sec,
+            nsec: val.nsec,
+            roundtrip: Duration::from_micros(val.roundtrip),
+            offset: TimeDelta::microseconds(val.offset),
+            stratum: val.stratum,
+            root_delay: val.root_delay,
+            root_dispersion: val.root_dispersion,
+            precision: val.precision,
+        }
+    }
+}
+
+impl From<DetailedResult> for NtpResult {
+    fn from(val: DetailedResult) -> Self {
+        let Timestamps { t1, t2, t3, t4 } = val.timestamps();
+        let delta = fixed_to_nanos(t4.diff(t1).saturating_sub(t3.diff(t2)));
+        let theta = fixed_to_nanos(half_sum(t2.diff(t1), t3.diff(t4)));
+
+        NtpResult {
+            roundtrip: Duration::from_nanos(delta.unsigned_abs()),
+            offset: TimeDelta::nanoseconds(theta),
+            ..NtpResult::from(val.result)
+        }
+    }
+}
+
+impl Debug for NtpResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NtpResult")
+            .field("sec", &self.sec)
+            .field("nsec", &self.nsec)
+            .field("roundtrip", &self.roundtrip)
+            .field("offset", &self.offset)
+            .field("stratum", &self.stratum)
+            .field("root_delay", &self.root_delay)
+            .field("root_dispersion", &self.root_dispersion)
+            .field("precision", &self.precision)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod v2_tests {
+    use crate::v2::NtpResult;
+    use crate::{DetailedResult, NtpTimestamp, SourceCheck, Timestamps};
+    use chrono::TimeDelta;
+    use std::time::Duration;
+
+    #[test]
+    fn test_nanosecond_resolution() {
+        let t1 = 10 << 32;
+        let t2 = t1 + 0x1_0000_0005;
+        let result = NtpResult::from(DetailedResult {
+            result: crate::NtpResult::new(10, 0, 0, 1_000_000),
+            server: "192.0.2.1:123".parse().unwrap(),
+            timings: Default::default(),
+            timestamps: Timestamps {
+                t1: NtpTimestamp::from_bits(t1),
+                t2: NtpTimestamp::from_bits(t2),
+                t3: NtpTimestamp::from_bits(t2),
+                t4: NtpTimestamp::from_bits(t1 + 0x10),
+            },
+            source_check: SourceCheck::Strict,
+        });
+
+        assert_eq!(Duration::from_nanos(4), result.roundtrip());
+        assert_eq!(TimeDelta::nanoseconds(999_999_999), result.offset());
+        assert_eq!(10, result.sec);
+    }
+}
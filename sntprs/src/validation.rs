@@ -0,0 +1,418 @@
+use crate::proto::{LeapIndicator, Mode};
+use crate::NtpPacket;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+
+/// A single failed sanity check on a server response,
+/// with the values observed in the packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Violation {
+    /// The origin timestamp does not echo the request transmit timestamp
+    OriginMismatch { expected: u64, received: u64 },
+    /// The mode is not server (4) for replies or broadcast (5)
+    /// for broadcasts
+    Mode(u8),
+    /// The leap indicator is out of range
+    LeapIndicator(u8),
+    /// The response version differs from the request one
+    Version { request: u8, response: u8 },
+    /// The stratum is above 15 (unsynchronized)
+    Stratum(u8),
+    /// The stratum is 0, a kiss-o'-death with the given kiss code
+    KissOfDeath(u32),
+    /// The transmit timestamp is zero
+    TransmitTimestamp,
+    /// The root distance, in NTP short format, is 16 s or more
+    RootDistance(u32),
+}
+
+/// Category of a rejected response, for automated handling
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// The response does not answer the request, e.g. spoofed, replayed
+    /// or from another address
+    Unsolicited,
+    /// The datagram is not a well formed server response
+    Malformed,
+    /// The server is not synchronized to a reference
+    Unsynchronized,
+    /// The server asks the client to back off or stop (kiss-o'-death)
+    KissOfDeath,
+    /// The server is too far from its reference to be useful
+    Inaccurate,
+}
+
+impl Reason {
+    /// Returns a stable identifier of the reason
+    pub fn code(self) -> &'static str {
+        match self {
+            Reason::Unsolicited => "unsolicited",
+            Reason::Malformed => "malformed",
+            Reason::Unsynchronized => "unsynchronized",
+            Reason::KissOfDeath => "kiss_of_death",
+            Reason::Inaccurate => "inaccurate",
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl Violation {
+    /// Returns the category of the failed check
+    pub fn reason(&self) -> Reason {
+        match self {
+            Violation::OriginMismatch { .. } => Reason::Unsolicited,
+            Violation::Mode(_)
+            | Violation::Version { .. }
+            | Violation::TransmitTimestamp => Reason::Malformed,
+            Violation::LeapIndicator(li) if *li > 3 => Reason::Malformed,
+            Violation::LeapIndicator(_) => Reason::Unsynchronized,
+            Violation::Stratum(_) => Reason::Unsynchronized,
+            Violation::KissOfDeath(_) => Reason::KissOfDeath,
+            Violation::RootDistance(_) => Reason::Inaccurate,
+        }
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::OriginMismatch { expected, received } => write!(
+                f,
+                "Incorrect origin timestamp: expected {:#018x}, got {:#018x}",
+                expected, received
+            ),
+            Violation::Mode(mode) => {
+                write!(f, "Incorrect MODE value: {}", mode)
+            }
+            Violation::LeapIndicator(li) => {
+                write!(f, "Incorrect LI value: {}", li)
+            }
+            Violation::Version { request, response } => write!(
+                f,
+                "Incorrect response version: sent {}, got {}",
+                request, response
+            ),
+            Violation::Stratum(stratum) => {
+                write!(f, "Incorrect STRATUM headers: {}", stratum)
+            }
+            Violation::KissOfDeath(code) => {
+                let code = code.to_be_bytes();
+
+                write!(f, "Kiss-o'-death: {}", String::from_utf8_lossy(&code))
+            }
+            Violation::TransmitTimestamp => {
+                write!(f, "Incorrect transmit timestamp: 0")
+            }
+            Violation::RootDistance(distance) => write!(
+                f,
+                "Incorrect root distance: {:.3} s",
+                f64::from(*distance) / 65536.0
+            ),
+        }
+    }
+}
+
+/// Set of sanity checks run on server responses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ValidationProfile {
+    /// Basic header checks plus RFC 4330 sanity checks on the leap
+    /// indicator, stratum, transmit timestamp and root distance
+    #[default]
+    Strict,
+    /// Basic header checks only, tolerating version mismatches and
+    /// unsynchronized leap indicators as sent by some embedded and
+    /// legacy servers
+    Lenient,
+}
+
+/// Policy deciding whether a response may come from another address
+/// than the one the request was sent to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SourceCheck {
+    /// Same address and port
+    #[default]
+    Strict,
+    /// Same address, any port, for servers replying from another port
+    SameHost,
+    /// Any address, only relying on the origin timestamp
+    Permissive,
+}
+
+impl SourceCheck {
+    /// Returns whether a response from `source` is accepted for a
+    /// request sent to `server`
+    pub fn accepts(self, server: SocketAddr, source: SocketAddr) -> bool {
+        match self {
+            SourceCheck::Strict => source == server,
+            SourceCheck::SameHost => source.ip() == server.ip(),
+            SourceCheck::Permissive => true,
+        }
+    }
+}
+
+/// Outcome of all the sanity checks on a server response
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ValidationReport {
+    /// Failed checks, in the order they are performed
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    /// Returns whether every check passed
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Returns failed checks
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Returns the category of every failed check, without duplicates
+    pub fn reasons(&self) -> Vec<Reason> {
+        let mut reasons = Vec::new();
+
+        for violation in &self.violations {
+            if !reasons.contains(&violation.reason()) {
+                reasons.push(violation.reason());
+            }
+        }
+
+        reasons
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.violations.is_empty() {
+            return write!(f, "Valid response");
+        }
+
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ValidationReport {}
+
+/// Run every sanity check of `profile` on `packet` received in reply
+/// to `req`. Broadcast packets are rejected
+pub(crate) fn validate(
+    req: &NtpPacket,
+    packet: &NtpPacket,
+    profile: ValidationProfile,
+) -> ValidationReport {
+    let strict = profile == ValidationProfile::Strict;
+    let mut violations = Vec::new();
+
+    if req.tx_timestamp != packet.origin_timestamp {
+        violations.push(Violation::OriginMismatch {
+            expected: req.tx_timestamp,
+            received: packet.origin_timestamp,
+        });
+    }
+    let resp_version = packet.version();
+    let req_version = req.version();
+
+    if packet.mode() != Mode::Server {
+        violations.push(Violation::Mode(packet.mode() as u8));
+    }
+
+    if strict && req_version != resp_version {
+        violations.push(Violation::Version {
+            request: req_version,
+            response: resp_version,
+        });
+    }
+
+    check_header(packet, strict, &mut violations);
+
+    ValidationReport { violations }
+}
+
+/// Run every sanity check of `profile` on an unsolicited broadcast
+/// `packet`. Server replies are rejected
+pub(crate) fn validate_broadcast(
+    packet: &NtpPacket,
+    profile: ValidationProfile,
+) -> ValidationReport {
+    let strict = profile == ValidationProfile::Strict;
+    let mut violations = Vec::new();
+
+    if packet.mode() != Mode::Broadcast {
+        violations.push(Violation::Mode(packet.mode() as u8));
+    }
+
+    check_header(packet, strict, &mut violations);
+
+    ValidationReport { violations }
+}
+
+/// Checks shared by server replies and broadcasts
+fn check_header(
+    packet: &NtpPacket,
+    strict: bool,
+    violations: &mut Vec<Violation>,
+) {
+    const MAX_STRATUM: u8 = 15;
+    // 16 s in NTP short format
+    const MAX_DISTANCE: u32 = 16 << 16;
+    let leap = packet.leap();
+
+    if strict && leap == LeapIndicator::Unsynchronized {
+        violations.push(Violation::LeapIndicator(leap as u8));
+    }
+
+    if packet.stratum == 0 {
+        violations.push(Violation::KissOfDeath(packet.ref_id));
+    } else if strict && packet.stratum > MAX_STRATUM {
+        violations.push(Violation::Stratum(packet.stratum));
+    }
+
+    if strict && packet.tx_timestamp == 0 {
+        violations.push(Violation::TransmitTimestamp);
+    }
+
+    let distance = packet.root_distance();
+
+    if strict && distance.to_bits() >= MAX_DISTANCE {
+        violations.push(Violation::RootDistance(distance.to_bits()));
+    }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use crate::validation::{
+        validate, validate_broadcast, Reason, SourceCheck, ValidationProfile,
+        Violation,
+    };
+    use crate::NtpPacket;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_validation_report() {
+        let req = NtpPacket::new();
+        let resp = NtpPacket {
+            li_vn_mode: 0b00_011_011,
+            stratum: 0,
+            origin_timestamp: req.tx_timestamp ^ 1,
+            ..NtpPacket::new()
+        };
+
+        let report = validate(&req, &resp, ValidationProfile::Strict);
+
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.violations(),
+            &[
+                Violation::OriginMismatch {
+                    expected: req.tx_timestamp,
+                    received: req.tx_timestamp ^ 1,
+                },
+                Violation::Mode(3),
+                Violation::Version {
+                    request: 4,
+                    response: 3,
+                },
+                Violation::KissOfDeath(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reason_codes() {
+        let req = NtpPacket::new();
+        let resp = NtpPacket {
+            li_vn_mode: 0b00_011_011,
+            stratum: 0,
+            origin_timestamp: req.tx_timestamp ^ 1,
+            ..NtpPacket::new()
+        };
+        let report = validate(&req, &resp, ValidationProfile::Strict);
+
+        assert_eq!(
+            report.reasons(),
+            vec![Reason::Unsolicited, Reason::Malformed, Reason::KissOfDeath]
+        );
+        assert_eq!("kiss_of_death", Reason::KissOfDeath.code());
+        assert_eq!(
+            Reason::Unsynchronized,
+            Violation::LeapIndicator(3).reason()
+        );
+        assert_eq!(Reason::Malformed, Violation::LeapIndicator(4).reason());
+        assert_eq!(Reason::Inaccurate, Violation::RootDistance(0).reason());
+    }
+
+    #[test]
+    fn test_lenient_profile() {
+        let req = NtpPacket::new();
+        let resp = NtpPacket {
+            li_vn_mode: 0b11_011_100,
+            stratum: 16,
+            origin_timestamp: req.tx_timestamp,
+            ..NtpPacket::new()
+        };
+
+        let strict = validate(&req, &resp, ValidationProfile::Strict);
+        let lenient = validate(&req, &resp, ValidationProfile::Lenient);
+
+        assert_eq!(
+            strict.violations(),
+            &[
+                Violation::Version {
+                    request: 4,
+                    response: 3,
+                },
+                Violation::LeapIndicator(3),
+                Violation::Stratum(16),
+            ]
+        );
+        assert!(lenient.is_valid());
+    }
+
+    #[test]
+    fn test_broadcast_validation() {
+        let req = NtpPacket::new();
+        let resp = NtpPacket {
+            li_vn_mode: 0b00_100_101,
+            stratum: 2,
+            origin_timestamp: req.tx_timestamp,
+            ..NtpPacket::new()
+        };
+
+        assert_eq!(
+            validate(&req, &resp, ValidationProfile::Lenient).violations(),
+            &[Violation::Mode(5)]
+        );
+        assert!(validate_broadcast(&resp, ValidationProfile::Strict).is_valid());
+    }
+
+    #[test]
+    fn test_source_check() {
+        let server: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let other_port: SocketAddr = "192.0.2.1:1123".parse().unwrap();
+        let other_host: SocketAddr = "192.0.2.2:123".parse().unwrap();
+
+        assert!(SourceCheck::Strict.accepts(server, server));
+        assert!(!SourceCheck::Strict.accepts(server, other_port));
+        assert!(SourceCheck::SameHost.accepts(server, other_port));
+        assert!(!SourceCheck::SameHost.accepts(server, other_host));
+        assert!(SourceCheck::Permissive.accepts(server, other_host));
+    }
+}
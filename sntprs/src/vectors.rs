@@ -0,0 +1,229 @@
+//! Conformance test vectors
+//!
+//! Known NTP responses along with the offset and roundtrip they yield,
+//! or the error they are rejected with, all answering a request sent
+//! at 2024-01-01 00:00:00 UTC and received 140.625 ms later. The
+//! timestamps use binary fractions of a second so the expected values
+//! are exact. [`verify_vectors`] runs them through the response
+//! processing of [`NtpClient`], giving ports and refactors of the
+//! protocol handling a correctness baseline
+//!
+//! ```rust
+//! sntprs::vectors::verify_vectors().unwrap();
+//! ```
+
+use std::io;
+
+use crate::proto::KISS_RATE;
+use crate::{Error, NtpClient, NtpPacket, Violation};
+
+/// Transmit time of the request, T1
+const T1: u64 = 0xe93c_7f00_0000_0000;
+/// Receive time of the response, T4
+const T4: u64 = 0xe93c_7f00_2400_0000;
+
+/// Stratum 1 server 1 s ahead, 125 ms roundtrip
+const SERVER_AHEAD: [u8; 48] = [
+    0x24, 0x01, 0x06, 0xec, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+    0x47, 0x50, 0x53, 0x00, 0xe9, 0x3c, 0x7e, 0xf1, 0x10, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01,
+    0x10, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01, 0x14, 0x00, 0x00, 0x00,
+];
+
+/// Stratum 1 server 500 ms behind, 125 ms roundtrip
+const SERVER_BEHIND: [u8; 48] = [
+    0x24, 0x01, 0x06, 0xec, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+    0x47, 0x50, 0x53, 0x00, 0xe9, 0x3c, 0x7e, 0xef, 0x90, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7e, 0xff,
+    0x90, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7e, 0xff, 0x94, 0x00, 0x00, 0x00,
+];
+
+/// Same as `SERVER_AHEAD`, followed by a key ID and a 16 bytes MD5 digest
+const SERVER_AHEAD_MAC: [u8; 68] = [
+    0x24, 0x01, 0x06, 0xec, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+    0x47, 0x50, 0x53, 0x00, 0xe9, 0x3c, 0x7e, 0xf1, 0x10, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01,
+    0x10, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01, 0x14, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x01, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xab, 0xac, 0xad, 0xae, 0xaf,
+];
+
+/// RATE kiss-o'-death
+const KISS_OF_DEATH: [u8; 48] = [
+    0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x52, 0x41, 0x54, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Server with the alarm leap indicator and stratum 16
+const UNSYNCHRONIZED: [u8; 48] = [
+    0xe4, 0x10, 0x06, 0xec, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7e, 0xf1, 0x10, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01,
+    0x10, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01, 0x14, 0x00, 0x00, 0x00,
+];
+
+/// Broadcast packet where a server reply is expected
+const BROADCAST: [u8; 48] = [
+    0x25, 0x01, 0x06, 0xec, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+    0x47, 0x50, 0x53, 0x00, 0xe9, 0x3c, 0x7e, 0xf1, 0x10, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01,
+    0x10, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01, 0x14, 0x00, 0x00, 0x00,
+];
+
+/// Reply to another request
+const ORIGIN_MISMATCH: [u8; 48] = [
+    0x24, 0x01, 0x06, 0xec, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+    0x47, 0x50, 0x53, 0x00, 0xe9, 0x3c, 0x7e, 0xf1, 0x10, 0x00, 0x00, 0x00,
+    0xe9, 0x3c, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x01, 0xe9, 0x3c, 0x7f, 0x01,
+    0x10, 0x00, 0x00, 0x00, 0xe9, 0x3c, 0x7f, 0x01, 0x14, 0x00, 0x00, 0x00,
+];
+
+/// Outcome expected from a [`TestVector`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expected {
+    /// Offset and roundtrip in microseconds
+    Result { offset: i64, roundtrip: u64 },
+    /// Error the response is rejected with
+    Error(Error),
+}
+
+/// Response to a request sent at `t1` and received at `t4`, in NTP
+/// timestamp format, along with the expected outcome
+#[derive(Clone, Copy, Debug)]
+pub struct TestVector {
+    /// Name of the vector
+    pub name: &'static str,
+    /// Request transmit time
+    pub t1: u64,
+    /// Response receive time
+    pub t4: u64,
+    /// Response datagram
+    pub response: &'static [u8],
+    /// Expected outcome
+    pub expected: Expected,
+}
+
+/// All the vectors
+pub const VECTORS: [TestVector; 8] = [
+    TestVector {
+        name: "server_ahead",
+        t1: T1,
+        t4: T4,
+        response: &SERVER_AHEAD,
+        expected: Expected::Result {
+            offset: 1_000_000,
+            roundtrip: 125_000,
+        },
+    },
+    TestVector {
+        name: "server_behind",
+        t1: T1,
+        t4: T4,
+        response: &SERVER_BEHIND,
+        expected: Expected::Result {
+            offset: -500_000,
+            roundtrip: 125_000,
+        },
+    },
+    TestVector {
+        name: "server_ahead_mac",
+        t1: T1,
+        t4: T4,
+        response: &SERVER_AHEAD_MAC,
+        expected: Expected::Result {
+            offset: 1_000_000,
+            roundtrip: 125_000,
+        },
+    },
+    TestVector {
+        name: "truncated",
+        t1: T1,
+        t4: T4,
+        response: SERVER_AHEAD.split_at(40).0,
+        expected: Expected::Error(Error::PacketSize(40)),
+    },
+    TestVector {
+        name: "kiss_of_death",
+        t1: T1,
+        t4: T4,
+        response: &KISS_OF_DEATH,
+        expected: Expected::Error(Error::Validation(Violation::KissOfDeath(
+            KISS_RATE,
+        ))),
+    },
+    TestVector {
+        name: "unsynchronized",
+        t1: T1,
+        t4: T4,
+        response: &UNSYNCHRONIZED,
+        expected: Expected::Error(Error::Validation(Violation::LeapIndicator(
+            3,
+        ))),
+    },
+    TestVector {
+        name: "broadcast",
+        t1: T1,
+        t4: T4,
+        response: &BROADCAST,
+        expected: Expected::Error(Error::Validation(Violation::Mode(5))),
+    },
+    TestVector {
+        name: "origin_mismatch",
+        t1: T1,
+        t4: T4,
+        response: &ORIGIN_MISMATCH,
+        expected: Expected::Error(Error::Validation(
+            Violation::OriginMismatch {
+                expected: T1,
+                received: T1 + 1,
+            },
+        )),
+    },
+];
+
+/// Returns the outcome of processing `vector` with the default client
+pub fn run_vector(vector: &TestVector) -> io::Result<Expected> {
+    let mut req = NtpPacket::new();
+
+    req.tx_timestamp = vector.t1;
+
+    match NtpClient::new().process(&req, vector.t1, vector.response, vector.t4)
+    {
+        Ok((result, _)) => Ok(Expected::Result {
+            offset: result.offset(),
+            roundtrip: result.roundtrip(),
+        }),
+        Err(err) => Error::from_io(&err).map(Expected::Error).ok_or(err),
+    }
+}
+
+/// Run every vector, failing on the first unexpected outcome
+pub fn verify_vectors() -> io::Result<()> {
+    for vector in &VECTORS {
+        let outcome = run_vector(vector)?;
+
+        if outcome != vector.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Vector {}: expected {:?}, got {:?}",
+                    vector.name, vector.expected, outcome
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod vectors_tests {
+    use crate::vectors::verify_vectors;
+
+    #[test]
+    fn test_vectors() {
+        verify_vectors().unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Webhook notifications
+//!
+//! A [`Webhook`] POSTs every [`Alert`] raised by a [`Monitor`] as a JSON
+//! document to a configured URL, to hook the service into incident
+//! tooling. Only plain `http://` URLs are supported, HTTPS endpoints
+//! need a local relay
+//!
+//! ```rust,no_run
+//! use sntprs::monitor::Monitor;
+//! use sntprs::webhook::Webhook;
+//!
+//! let mut monitor = Monitor::new().offset_threshold(100_000);
+//!
+//! Webhook::new("http://localhost:8080/alerts").unwrap().register(&mut monitor);
+//! ```
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::debug;
+
+use crate::monitor::{Alert, Monitor};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Endpoint notified of the alerts
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Webhook {
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl Webhook {
+    /// Create new webhook posting to `url`, as `http://host[:port]/path`
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported webhook URL: {}", url),
+            )
+        };
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Webhook {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Give up connecting, sending or waiting for the answer after
+    /// `timeout`. Defaults to 5 s
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Post every alert raised by `monitor` from a background thread,
+    /// logging failures
+    pub fn register(self, monitor: &mut Monitor) {
+        monitor.on_alert(move |alert| {
+            let webhook = self.clone();
+            let alert = alert.clone();
+
+            thread::spawn(move || {
+                if let Err(err) = webhook.post(&alert) {
+                    debug!("Webhook {} failed: {}", webhook.host, err);
+                }
+            });
+        });
+    }
+
+    /// Post `alert` and wait for a successful answer
+    pub fn post(&self, alert: &Alert) -> io::Result<()> {
+        let body = payload(alert, SystemTime::now());
+        let addr = (self.host.trim_matches(['[', ']']), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(io::ErrorKind::AddrNotAvailable)?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        )?;
+
+        let mut status = [0u8; 12];
+
+        stream.read_exact(&mut status)?;
+
+        // "HTTP/1.1 2xx"
+        if status[9] != b'2' {
+            return Err(io::Error::other(format!(
+                "Webhook answered {}",
+                String::from_utf8_lossy(&status[9..])
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the JSON document describing `alert`
+fn payload(alert: &Alert, time: SystemTime) -> String {
+    let fields = match alert {
+        Alert::OffsetExceeded { offset, threshold } => format!(
+            "\"event\":\"offset_exceeded\",\"offset_us\":{},\
+             \"threshold_us\":{}",
+            offset, threshold
+        ),
+        Alert::StratumDegraded { previous, current } => format!(
+            "\"event\":\"stratum_degraded\",\"previous\":{},\"current\":{}",
+            previous, current
+        ),
+        Alert::PollsFailed { count } => {
+            format!("\"event\":\"polls_failed\",\"count\":{}", count)
+        }
+        Alert::Degraded => "\"event\":\"sync_lost\"".to_string(),
+        Alert::Recovered => "\"event\":\"sync_recovered\"".to_string(),
+        Alert::ClockStepped { step } => {
+            format!("\"event\":\"clock_stepped\",\"step_us\":{}", step)
+        }
+        Alert::Resumed { slept } => {
+            format!("\"event\":\"resumed\",\"slept_ms\":{}", slept.as_millis())
+        }
+    };
+    let time = DateTime::<Utc>::from(time)
+        .to_rfc3339_opts(SecondsFormat::Millis, true);
+
+    format!("{{{},\"time\":\"{}\"}}", fields, time)
+}
+
+#[cfg(test)]
+mod webhook_tests {
+    use crate::monitor::Alert;
+    use crate::webhook::{payload, Webhook};
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_webhook_payload() {
+        let webhook = Webhook::new("http://[::1]:8080/hooks/ntp").unwrap();
+
+        assert_eq!(("[::1]", 8080), (webhook.host.as_str(), webhook.port));
+        assert_eq!("/hooks/ntp", webhook.path);
+        assert_eq!(80, Webhook::new("http://example.com").unwrap().port);
+        assert!(Webhook::new("https://example.com/").is_err());
+        assert_eq!(
+            "{\"event\":\"offset_exceeded\",\"offset_us\":-150,\
+             \"threshold_us\":100,\"time\":\"1970-01-01T00:00:00.000Z\"}",
+            payload(
+                &Alert::OffsetExceeded {
+                    offset: -150,
+                    threshold: 100
+                },
+                UNIX_EPOCH
+            )
+        );
+    }
+}